@@ -1,5 +1,16 @@
+use std::fmt;
+use std::rc::Rc;
+
 use crate::token::Token;
 
+pub mod fold;
+pub mod json;
+pub mod printer;
+pub mod visit;
+
+pub use fold::Folder;
+pub use visit::Visitor;
+
 pub type Program = Vec<Statement>;
 
 #[derive(Clone, PartialEq)]
@@ -8,6 +19,12 @@ pub enum Statement {
     ReturnStatement(ReturnStatement),
     ExpressionStatement(ExpressionStatement),
     BlockStatement(BlockStatement),
+    WhileStatement(WhileStatement),
+    BreakStatement(BreakStatement),
+    ContinueStatement(ContinueStatement),
+    ForStatement(ForStatement),
+    ThrowStatement(ThrowStatement),
+    InfixStatement(InfixStatement),
 }
 
 impl Statement {
@@ -17,6 +34,27 @@ impl Statement {
             Statement::ReturnStatement(x) => return x.print(),
             Statement::ExpressionStatement(x) => return x.print(),
             Statement::BlockStatement(x) => return x.print(),
+            Statement::WhileStatement(x) => return x.print(),
+            Statement::BreakStatement(x) => return x.print(),
+            Statement::ContinueStatement(x) => return x.print(),
+            Statement::ForStatement(x) => return x.print(),
+            Statement::ThrowStatement(x) => return x.print(),
+            Statement::InfixStatement(x) => return x.print(),
+        }
+    }
+
+    pub fn token(&self) -> &Token {
+        match self {
+            Statement::LetStatement(x) => &x.token,
+            Statement::ReturnStatement(x) => &x.token,
+            Statement::ExpressionStatement(x) => &x.token,
+            Statement::BlockStatement(x) => &x.token,
+            Statement::WhileStatement(x) => &x.token,
+            Statement::BreakStatement(x) => &x.token,
+            Statement::ContinueStatement(x) => &x.token,
+            Statement::ForStatement(x) => &x.token,
+            Statement::ThrowStatement(x) => &x.token,
+            Statement::InfixStatement(x) => &x.token,
         }
     }
 }
@@ -25,6 +63,8 @@ impl Statement {
 pub struct LetStatement {
     pub token: Token,
     pub name: Option<Identifier>,
+    /// The type after the name in `let x: int = 5;`.
+    pub annotation: Option<TypeAnnotation>,
     pub value: Option<Box<Expression>>,
 }
 
@@ -82,6 +122,8 @@ impl ExpressionStatement {
 pub struct BlockStatement {
     pub token: Token,
     pub statements: Vec<Statement>,
+    /// The closing `}`, so a printer knows where the block ends.
+    pub end: Token,
 }
 
 impl BlockStatement {
@@ -95,6 +137,119 @@ impl BlockStatement {
     }
 }
 
+#[derive(Clone, PartialEq)]
+pub struct WhileStatement {
+    pub token: Token,
+    pub cond: Option<Box<Expression>>,
+    pub body: Option<Box<Statement>>,
+}
+
+impl WhileStatement {
+    fn print(&self) -> String {
+        let con = match &self.cond {
+            Some(c) => c.print(),
+            None => "None".to_string(),
+        };
+        let bod = match &self.body {
+            Some(b) => b.print(),
+            None => "None".to_string(),
+        };
+        format!("WhileStmt: {:?} cond: {} body: {}", self.token, con, bod)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct BreakStatement {
+    pub token: Token,
+}
+
+impl BreakStatement {
+    fn print(&self) -> String {
+        format!("BreakStmt: {:?}", self.token)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ContinueStatement {
+    pub token: Token,
+}
+
+impl ContinueStatement {
+    fn print(&self) -> String {
+        format!("ContinueStmt: {:?}", self.token)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ForStatement {
+    pub token: Token,
+    pub ident: Option<Identifier>,
+    pub iterable: Option<Box<Expression>>,
+    pub body: Option<Box<Statement>>,
+}
+
+impl ForStatement {
+    fn print(&self) -> String {
+        let ident = match &self.ident {
+            Some(i) => i.print(),
+            None => "None".to_string(),
+        };
+        let iter = match &self.iterable {
+            Some(i) => i.print(),
+            None => "None".to_string(),
+        };
+        let bod = match &self.body {
+            Some(b) => b.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "ForStmt: {:?} ident: {} iterable: {} body: {}",
+            self.token, ident, iter, bod
+        )
+    }
+}
+
+/// `throw value;` raises `value` as a user error, or re-raises a caught
+/// error unchanged.
+#[derive(Clone, PartialEq)]
+pub struct ThrowStatement {
+    pub token: Token,
+    pub value: Option<Box<Expression>>,
+}
+
+impl ThrowStatement {
+    fn print(&self) -> String {
+        let val = match &self.value {
+            Some(v) => v.print(),
+            None => "None".to_string(),
+        };
+        format!("ThrowStmt: {:?} value: {}", self.token, val)
+    }
+}
+
+/// An operator declaration, `infixl 6 <+> = fn(a, b) { ... }`. The token is
+/// `infixl` or `infixr` and gives the associativity.
+#[derive(Clone, PartialEq)]
+pub struct InfixStatement {
+    pub token: Token,
+    pub precedence: i32,
+    pub operator: String,
+    pub value: Option<Box<Expression>>,
+}
+
+impl InfixStatement {
+    fn print(&self) -> String {
+        let val = match &self.value {
+            Some(v) => v.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "InfixStmt: {:?} precedence: {} operator: {} value: {}",
+            self.token, self.precedence, self.operator, val
+        )
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Expression {
     Identifier(Identifier),
@@ -106,6 +261,13 @@ pub enum Expression {
     FunctionLiteral(FunctionLiteral),
     CallExpression(CallExpression),
     StringLiteral(StringLiteral),
+    InterpolatedString(InterpolatedString),
+    ArrayLiteral(ArrayLiteral),
+    HashLiteral(HashLiteral),
+    IndexExpression(IndexExpression),
+    AssignExpression(AssignExpression),
+    TryExpression(TryExpression),
+    MacroLiteral(MacroLiteral),
 }
 
 impl Expression {
@@ -120,6 +282,34 @@ impl Expression {
             Expression::FunctionLiteral(x) => return x.print(),
             Expression::CallExpression(x) => return x.print(),
             Expression::StringLiteral(x) => return x.print(),
+            Expression::InterpolatedString(x) => return x.print(),
+            Expression::ArrayLiteral(x) => return x.print(),
+            Expression::HashLiteral(x) => return x.print(),
+            Expression::IndexExpression(x) => return x.print(),
+            Expression::AssignExpression(x) => return x.print(),
+            Expression::TryExpression(x) => return x.print(),
+            Expression::MacroLiteral(x) => return x.print(),
+        }
+    }
+
+    pub fn token(&self) -> &Token {
+        match self {
+            Expression::Identifier(x) => &x.token,
+            Expression::IntegerLiteral(x) => &x.token,
+            Expression::PrefixExpression(x) => &x.token,
+            Expression::InfixExpression(x) => &x.token,
+            Expression::Boolean(x) => &x.token,
+            Expression::IfExpression(x) => &x.token,
+            Expression::FunctionLiteral(x) => &x.token,
+            Expression::CallExpression(x) => &x.token,
+            Expression::StringLiteral(x) => &x.token,
+            Expression::InterpolatedString(x) => &x.token,
+            Expression::ArrayLiteral(x) => &x.token,
+            Expression::HashLiteral(x) => &x.token,
+            Expression::IndexExpression(x) => &x.token,
+            Expression::AssignExpression(x) => &x.token,
+            Expression::TryExpression(x) => &x.token,
+            Expression::MacroLiteral(x) => &x.token,
         }
     }
 }
@@ -252,11 +442,16 @@ impl IfExpression {
     }
 }
 
+/// `fn(params) -> type { body }`. The parameters, annotations and body are
+/// shared with every function value made from the literal.
 #[derive(Clone, PartialEq)]
 pub struct FunctionLiteral {
     pub token: Token,
-    pub parameters: Option<Vec<Expression>>,
-    pub body: Option<Box<Statement>>,
+    pub parameters: Option<Rc<Vec<Expression>>>,
+    /// The annotation of each parameter, in the same order.
+    pub param_types: Rc<Vec<Option<TypeAnnotation>>>,
+    pub return_type: Option<Rc<TypeAnnotation>>,
+    pub body: Option<Rc<BlockStatement>>,
 }
 
 impl FunctionLiteral {
@@ -267,7 +462,7 @@ impl FunctionLiteral {
         match &self.parameters {
             Some(p) => {
                 // let mut s: String = String::from("");
-                for i in p {
+                for i in p.iter() {
                     par.push_str(i.print().as_str());
                 }
             }
@@ -284,6 +479,76 @@ impl FunctionLiteral {
     }
 }
 
+/// A type written after `:` or `->`, such as `int`, `[string]`,
+/// `{string: int}` or `fn(int) -> bool`. The names in `NAMES` are the only
+/// named types; `any` accepts every value.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TypeAnnotation {
+    Named(Token),
+    Array(Token, Box<TypeAnnotation>),
+    Hash(Token, Box<TypeAnnotation>, Box<TypeAnnotation>),
+    Function(Token, Vec<TypeAnnotation>, Box<TypeAnnotation>),
+}
+
+impl TypeAnnotation {
+    pub const NAMES: [&'static str; 7] = ["int", "string", "bool", "null", "range", "error", "any"];
+
+    pub fn token(&self) -> &Token {
+        match self {
+            TypeAnnotation::Named(t) => t,
+            TypeAnnotation::Array(t, _) => t,
+            TypeAnnotation::Hash(t, _, _) => t,
+            TypeAnnotation::Function(t, _, _) => t,
+        }
+    }
+}
+
+/// Prints the annotation as it is written in the source.
+impl fmt::Display for TypeAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeAnnotation::Named(t) => write!(f, "{}", t.literal),
+            TypeAnnotation::Array(_, item) => write!(f, "[{}]", item),
+            TypeAnnotation::Hash(_, key, value) => write!(f, "{{{}: {}}}", key, value),
+            TypeAnnotation::Function(_, params, ret) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
+        }
+    }
+}
+
+/// `macro(params) { body }`. Bound with a top-level `let`, it is expanded
+/// before the program runs.
+#[derive(Clone, PartialEq)]
+pub struct MacroLiteral {
+    pub token: Token,
+    pub parameters: Option<Vec<Expression>>,
+    pub body: Option<Rc<BlockStatement>>,
+}
+
+impl MacroLiteral {
+    fn print(&self) -> String {
+        let mut par = String::new();
+        match &self.parameters {
+            Some(p) => {
+                for i in p {
+                    par.push_str(i.print().as_str());
+                }
+            }
+            None => par = "None".to_string(),
+        }
+        let bod = match &self.body {
+            Some(b) => b.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "MacroLiteral: {:?} params: {} body: {}",
+            self.token, par, bod
+        )
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct CallExpression {
     pub token: Token,
@@ -327,11 +592,150 @@ impl StringLiteral {
     }
 }
 
+#[derive(Clone, PartialEq)]
+pub enum InterpolationPart {
+    Literal(String),
+    Expression(Expression),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct InterpolatedString {
+    pub token: Token,
+    pub parts: Vec<InterpolationPart>,
+}
+
+impl InterpolatedString {
+    fn print(&self) -> String {
+        let mut parts = String::new();
+        for part in &self.parts {
+            match part {
+                InterpolationPart::Literal(x) => parts.push_str(&format!("(Literal: {})", x)),
+                InterpolationPart::Expression(x) => parts.push_str(&x.print()),
+            }
+        }
+        format!("InterpolatedString: {:?} parts: {}", self.token, parts)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ArrayLiteral {
+    pub token: Token,
+    pub elements: Vec<Expression>,
+}
+
+impl ArrayLiteral {
+    fn print(&self) -> String {
+        let mut elems = String::new();
+        for i in &self.elements {
+            elems.push_str(i.print().as_str());
+        }
+        format!("ArrayLiteral: {:?} elements: {}", self.token, elems)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct HashLiteral {
+    pub token: Token,
+    pub pairs: Vec<(Expression, Expression)>,
+}
+
+impl HashLiteral {
+    fn print(&self) -> String {
+        let mut pairs = String::new();
+        for (k, v) in &self.pairs {
+            pairs.push_str(&format!("({} : {})", k.print(), v.print()));
+        }
+        format!("HashLiteral: {:?} pairs: {}", self.token, pairs)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct IndexExpression {
+    pub token: Token,
+    pub left: Option<Box<Expression>>,
+    pub index: Option<Box<Expression>>,
+}
+
+impl IndexExpression {
+    fn print(&self) -> String {
+        let left = match &self.left {
+            Some(l) => l.print(),
+            None => "None".to_string(),
+        };
+        let index = match &self.index {
+            Some(i) => i.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "IndexExpr: {:?} left: {} index: {}",
+            self.token, left, index
+        )
+    }
+}
+
+/// `target = value` or a compound form such as `target += value`. The
+/// target is an identifier or an index expression.
+#[derive(Clone, PartialEq)]
+pub struct AssignExpression {
+    pub token: Token,
+    pub target: Option<Box<Expression>>,
+    pub operator: String,
+    pub value: Option<Box<Expression>>,
+}
+
+impl AssignExpression {
+    fn print(&self) -> String {
+        let target = match &self.target {
+            Some(t) => t.print(),
+            None => "None".to_string(),
+        };
+        let value = match &self.value {
+            Some(v) => v.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "AssignExpr: {:?} target: {} operator: {} value: {}",
+            self.token, target, self.operator, value
+        )
+    }
+}
+
+/// `try { body } catch (ident) { handler }`. Evaluates to the body's value,
+/// or to the handler's value with the caught error bound to `ident`.
+#[derive(Clone, PartialEq)]
+pub struct TryExpression {
+    pub token: Token,
+    pub body: Option<Box<Statement>>,
+    pub ident: Option<Identifier>,
+    pub handler: Option<Box<Statement>>,
+}
+
+impl TryExpression {
+    fn print(&self) -> String {
+        let body = match &self.body {
+            Some(b) => b.print(),
+            None => "None".to_string(),
+        };
+        let ident = match &self.ident {
+            Some(i) => i.print(),
+            None => "None".to_string(),
+        };
+        let handler = match &self.handler {
+            Some(h) => h.print(),
+            None => "None".to_string(),
+        };
+        format!(
+            "TryExpr: {:?} body: {} ident: {} handler: {}",
+            self.token, body, ident, handler
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         ast::{Expression, Identifier, IntegerLiteral},
-        token::{Token, TokenType},
+        token::{Span, Token, TokenType},
     };
 
     #[test]
@@ -340,6 +744,7 @@ mod tests {
             token: Token {
                 type_: TokenType::IDENT,
                 literal: "x".to_string(),
+                span: Span::default(),
             },
             value: "5".to_string(),
         });
@@ -354,6 +759,7 @@ mod tests {
             token: Token {
                 type_: TokenType::INT,
                 literal: "100".to_string(),
+                span: Span::default(),
             },
             value: 100,
         });
@@ -0,0 +1,439 @@
+use std::rc::Rc;
+
+use crate::ast::*;
+
+/// Rewriting traversal of the tree. A `Folder` takes each node by value and
+/// returns its replacement. The default methods rebuild the node from its
+/// folded children; override `fold_expression` or `fold_statement` to
+/// replace a node with one of another kind.
+pub trait Folder {
+    fn fold_program(&mut self, prog: Program) -> Program {
+        walk_program(self, prog)
+    }
+
+    fn fold_statement(&mut self, stmt: Statement) -> Statement {
+        walk_statement(self, stmt)
+    }
+
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        walk_expression(self, expr)
+    }
+
+    fn fold_let_statement(&mut self, stmt: LetStatement) -> LetStatement {
+        walk_let_statement(self, stmt)
+    }
+
+    fn fold_return_statement(&mut self, stmt: ReturnStatement) -> ReturnStatement {
+        walk_return_statement(self, stmt)
+    }
+
+    fn fold_expression_statement(&mut self, stmt: ExpressionStatement) -> ExpressionStatement {
+        walk_expression_statement(self, stmt)
+    }
+
+    fn fold_block_statement(&mut self, block: BlockStatement) -> BlockStatement {
+        walk_block_statement(self, block)
+    }
+
+    fn fold_while_statement(&mut self, stmt: WhileStatement) -> WhileStatement {
+        walk_while_statement(self, stmt)
+    }
+
+    fn fold_break_statement(&mut self, stmt: BreakStatement) -> BreakStatement {
+        stmt
+    }
+
+    fn fold_continue_statement(&mut self, stmt: ContinueStatement) -> ContinueStatement {
+        stmt
+    }
+
+    fn fold_for_statement(&mut self, stmt: ForStatement) -> ForStatement {
+        walk_for_statement(self, stmt)
+    }
+
+    fn fold_throw_statement(&mut self, stmt: ThrowStatement) -> ThrowStatement {
+        walk_throw_statement(self, stmt)
+    }
+
+    fn fold_infix_statement(&mut self, stmt: InfixStatement) -> InfixStatement {
+        walk_infix_statement(self, stmt)
+    }
+
+    fn fold_identifier(&mut self, ident: Identifier) -> Identifier {
+        ident
+    }
+
+    fn fold_integer_literal(&mut self, lit: IntegerLiteral) -> IntegerLiteral {
+        lit
+    }
+
+    fn fold_boolean(&mut self, lit: Boolean) -> Boolean {
+        lit
+    }
+
+    fn fold_string_literal(&mut self, lit: StringLiteral) -> StringLiteral {
+        lit
+    }
+
+    fn fold_prefix_expression(&mut self, expr: PrefixExpression) -> PrefixExpression {
+        walk_prefix_expression(self, expr)
+    }
+
+    fn fold_infix_expression(&mut self, expr: InfixExpression) -> InfixExpression {
+        walk_infix_expression(self, expr)
+    }
+
+    fn fold_if_expression(&mut self, expr: IfExpression) -> IfExpression {
+        walk_if_expression(self, expr)
+    }
+
+    fn fold_function_literal(&mut self, lit: FunctionLiteral) -> FunctionLiteral {
+        walk_function_literal(self, lit)
+    }
+
+    fn fold_macro_literal(&mut self, lit: MacroLiteral) -> MacroLiteral {
+        walk_macro_literal(self, lit)
+    }
+
+    fn fold_call_expression(&mut self, expr: CallExpression) -> CallExpression {
+        walk_call_expression(self, expr)
+    }
+
+    fn fold_interpolated_string(&mut self, lit: InterpolatedString) -> InterpolatedString {
+        walk_interpolated_string(self, lit)
+    }
+
+    fn fold_array_literal(&mut self, lit: ArrayLiteral) -> ArrayLiteral {
+        walk_array_literal(self, lit)
+    }
+
+    fn fold_hash_literal(&mut self, lit: HashLiteral) -> HashLiteral {
+        walk_hash_literal(self, lit)
+    }
+
+    fn fold_index_expression(&mut self, expr: IndexExpression) -> IndexExpression {
+        walk_index_expression(self, expr)
+    }
+
+    fn fold_assign_expression(&mut self, expr: AssignExpression) -> AssignExpression {
+        walk_assign_expression(self, expr)
+    }
+
+    fn fold_try_expression(&mut self, expr: TryExpression) -> TryExpression {
+        walk_try_expression(self, expr)
+    }
+}
+
+pub fn walk_program<F: Folder + ?Sized>(f: &mut F, prog: Program) -> Program {
+    prog.into_iter().map(|s| f.fold_statement(s)).collect()
+}
+
+pub fn walk_statement<F: Folder + ?Sized>(f: &mut F, stmt: Statement) -> Statement {
+    match stmt {
+        Statement::LetStatement(x) => Statement::LetStatement(f.fold_let_statement(x)),
+        Statement::ReturnStatement(x) => Statement::ReturnStatement(f.fold_return_statement(x)),
+        Statement::ExpressionStatement(x) => {
+            Statement::ExpressionStatement(f.fold_expression_statement(x))
+        }
+        Statement::BlockStatement(x) => Statement::BlockStatement(f.fold_block_statement(x)),
+        Statement::WhileStatement(x) => Statement::WhileStatement(f.fold_while_statement(x)),
+        Statement::BreakStatement(x) => Statement::BreakStatement(f.fold_break_statement(x)),
+        Statement::ContinueStatement(x) => {
+            Statement::ContinueStatement(f.fold_continue_statement(x))
+        }
+        Statement::ForStatement(x) => Statement::ForStatement(f.fold_for_statement(x)),
+        Statement::ThrowStatement(x) => Statement::ThrowStatement(f.fold_throw_statement(x)),
+        Statement::InfixStatement(x) => Statement::InfixStatement(f.fold_infix_statement(x)),
+    }
+}
+
+pub fn walk_expression<F: Folder + ?Sized>(f: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::Identifier(x) => Expression::Identifier(f.fold_identifier(x)),
+        Expression::IntegerLiteral(x) => Expression::IntegerLiteral(f.fold_integer_literal(x)),
+        Expression::PrefixExpression(x) => {
+            Expression::PrefixExpression(f.fold_prefix_expression(x))
+        }
+        Expression::InfixExpression(x) => Expression::InfixExpression(f.fold_infix_expression(x)),
+        Expression::Boolean(x) => Expression::Boolean(f.fold_boolean(x)),
+        Expression::IfExpression(x) => Expression::IfExpression(f.fold_if_expression(x)),
+        Expression::FunctionLiteral(x) => Expression::FunctionLiteral(f.fold_function_literal(x)),
+        Expression::CallExpression(x) => Expression::CallExpression(f.fold_call_expression(x)),
+        Expression::StringLiteral(x) => Expression::StringLiteral(f.fold_string_literal(x)),
+        Expression::InterpolatedString(x) => {
+            Expression::InterpolatedString(f.fold_interpolated_string(x))
+        }
+        Expression::ArrayLiteral(x) => Expression::ArrayLiteral(f.fold_array_literal(x)),
+        Expression::HashLiteral(x) => Expression::HashLiteral(f.fold_hash_literal(x)),
+        Expression::IndexExpression(x) => Expression::IndexExpression(f.fold_index_expression(x)),
+        Expression::AssignExpression(x) => {
+            Expression::AssignExpression(f.fold_assign_expression(x))
+        }
+        Expression::TryExpression(x) => Expression::TryExpression(f.fold_try_expression(x)),
+        Expression::MacroLiteral(x) => Expression::MacroLiteral(f.fold_macro_literal(x)),
+    }
+}
+
+fn fold_boxed<F: Folder + ?Sized>(
+    f: &mut F,
+    expr: Option<Box<Expression>>,
+) -> Option<Box<Expression>> {
+    expr.map(|e| Box::new(f.fold_expression(*e)))
+}
+
+fn fold_boxed_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    stmt: Option<Box<Statement>>,
+) -> Option<Box<Statement>> {
+    stmt.map(|s| Box::new(f.fold_statement(*s)))
+}
+
+fn fold_exprs<F: Folder + ?Sized>(f: &mut F, exprs: Vec<Expression>) -> Vec<Expression> {
+    exprs.into_iter().map(|e| f.fold_expression(e)).collect()
+}
+
+/// Function bodies are shared, so a body is copied before it is folded
+/// unless nothing else holds it.
+fn fold_body<F: Folder + ?Sized>(
+    f: &mut F,
+    body: Option<Rc<BlockStatement>>,
+) -> Option<Rc<BlockStatement>> {
+    body.map(|b| Rc::new(f.fold_block_statement(Rc::unwrap_or_clone(b))))
+}
+
+pub fn walk_let_statement<F: Folder + ?Sized>(f: &mut F, mut stmt: LetStatement) -> LetStatement {
+    stmt.name = stmt.name.map(|n| f.fold_identifier(n));
+    stmt.value = fold_boxed(f, stmt.value);
+    stmt
+}
+
+pub fn walk_return_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut stmt: ReturnStatement,
+) -> ReturnStatement {
+    stmt.value = fold_boxed(f, stmt.value);
+    stmt
+}
+
+pub fn walk_expression_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut stmt: ExpressionStatement,
+) -> ExpressionStatement {
+    stmt.expr = fold_boxed(f, stmt.expr);
+    stmt
+}
+
+pub fn walk_block_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut block: BlockStatement,
+) -> BlockStatement {
+    block.statements = block
+        .statements
+        .into_iter()
+        .map(|s| f.fold_statement(s))
+        .collect();
+    block
+}
+
+pub fn walk_while_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut stmt: WhileStatement,
+) -> WhileStatement {
+    stmt.cond = fold_boxed(f, stmt.cond);
+    stmt.body = fold_boxed_statement(f, stmt.body);
+    stmt
+}
+
+pub fn walk_for_statement<F: Folder + ?Sized>(f: &mut F, mut stmt: ForStatement) -> ForStatement {
+    stmt.ident = stmt.ident.map(|i| f.fold_identifier(i));
+    stmt.iterable = fold_boxed(f, stmt.iterable);
+    stmt.body = fold_boxed_statement(f, stmt.body);
+    stmt
+}
+
+pub fn walk_throw_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut stmt: ThrowStatement,
+) -> ThrowStatement {
+    stmt.value = fold_boxed(f, stmt.value);
+    stmt
+}
+
+pub fn walk_infix_statement<F: Folder + ?Sized>(
+    f: &mut F,
+    mut stmt: InfixStatement,
+) -> InfixStatement {
+    stmt.value = fold_boxed(f, stmt.value);
+    stmt
+}
+
+pub fn walk_prefix_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: PrefixExpression,
+) -> PrefixExpression {
+    expr.right = fold_boxed(f, expr.right);
+    expr
+}
+
+pub fn walk_infix_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: InfixExpression,
+) -> InfixExpression {
+    expr.left = fold_boxed(f, expr.left);
+    expr.right = fold_boxed(f, expr.right);
+    expr
+}
+
+pub fn walk_if_expression<F: Folder + ?Sized>(f: &mut F, mut expr: IfExpression) -> IfExpression {
+    expr.cond = fold_boxed(f, expr.cond);
+    expr.consequence = fold_boxed_statement(f, expr.consequence);
+    expr.alternative = fold_boxed_statement(f, expr.alternative);
+    expr
+}
+
+pub fn walk_function_literal<F: Folder + ?Sized>(
+    f: &mut F,
+    mut lit: FunctionLiteral,
+) -> FunctionLiteral {
+    lit.parameters = lit
+        .parameters
+        .map(|ps| Rc::new(fold_exprs(f, Rc::unwrap_or_clone(ps))));
+    lit.body = fold_body(f, lit.body);
+    lit
+}
+
+pub fn walk_macro_literal<F: Folder + ?Sized>(f: &mut F, mut lit: MacroLiteral) -> MacroLiteral {
+    lit.parameters = lit.parameters.map(|ps| fold_exprs(f, ps));
+    lit.body = fold_body(f, lit.body);
+    lit
+}
+
+pub fn walk_call_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: CallExpression,
+) -> CallExpression {
+    expr.func = fold_boxed(f, expr.func);
+    expr.args = expr.args.map(|args| fold_exprs(f, args));
+    expr
+}
+
+pub fn walk_interpolated_string<F: Folder + ?Sized>(
+    f: &mut F,
+    mut lit: InterpolatedString,
+) -> InterpolatedString {
+    lit.parts = lit
+        .parts
+        .into_iter()
+        .map(|part| match part {
+            InterpolationPart::Expression(e) => InterpolationPart::Expression(f.fold_expression(e)),
+            literal => literal,
+        })
+        .collect();
+    lit
+}
+
+pub fn walk_array_literal<F: Folder + ?Sized>(f: &mut F, mut lit: ArrayLiteral) -> ArrayLiteral {
+    lit.elements = fold_exprs(f, lit.elements);
+    lit
+}
+
+pub fn walk_hash_literal<F: Folder + ?Sized>(f: &mut F, mut lit: HashLiteral) -> HashLiteral {
+    lit.pairs = lit
+        .pairs
+        .into_iter()
+        .map(|(k, v)| (f.fold_expression(k), f.fold_expression(v)))
+        .collect();
+    lit
+}
+
+pub fn walk_index_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: IndexExpression,
+) -> IndexExpression {
+    expr.left = fold_boxed(f, expr.left);
+    expr.index = fold_boxed(f, expr.index);
+    expr
+}
+
+pub fn walk_assign_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: AssignExpression,
+) -> AssignExpression {
+    expr.target = fold_boxed(f, expr.target);
+    expr.value = fold_boxed(f, expr.value);
+    expr
+}
+
+pub fn walk_try_expression<F: Folder + ?Sized>(
+    f: &mut F,
+    mut expr: TryExpression,
+) -> TryExpression {
+    expr.body = fold_boxed_statement(f, expr.body);
+    expr.ident = expr.ident.map(|i| f.fold_identifier(i));
+    expr.handler = fold_boxed_statement(f, expr.handler);
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Renames identifiers and replaces `x * 1` with `x`.
+    struct Rewrite;
+
+    impl Folder for Rewrite {
+        fn fold_identifier(&mut self, mut ident: Identifier) -> Identifier {
+            ident.value = ident.value.to_uppercase();
+            ident.token.literal = ident.value.clone();
+            ident
+        }
+
+        fn fold_expression(&mut self, expr: Expression) -> Expression {
+            match walk_expression(self, expr) {
+                Expression::InfixExpression(InfixExpression {
+                    left: Some(left),
+                    operator,
+                    right: Some(right),
+                    ..
+                }) if operator == "*"
+                    && matches!(
+                        *right,
+                        Expression::IntegerLiteral(IntegerLiteral { value: 1, .. })
+                    ) =>
+                {
+                    *left
+                }
+                other => other,
+            }
+        }
+    }
+
+    /// The expressions of a program's statements.
+    fn exprs(prog: Program) -> Vec<Expression> {
+        prog.into_iter()
+            .map(|s| match s {
+                Statement::LetStatement(LetStatement { value: Some(v), .. }) => *v,
+                Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => *e,
+                _ => panic!("expected let or expression statement, got {}", s.print()),
+            })
+            .collect()
+    }
+
+    fn parse(input: &str) -> Program {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        par.parse_program()
+    }
+
+    #[test]
+    fn fold_test() {
+        let folded = Rewrite.fold_program(parse("let f = fn(a) { [a * 1, b] }; f(c * 1)"));
+        match &folded[0] {
+            Statement::LetStatement(x) => assert_eq!(x.name.as_ref().unwrap().value, "F"),
+            _ => panic!("expected let statement, got {}", folded[0].print()),
+        }
+        assert!(exprs(folded) == exprs(parse("fn(A) { [A, B] }; F(C)")));
+    }
+}
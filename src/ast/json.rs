@@ -0,0 +1,506 @@
+use crate::ast::*;
+use crate::json::Json;
+use crate::token::{Span, TokenType};
+
+/// Conversion of tree nodes to JSON. Every node becomes an object with a
+/// `kind` naming its type followed by its fields; tokens carry their span.
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// The inverse of `ToJson`.
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> Result<Self, String>;
+}
+
+/// Serializes a program as a JSON array of statements.
+pub fn program_to_json(program: &Program) -> Json {
+    program.to_json()
+}
+
+/// Reads back what `program_to_json` wrote. Only the round-trip tests call
+/// it so far.
+#[allow(dead_code)]
+pub fn program_from_json(text: &str) -> Result<Program, String> {
+    Program::from_json(&Json::parse(text)?)
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> Result<&'a Json, String> {
+    json.get(name)
+        .ok_or_else(|| format!("missing field {} in {}", name, json))
+}
+
+fn kind(json: &Json) -> Result<&str, String> {
+    match field(json, "kind")? {
+        Json::String(s) => Ok(s),
+        other => Err(format!("expected string kind, got {}", other.type_())),
+    }
+}
+
+/// Implements both traits for a node struct from its field list.
+macro_rules! json_node {
+    ($name:ident, [$($field:ident),*]) => {
+        impl ToJson for $name {
+            fn to_json(&self) -> Json {
+                Json::Object(vec![
+                    ("kind".to_string(), Json::String(stringify!($name).to_string())),
+                    $((stringify!($field).to_string(), self.$field.to_json()),)*
+                ])
+            }
+        }
+
+        impl FromJson for $name {
+            fn from_json(json: &Json) -> Result<Self, String> {
+                Ok($name {
+                    $($field: FromJson::from_json(field(json, stringify!($field))?)?,)*
+                })
+            }
+        }
+    };
+}
+
+json_node!(LetStatement, [token, name, annotation, value]);
+json_node!(ReturnStatement, [token, value]);
+json_node!(ExpressionStatement, [token, expr]);
+json_node!(BlockStatement, [token, statements, end]);
+json_node!(WhileStatement, [token, cond, body]);
+json_node!(BreakStatement, [token]);
+json_node!(ContinueStatement, [token]);
+json_node!(ForStatement, [token, ident, iterable, body]);
+json_node!(ThrowStatement, [token, value]);
+json_node!(InfixStatement, [token, precedence, operator, value]);
+json_node!(Identifier, [token, value]);
+json_node!(IntegerLiteral, [token, value]);
+json_node!(PrefixExpression, [token, operator, right]);
+json_node!(InfixExpression, [token, left, operator, right]);
+json_node!(Boolean, [token, value]);
+json_node!(IfExpression, [token, cond, consequence, alternative]);
+json_node!(
+    FunctionLiteral,
+    [token, parameters, param_types, return_type, body]
+);
+json_node!(MacroLiteral, [token, parameters, body]);
+json_node!(CallExpression, [token, func, args]);
+json_node!(StringLiteral, [token, value]);
+json_node!(InterpolatedString, [token, parts]);
+json_node!(ArrayLiteral, [token, elements]);
+json_node!(HashLiteral, [token, pairs]);
+json_node!(IndexExpression, [token, left, index]);
+json_node!(AssignExpression, [token, target, operator, value]);
+json_node!(TryExpression, [token, body, ident, handler]);
+
+impl ToJson for Statement {
+    fn to_json(&self) -> Json {
+        match self {
+            Statement::LetStatement(x) => x.to_json(),
+            Statement::ReturnStatement(x) => x.to_json(),
+            Statement::ExpressionStatement(x) => x.to_json(),
+            Statement::BlockStatement(x) => x.to_json(),
+            Statement::WhileStatement(x) => x.to_json(),
+            Statement::BreakStatement(x) => x.to_json(),
+            Statement::ContinueStatement(x) => x.to_json(),
+            Statement::ForStatement(x) => x.to_json(),
+            Statement::ThrowStatement(x) => x.to_json(),
+            Statement::InfixStatement(x) => x.to_json(),
+        }
+    }
+}
+
+impl FromJson for Statement {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match kind(json)? {
+            "LetStatement" => Statement::LetStatement(FromJson::from_json(json)?),
+            "ReturnStatement" => Statement::ReturnStatement(FromJson::from_json(json)?),
+            "ExpressionStatement" => Statement::ExpressionStatement(FromJson::from_json(json)?),
+            "BlockStatement" => Statement::BlockStatement(FromJson::from_json(json)?),
+            "WhileStatement" => Statement::WhileStatement(FromJson::from_json(json)?),
+            "BreakStatement" => Statement::BreakStatement(FromJson::from_json(json)?),
+            "ContinueStatement" => Statement::ContinueStatement(FromJson::from_json(json)?),
+            "ForStatement" => Statement::ForStatement(FromJson::from_json(json)?),
+            "ThrowStatement" => Statement::ThrowStatement(FromJson::from_json(json)?),
+            "InfixStatement" => Statement::InfixStatement(FromJson::from_json(json)?),
+            other => return Err(format!("unknown statement kind {}", other)),
+        })
+    }
+}
+
+impl ToJson for Expression {
+    fn to_json(&self) -> Json {
+        match self {
+            Expression::Identifier(x) => x.to_json(),
+            Expression::IntegerLiteral(x) => x.to_json(),
+            Expression::PrefixExpression(x) => x.to_json(),
+            Expression::InfixExpression(x) => x.to_json(),
+            Expression::Boolean(x) => x.to_json(),
+            Expression::IfExpression(x) => x.to_json(),
+            Expression::FunctionLiteral(x) => x.to_json(),
+            Expression::CallExpression(x) => x.to_json(),
+            Expression::StringLiteral(x) => x.to_json(),
+            Expression::InterpolatedString(x) => x.to_json(),
+            Expression::ArrayLiteral(x) => x.to_json(),
+            Expression::HashLiteral(x) => x.to_json(),
+            Expression::IndexExpression(x) => x.to_json(),
+            Expression::AssignExpression(x) => x.to_json(),
+            Expression::TryExpression(x) => x.to_json(),
+            Expression::MacroLiteral(x) => x.to_json(),
+        }
+    }
+}
+
+impl FromJson for Expression {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(match kind(json)? {
+            "Identifier" => Expression::Identifier(FromJson::from_json(json)?),
+            "IntegerLiteral" => Expression::IntegerLiteral(FromJson::from_json(json)?),
+            "PrefixExpression" => Expression::PrefixExpression(FromJson::from_json(json)?),
+            "InfixExpression" => Expression::InfixExpression(FromJson::from_json(json)?),
+            "Boolean" => Expression::Boolean(FromJson::from_json(json)?),
+            "IfExpression" => Expression::IfExpression(FromJson::from_json(json)?),
+            "FunctionLiteral" => Expression::FunctionLiteral(FromJson::from_json(json)?),
+            "CallExpression" => Expression::CallExpression(FromJson::from_json(json)?),
+            "StringLiteral" => Expression::StringLiteral(FromJson::from_json(json)?),
+            "InterpolatedString" => Expression::InterpolatedString(FromJson::from_json(json)?),
+            "ArrayLiteral" => Expression::ArrayLiteral(FromJson::from_json(json)?),
+            "HashLiteral" => Expression::HashLiteral(FromJson::from_json(json)?),
+            "IndexExpression" => Expression::IndexExpression(FromJson::from_json(json)?),
+            "AssignExpression" => Expression::AssignExpression(FromJson::from_json(json)?),
+            "TryExpression" => Expression::TryExpression(FromJson::from_json(json)?),
+            "MacroLiteral" => Expression::MacroLiteral(FromJson::from_json(json)?),
+            other => return Err(format!("unknown expression kind {}", other)),
+        })
+    }
+}
+
+/// Literal text is `{"kind": "Literal", "value": ...}`; an embedded
+/// expression is the expression itself.
+impl ToJson for InterpolationPart {
+    fn to_json(&self) -> Json {
+        match self {
+            InterpolationPart::Literal(s) => Json::Object(vec![
+                ("kind".to_string(), Json::String("Literal".to_string())),
+                ("value".to_string(), s.to_json()),
+            ]),
+            InterpolationPart::Expression(e) => e.to_json(),
+        }
+    }
+}
+
+impl FromJson for InterpolationPart {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match kind(json)? {
+            "Literal" => Ok(InterpolationPart::Literal(String::from_json(field(
+                json, "value",
+            )?)?)),
+            _ => Ok(InterpolationPart::Expression(Expression::from_json(json)?)),
+        }
+    }
+}
+
+impl ToJson for TypeAnnotation {
+    fn to_json(&self) -> Json {
+        let node = |kind: &str, fields: Vec<(&str, Json)>| {
+            let mut pairs = vec![("kind".to_string(), Json::String(kind.to_string()))];
+            pairs.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+            Json::Object(pairs)
+        };
+        match self {
+            TypeAnnotation::Named(t) => node("Named", vec![("token", t.to_json())]),
+            TypeAnnotation::Array(t, item) => node(
+                "Array",
+                vec![("token", t.to_json()), ("item", item.to_json())],
+            ),
+            TypeAnnotation::Hash(t, key, value) => node(
+                "Hash",
+                vec![
+                    ("token", t.to_json()),
+                    ("key", key.to_json()),
+                    ("value", value.to_json()),
+                ],
+            ),
+            TypeAnnotation::Function(t, params, ret) => node(
+                "Function",
+                vec![
+                    ("token", t.to_json()),
+                    ("params", params.to_json()),
+                    ("ret", ret.to_json()),
+                ],
+            ),
+        }
+    }
+}
+
+impl FromJson for TypeAnnotation {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let token = Token::from_json(field(json, "token")?)?;
+        match kind(json)? {
+            "Named" => Ok(TypeAnnotation::Named(token)),
+            "Array" => Ok(TypeAnnotation::Array(
+                token,
+                Box::from_json(field(json, "item")?)?,
+            )),
+            "Hash" => Ok(TypeAnnotation::Hash(
+                token,
+                Box::from_json(field(json, "key")?)?,
+                Box::from_json(field(json, "value")?)?,
+            )),
+            "Function" => Ok(TypeAnnotation::Function(
+                token,
+                Vec::from_json(field(json, "params")?)?,
+                Box::from_json(field(json, "ret")?)?,
+            )),
+            other => Err(format!("unknown type annotation kind {}", other)),
+        }
+    }
+}
+
+impl ToJson for Token {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            (
+                "type".to_string(),
+                Json::String(format!("{:?}", self.type_)),
+            ),
+            ("literal".to_string(), self.literal.to_json()),
+            ("span".to_string(), self.span.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Token {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let type_ = String::from_json(field(json, "type")?)?;
+        Ok(Token {
+            type_: TokenType::from_name(&type_)
+                .ok_or_else(|| format!("unknown token type {}", type_))?,
+            literal: String::from_json(field(json, "literal")?)?,
+            span: Span::from_json(field(json, "span")?)?,
+        })
+    }
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("start".to_string(), self.start.to_json()),
+            ("end".to_string(), self.end.to_json()),
+            ("line".to_string(), self.line.to_json()),
+            ("column".to_string(), self.column.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Span {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Span {
+            start: usize::from_json(field(json, "start")?)?,
+            end: usize::from_json(field(json, "end")?)?,
+            line: usize::from_json(field(json, "line")?)?,
+            column: usize::from_json(field(json, "column")?)?,
+        })
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::String(s) => Ok(s.clone()),
+            other => Err(format!("expected string, got {}", other.type_())),
+        }
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Json {
+        Json::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Bool(b) => Ok(*b),
+            other => Err(format!("expected bool, got {}", other.type_())),
+        }
+    }
+}
+
+impl ToJson for i64 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self)
+    }
+}
+
+impl FromJson for i64 {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Number(n) => Ok(*n),
+            other => Err(format!("expected number, got {}", other.type_())),
+        }
+    }
+}
+
+impl ToJson for i32 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as i64)
+    }
+}
+
+impl FromJson for i32 {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        i64::from_json(json)?
+            .try_into()
+            .map_err(|_| format!("number out of range: {}", json))
+    }
+}
+
+impl ToJson for usize {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as i64)
+    }
+}
+
+impl FromJson for usize {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        i64::from_json(json)?
+            .try_into()
+            .map_err(|_| format!("number out of range: {}", json))
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(x) => x.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Null => Ok(None),
+            other => T::from_json(other).map(Some),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Array(items) => items.iter().map(T::from_json).collect(),
+            other => Err(format!("expected array, got {}", other.type_())),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Box<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        T::from_json(json).map(Box::new)
+    }
+}
+
+impl<T: ToJson> ToJson for Rc<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Rc<T> {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        T::from_json(json).map(Rc::new)
+    }
+}
+
+/// Pairs are two-element arrays.
+impl<A: ToJson, B: ToJson> ToJson for (A, B) {
+    fn to_json(&self) -> Json {
+        Json::Array(vec![self.0.to_json(), self.1.to_json()])
+    }
+}
+
+impl<A: FromJson, B: FromJson> FromJson for (A, B) {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Array(items) if items.len() == 2 => {
+                Ok((A::from_json(&items[0])?, B::from_json(&items[1])?))
+            }
+            other => Err(format!("expected pair, got {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn json_round_trip_test() {
+        let input = "let x = 5; let f = fn(a, b) { if (a > b) { return a; } else { b } };
+            let h = {\"k\": [1, 2][0]}; x += f(1, -2); \"v ${x}!\";
+            while (x < 10) { x = x + 1; if (x == 8) { break } }
+            for (i in 0..3) { continue; }
+            try { throw \"e\" } catch (e) { e }
+            infixr 5 <> = fn(a, b) { a }; 1 <> 2;
+            let m = macro(a) { quote(unquote(a)) };
+            let g: fn([int], {string: any}) -> bool = fn(xs: [int], h) -> bool { true };";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new());
+        let text = program_to_json(&program).to_string();
+        let back = program_from_json(&text).unwrap();
+        assert!(back == program);
+        assert_eq!(program_to_json(&back).to_string(), text);
+    }
+
+    #[test]
+    fn json_format_test() {
+        let mut lex = Lexer::init_lexer("-x");
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(
+            program_to_json(&program).to_string(),
+            concat!(
+                r#"[{"kind":"ExpressionStatement","token":{"type":"MINUS","literal":"-","#,
+                r#""span":{"start":0,"end":1,"line":1,"column":1}},"expr":{"kind":"#,
+                r#""PrefixExpression","token":{"type":"MINUS","literal":"-","span":"#,
+                r#"{"start":0,"end":1,"line":1,"column":1}},"operator":"-","right":"#,
+                r#"{"kind":"Identifier","token":{"type":"IDENT","literal":"x","span":"#,
+                r#"{"start":1,"end":2,"line":1,"column":2}},"value":"x"}}}]"#
+            )
+        );
+        let errors = vec![
+            (r#"[{"kind":"Nope"}]"#, "unknown statement kind Nope"),
+            (
+                r#"[{"kind":"BreakStatement"}]"#,
+                r#"missing field token in {"kind":"BreakStatement"}"#,
+            ),
+            ("{}", "expected array, got object"),
+            ("[", "invalid JSON at byte 1: unexpected end of input"),
+        ];
+        for (input, expected) in errors {
+            assert_eq!(program_from_json(input).err(), Some(expected.to_string()));
+        }
+    }
+}
@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::lexer::Lexer;
+use crate::parser::{
+    Parser, ASSIGN, CALL, EQUALS, INDEX, LESSGREATER, LOWEST, PREFIX, PRODUCT, RANGE, SUM,
+};
+use crate::token::{Comment, TokenType};
+
+const INDENT: &str = "    ";
+
+/// A block holding one statement no longer than this stays on one line.
+const INLINE_WIDTH: usize = 40;
+
+/// Parses `input` and prints it back in canonical form, keeping its
+/// comments and single blank lines between statements.
+pub fn format_source(input: &str) -> Result<String, Vec<String>> {
+    let mut lex = Lexer::init_lexer(input);
+    let mut parser = Parser::new(&mut lex);
+    let program = parser.parse_program();
+    let errors = parser.errors();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut printer = Printer::new(input, parser.comments());
+    printer.program(&program);
+    Ok(printer.finish())
+}
+
+/// Prints a single expression as Monkey source.
+pub fn print_expression(expr: &Expression) -> String {
+    let mut printer = Printer::new("", &[]);
+    printer.expr(expr);
+    printer.out
+}
+
+/// Prints a single statement as Monkey source, without a trailing
+/// semicolon on an expression.
+pub fn print_statement(stmt: &Statement) -> String {
+    let mut printer = Printer::new("", &[]);
+    printer.statement(stmt, None, true);
+    printer.out
+}
+
+/// Turns a tree back into Monkey source. Parentheses are added only where
+/// the parser would otherwise group operators differently, so printing and
+/// reparsing gives back the same tree.
+pub struct Printer<'a> {
+    out: String,
+    indent: usize,
+    source: &'a str,
+    comments: &'a [Comment],
+    next_comment: usize,
+    /// Precedence and right associativity of declared operators.
+    operators: HashMap<String, (i32, bool)>,
+}
+
+impl<'a> Printer<'a> {
+    /// `source` is the text the tree was parsed from, used to keep blank
+    /// lines; it may be empty.
+    pub fn new(source: &'a str, comments: &'a [Comment]) -> Printer<'a> {
+        Printer {
+            out: String::new(),
+            indent: 0,
+            source,
+            comments,
+            next_comment: 0,
+            operators: HashMap::new(),
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    pub fn program(&mut self, prog: &Program) {
+        let mut decls = OperatorDecls(&mut self.operators);
+        decls.visit_program(prog);
+        self.statements(prog, None);
+    }
+
+    fn statements(&mut self, stmts: &[Statement], end: Option<&Token>) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            let start = stmt.token().span.start;
+            self.comments_before(start);
+            if self.blank_line_before(start) {
+                self.out.push('\n');
+            }
+            self.write_indent();
+            let last = end.is_some() && i + 1 == stmts.len();
+            self.statement(stmt, stmts.get(i + 1), last);
+            self.out.push('\n');
+        }
+        match end {
+            Some(tok) => self.comments_before(tok.span.start),
+            None => self.comments_before(usize::MAX),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement, next: Option<&Statement>, last: bool) {
+        match stmt {
+            Statement::LetStatement(x) => {
+                self.out.push_str("let ");
+                if let Some(name) = &x.name {
+                    self.out.push_str(&name.value);
+                }
+                if let Some(ty) = &x.annotation {
+                    self.out.push_str(&format!(": {}", ty));
+                }
+                self.out.push_str(" = ");
+                self.expr_opt(&x.value);
+                self.out.push(';');
+            }
+            Statement::ReturnStatement(x) => {
+                self.out.push_str("return");
+                if let Some(value) = &x.value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+                self.out.push(';');
+            }
+            Statement::ExpressionStatement(x) => {
+                self.expr_opt(&x.expr);
+                let semicolon = match x.expr.as_deref() {
+                    _ if last => false,
+                    // A trailing `}` already ends these unless the next
+                    // statement would be read as an operand.
+                    Some(Expression::IfExpression(_)) | Some(Expression::TryExpression(_)) => {
+                        next.is_some_and(|n| self.continues_expression(n))
+                    }
+                    _ => true,
+                };
+                if semicolon {
+                    self.out.push(';');
+                }
+            }
+            Statement::BlockStatement(x) => self.block(x),
+            Statement::WhileStatement(x) => {
+                self.out.push_str("while (");
+                self.expr_opt(&x.cond);
+                self.out.push_str(") ");
+                self.body(&x.body);
+            }
+            Statement::BreakStatement(_) => self.out.push_str("break;"),
+            Statement::ContinueStatement(_) => self.out.push_str("continue;"),
+            Statement::ForStatement(x) => {
+                self.out.push_str("for (");
+                if let Some(ident) = &x.ident {
+                    self.out.push_str(&ident.value);
+                }
+                self.out.push_str(" in ");
+                self.expr_opt(&x.iterable);
+                self.out.push_str(") ");
+                self.body(&x.body);
+            }
+            Statement::ThrowStatement(x) => {
+                self.out.push_str("throw ");
+                self.expr_opt(&x.value);
+                self.out.push(';');
+            }
+            Statement::InfixStatement(x) => {
+                let keyword = match x.token.type_ {
+                    TokenType::INFIXR => "infixr",
+                    _ => "infixl",
+                };
+                self.out
+                    .push_str(&format!("{} {} {} = ", keyword, x.precedence, x.operator));
+                self.expr_opt(&x.value);
+                self.out.push(';');
+            }
+        }
+    }
+
+    /// Whether `stmt` starts with a token the parser would take as the
+    /// continuation of a preceding expression.
+    fn continues_expression(&self, stmt: &Statement) -> bool {
+        let expr = match stmt {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => return false,
+        };
+        let mut printer = Printer::new("", &[]);
+        printer.operators = self.operators.clone();
+        printer.expr(expr);
+        printer.out.starts_with(['(', '[', '-'])
+    }
+
+    fn body(&mut self, body: &Option<Box<Statement>>) {
+        match body.as_deref() {
+            Some(Statement::BlockStatement(b)) => self.block(b),
+            _ => self.out.push_str("{}"),
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        if let Some(line) = self.inline_block(block) {
+            self.out.push_str(&line);
+            return;
+        }
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.statements(&block.statements, Some(&block.end));
+        self.indent -= 1;
+        self.write_indent();
+        self.out.push('}');
+    }
+
+    /// The one-line form of a block, if it has no comments and at most one
+    /// short simple statement.
+    fn inline_block(&mut self, block: &BlockStatement) -> Option<String> {
+        let (start, end) = (block.token.span.start, block.end.span.start);
+        if self
+            .comments
+            .iter()
+            .any(|c| start <= c.span.start && c.span.start < end)
+        {
+            return None;
+        }
+        let stmt = match block.statements.as_slice() {
+            [] => return Some("{}".to_string()),
+            [stmt] => stmt,
+            _ => return None,
+        };
+        match stmt {
+            Statement::ExpressionStatement(_)
+            | Statement::ReturnStatement(_)
+            | Statement::BreakStatement(_)
+            | Statement::ContinueStatement(_)
+            | Statement::ThrowStatement(_) => (),
+            _ => return None,
+        }
+        let mark = self.out.len();
+        self.statement(stmt, None, true);
+        let line = self.out.split_off(mark);
+        if line.len() > INLINE_WIDTH || line.contains('\n') {
+            return None;
+        }
+        Some(format!("{{ {} }}", line))
+    }
+
+    /// Prints the comments that start before `offset`. A comment that
+    /// trailed code goes back on the end of the last printed line.
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.span.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            if !c.own_line && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out.push(' ');
+            } else {
+                if self.blank_line_before(c.span.start) {
+                    self.out.push('\n');
+                }
+                self.write_indent();
+            }
+            self.out.push_str(&c.text);
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether the source had an empty line right before `offset`. Blank
+    /// lines at the start of a block or file are dropped.
+    fn blank_line_before(&self, offset: usize) -> bool {
+        if self.out.is_empty() || self.out.ends_with("{\n") {
+            return false;
+        }
+        let before = match self.source.get(..offset) {
+            Some(s) => s,
+            None => return false,
+        };
+        let gap = &before[before.trim_end().len()..];
+        gap.matches('\n').count() > 1
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn expr_opt(&mut self, expr: &Option<Box<Expression>>) {
+        if let Some(e) = expr {
+            self.expr(e);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Identifier(x) => self.out.push_str(&x.value),
+            Expression::IntegerLiteral(x) => self.out.push_str(&x.value.to_string()),
+            Expression::Boolean(x) => self.out.push_str(if x.value { "true" } else { "false" }),
+            Expression::PrefixExpression(x) => {
+                self.out.push_str(&x.operator);
+                if let Some(right) = &x.right {
+                    let parens = match right.as_ref() {
+                        Expression::PrefixExpression(_) => true,
+                        e => self.loosens(e, PREFIX),
+                    };
+                    self.operand(right, parens);
+                }
+            }
+            Expression::InfixExpression(x) => {
+                let (prec, rbp) = self.infix_binding(&x.operator);
+                if let Some(left) = &x.left {
+                    let parens = self.binds_past(left, prec);
+                    self.operand(left, parens);
+                }
+                if x.operator == ".." {
+                    self.out.push_str("..");
+                } else {
+                    self.out.push_str(&format!(" {} ", x.operator));
+                }
+                if let Some(right) = &x.right {
+                    let parens = self.loosens(right, rbp);
+                    self.operand(right, parens);
+                }
+            }
+            Expression::IfExpression(x) => {
+                self.out.push_str("if (");
+                self.expr_opt(&x.cond);
+                self.out.push_str(") ");
+                self.body(&x.consequence);
+                if x.alternative.is_some() {
+                    self.out.push_str(" else ");
+                    self.body(&x.alternative);
+                }
+            }
+            Expression::FunctionLiteral(x) => {
+                self.out.push_str("fn");
+                self.parameters(
+                    x.parameters.as_deref().map(Vec::as_slice),
+                    &x.param_types,
+                    x.return_type.as_deref(),
+                );
+                self.function_body(&x.body);
+            }
+            Expression::MacroLiteral(x) => {
+                self.out.push_str("macro");
+                self.parameters(x.parameters.as_deref(), &[], None);
+                self.function_body(&x.body);
+            }
+            Expression::CallExpression(x) => {
+                if let Some(func) = &x.func {
+                    let parens = self.binds_past(func, CALL);
+                    self.operand(func, parens);
+                }
+                self.out.push('(');
+                self.list(x.args.as_deref().unwrap_or_default());
+                self.out.push(')');
+            }
+            Expression::StringLiteral(x) => self.out.push_str(&format!("\"{}\"", x.value)),
+            Expression::InterpolatedString(x) => {
+                self.out.push('"');
+                for part in &x.parts {
+                    match part {
+                        InterpolationPart::Literal(s) => self.out.push_str(s),
+                        InterpolationPart::Expression(e) => {
+                            self.out.push_str("${");
+                            self.expr(e);
+                            self.out.push('}');
+                        }
+                    }
+                }
+                self.out.push('"');
+            }
+            Expression::ArrayLiteral(x) => {
+                self.out.push('[');
+                self.list(&x.elements);
+                self.out.push(']');
+            }
+            Expression::HashLiteral(x) => {
+                self.out.push('{');
+                for (i, (key, value)) in x.pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(key);
+                    self.out.push_str(": ");
+                    self.expr(value);
+                }
+                self.out.push('}');
+            }
+            Expression::IndexExpression(x) => {
+                if let Some(left) = &x.left {
+                    let parens = self.binds_past(left, INDEX);
+                    self.operand(left, parens);
+                }
+                self.out.push('[');
+                self.expr_opt(&x.index);
+                self.out.push(']');
+            }
+            Expression::AssignExpression(x) => {
+                self.expr_opt(&x.target);
+                self.out.push_str(&format!(" {} ", x.operator));
+                self.expr_opt(&x.value);
+            }
+            Expression::TryExpression(x) => {
+                self.out.push_str("try ");
+                self.body(&x.body);
+                self.out.push_str(" catch (");
+                if let Some(ident) = &x.ident {
+                    self.out.push_str(&ident.value);
+                }
+                self.out.push_str(") ");
+                self.body(&x.handler);
+            }
+        }
+    }
+
+    fn operand(&mut self, expr: &Expression, parens: bool) {
+        if parens {
+            self.out.push('(');
+        }
+        self.expr(expr);
+        if parens {
+            self.out.push(')');
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expression]) {
+        for (i, e) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(e);
+        }
+    }
+
+    fn parameters(
+        &mut self,
+        params: Option<&[Expression]>,
+        types: &[Option<TypeAnnotation>],
+        ret: Option<&TypeAnnotation>,
+    ) {
+        self.out.push('(');
+        for (i, param) in params.into_iter().flatten().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(param);
+            if let Some(Some(ty)) = types.get(i) {
+                self.out.push_str(&format!(": {}", ty));
+            }
+        }
+        self.out.push_str(") ");
+        if let Some(ty) = ret {
+            self.out.push_str(&format!("-> {} ", ty));
+        }
+    }
+
+    fn function_body(&mut self, body: &Option<Rc<BlockStatement>>) {
+        match body {
+            Some(b) => self.block(b),
+            None => self.out.push_str("{}"),
+        }
+    }
+
+    /// The precedence of an infix operator and the precedence its right
+    /// operand is parsed at.
+    fn infix_binding(&self, operator: &str) -> (i32, i32) {
+        match operator {
+            "==" | "!=" => (EQUALS, EQUALS),
+            "<" | ">" => (LESSGREATER, LESSGREATER),
+            ".." => (RANGE, RANGE),
+            // The parser groups `+` to the right.
+            "+" => (SUM, SUM - 1),
+            "-" => (SUM, SUM),
+            "*" | "/" => (PRODUCT, PRODUCT),
+            _ => match self.operators.get(operator) {
+                Some(&(prec, true)) => (prec, prec - 1),
+                Some(&(prec, false)) => (prec, prec),
+                None => (LOWEST, LOWEST),
+            },
+        }
+    }
+
+    /// Whether `expr`, parsed as an operand at precedence `rbp`, would stop
+    /// before its own operator and so needs parentheses.
+    fn loosens(&self, expr: &Expression, rbp: i32) -> bool {
+        match expr {
+            Expression::InfixExpression(x) => self.infix_binding(&x.operator).0 <= rbp,
+            Expression::AssignExpression(_) => ASSIGN <= rbp,
+            _ => false,
+        }
+    }
+
+    /// Whether the trailing operand of `expr` would swallow a following
+    /// operator of precedence `prec`, so `expr` needs parentheses to stay
+    /// the left operand.
+    fn binds_past(&self, expr: &Expression, prec: i32) -> bool {
+        let rbp = match expr {
+            Expression::InfixExpression(x) => self.infix_binding(&x.operator).1,
+            Expression::AssignExpression(_) => ASSIGN - 1,
+            Expression::PrefixExpression(_) => PREFIX,
+            _ => return false,
+        };
+        prec > rbp
+    }
+}
+
+/// Collects the operators a program declares with `infixl` and `infixr`.
+struct OperatorDecls<'a>(&'a mut HashMap<String, (i32, bool)>);
+
+impl Visitor for OperatorDecls<'_> {
+    fn visit_infix_statement(&mut self, stmt: &InfixStatement) {
+        let right = stmt.token.type_ == TokenType::INFIXR;
+        self.0
+            .insert(stmt.operator.clone(), (stmt.precedence, right));
+        visit::walk_infix_statement(self, stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Program {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        program
+    }
+
+    #[test]
+    fn format_test() {
+        let tests = vec![
+            ("let   x=5", "let x = 5;\n"),
+            ("a+b*c", "a + b * c;\n"),
+            ("(a+b)*c", "(a + b) * c;\n"),
+            ("a-(b-c)", "a - (b - c);\n"),
+            ("(a + b) - c", "(a + b) - c;\n"),
+            ("a + b - c", "a + b - c;\n"),
+            ("-(a + b)", "-(a + b);\n"),
+            ("(-a)[0]", "(-a)[0];\n"),
+            ("-(-a)", "-(-a);\n"),
+            ("f(1,2)[0]", "f(1, 2)[0];\n"),
+            ("x += 1 + 2", "x += 1 + 2;\n"),
+            ("1 .. 10", "1..10;\n"),
+            (
+                "let h = {\"a\":1, 2:[1,2]}",
+                "let h = {\"a\": 1, 2: [1, 2]};\n",
+            ),
+            ("\"sum ${ a+b }\"", "\"sum ${a + b}\";\n"),
+            (
+                "let f = fn(x,y){x+y}; f(1,2)",
+                "let f = fn(x, y) { x + y };\nf(1, 2);\n",
+            ),
+            (
+                "let f=fn(x:int,y)->[int]{[x]}",
+                "let f = fn(x: int, y) -> [int] { [x] };\n",
+            ),
+            (
+                "let h:{string:fn(int)->bool}={}",
+                "let h: {string: fn(int) -> bool} = {};\n",
+            ),
+            (
+                "if (x > 1) { return x } else { y }",
+                "if (x > 1) { return x; } else { y }\n",
+            ),
+            (
+                "let f = fn(x) { let y = x; y * 2 };",
+                "let f = fn(x) {\n    let y = x;\n    y * 2\n};\n",
+            ),
+            (
+                "while (i < 3) { i += 1; if (i == 2) { break } }",
+                "while (i < 3) {\n    i += 1;\n    if (i == 2) { break; }\n}\n",
+            ),
+            (
+                "for (x in [1, 2]) { puts(x) }",
+                "for (x in [1, 2]) { puts(x) }\n",
+            ),
+            (
+                "try { throw \"no\" } catch (e) { e }",
+                "try { throw \"no\"; } catch (e) { e }\n",
+            ),
+            (
+                "if (a) { 1 } else { 2 }; (b + 1) * 2",
+                "if (a) { 1 } else { 2 };\n(b + 1) * 2;\n",
+            ),
+            (
+                "if (a) { 1 } else { 2 }; -1",
+                "if (a) { 1 } else { 2 };\n-1;\n",
+            ),
+            ("if (a) { 1 }; b", "if (a) { 1 }\nb;\n"),
+            (
+                "infixr 6 <> = fn(a, b) { a + b }; 1 <> 2 <> 3; (1 <> 2) <> 3",
+                "infixr 6 <> = fn(a, b) { a + b };\n1 <> 2 <> 3;\n(1 <> 2) <> 3;\n",
+            ),
+            (
+                "let m = macro(a) { quote(unquote(a)) };",
+                "let m = macro(a) { quote(unquote(a)) };\n",
+            ),
+        ];
+        for (input, expected) in tests {
+            let formatted = format_source(input).unwrap();
+            assert_eq!(formatted, expected, "{}", input);
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", input);
+        }
+    }
+
+    #[test]
+    fn format_round_trip_test() {
+        let inputs = vec![
+            "let r = [a + (b + c), (a + b) + c, a - b - c, a * (b * c), (a * b) * c]",
+            "let r = [-a * b, -(a * b), !f(x), (!a)(b), a == (b == c), (a = b) + 1]",
+            "let x = fn(f) { fn(y) { f(y)(y) } }(1)[2]; x = y = 3",
+            "infixl 9 <+> = fn(a, b) { a }; let r = [-a <+> b, (-a) <+> b, a <+> b <+> c]",
+        ];
+        for input in inputs {
+            let formatted = format_source(input).unwrap();
+            assert!(
+                parse(&formatted) == parse(input),
+                "{} => {}",
+                input,
+                formatted
+            );
+        }
+    }
+
+    #[test]
+    fn format_comments_test() {
+        let input = "// header
+
+let x = 1; // one
+// about y
+
+
+let y = fn(a) { // opens
+    // inside
+    a
+    // before close
+};
+x // last
+// end
+";
+        let expected = "// header
+
+let x = 1; // one
+// about y
+
+let y = fn(a) { // opens
+    // inside
+    a
+    // before close
+};
+x; // last
+// end
+";
+        assert_eq!(format_source(input).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn print_expression_test() {
+        let program = parse("(1 + 2) * f(x)[0]");
+        let expr = match &program[0] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => panic!("expected expression statement"),
+        };
+        assert_eq!(print_expression(expr), "(1 + 2) * f(x)[0]");
+    }
+}
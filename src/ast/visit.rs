@@ -0,0 +1,362 @@
+use crate::ast::*;
+
+/// Read-only traversal of the tree. Implement `Visitor`, override the
+/// methods for the nodes a pass cares about, and call the matching `walk_`
+/// function from an override to keep descending into the node's children.
+pub trait Visitor {
+    fn visit_program(&mut self, prog: &Program) {
+        walk_program(self, prog)
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement) {
+        walk_statement(self, stmt)
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr)
+    }
+
+    fn visit_let_statement(&mut self, stmt: &LetStatement) {
+        walk_let_statement(self, stmt)
+    }
+
+    fn visit_return_statement(&mut self, stmt: &ReturnStatement) {
+        walk_return_statement(self, stmt)
+    }
+
+    fn visit_expression_statement(&mut self, stmt: &ExpressionStatement) {
+        walk_expression_statement(self, stmt)
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        walk_block_statement(self, block)
+    }
+
+    fn visit_while_statement(&mut self, stmt: &WhileStatement) {
+        walk_while_statement(self, stmt)
+    }
+
+    fn visit_break_statement(&mut self, _stmt: &BreakStatement) {}
+
+    fn visit_continue_statement(&mut self, _stmt: &ContinueStatement) {}
+
+    fn visit_for_statement(&mut self, stmt: &ForStatement) {
+        walk_for_statement(self, stmt)
+    }
+
+    fn visit_throw_statement(&mut self, stmt: &ThrowStatement) {
+        walk_throw_statement(self, stmt)
+    }
+
+    fn visit_infix_statement(&mut self, stmt: &InfixStatement) {
+        walk_infix_statement(self, stmt)
+    }
+
+    fn visit_identifier(&mut self, _ident: &Identifier) {}
+
+    fn visit_integer_literal(&mut self, _lit: &IntegerLiteral) {}
+
+    fn visit_boolean(&mut self, _lit: &Boolean) {}
+
+    fn visit_string_literal(&mut self, _lit: &StringLiteral) {}
+
+    fn visit_prefix_expression(&mut self, expr: &PrefixExpression) {
+        walk_prefix_expression(self, expr)
+    }
+
+    fn visit_infix_expression(&mut self, expr: &InfixExpression) {
+        walk_infix_expression(self, expr)
+    }
+
+    fn visit_if_expression(&mut self, expr: &IfExpression) {
+        walk_if_expression(self, expr)
+    }
+
+    fn visit_function_literal(&mut self, lit: &FunctionLiteral) {
+        walk_function_literal(self, lit)
+    }
+
+    fn visit_macro_literal(&mut self, lit: &MacroLiteral) {
+        walk_macro_literal(self, lit)
+    }
+
+    fn visit_call_expression(&mut self, expr: &CallExpression) {
+        walk_call_expression(self, expr)
+    }
+
+    fn visit_interpolated_string(&mut self, lit: &InterpolatedString) {
+        walk_interpolated_string(self, lit)
+    }
+
+    fn visit_array_literal(&mut self, lit: &ArrayLiteral) {
+        walk_array_literal(self, lit)
+    }
+
+    fn visit_hash_literal(&mut self, lit: &HashLiteral) {
+        walk_hash_literal(self, lit)
+    }
+
+    fn visit_index_expression(&mut self, expr: &IndexExpression) {
+        walk_index_expression(self, expr)
+    }
+
+    fn visit_assign_expression(&mut self, expr: &AssignExpression) {
+        walk_assign_expression(self, expr)
+    }
+
+    fn visit_try_expression(&mut self, expr: &TryExpression) {
+        walk_try_expression(self, expr)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(v: &mut V, prog: &Program) {
+    for stmt in prog {
+        v.visit_statement(stmt);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &Statement) {
+    match stmt {
+        Statement::LetStatement(x) => v.visit_let_statement(x),
+        Statement::ReturnStatement(x) => v.visit_return_statement(x),
+        Statement::ExpressionStatement(x) => v.visit_expression_statement(x),
+        Statement::BlockStatement(x) => v.visit_block_statement(x),
+        Statement::WhileStatement(x) => v.visit_while_statement(x),
+        Statement::BreakStatement(x) => v.visit_break_statement(x),
+        Statement::ContinueStatement(x) => v.visit_continue_statement(x),
+        Statement::ForStatement(x) => v.visit_for_statement(x),
+        Statement::ThrowStatement(x) => v.visit_throw_statement(x),
+        Statement::InfixStatement(x) => v.visit_infix_statement(x),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(v: &mut V, expr: &Expression) {
+    match expr {
+        Expression::Identifier(x) => v.visit_identifier(x),
+        Expression::IntegerLiteral(x) => v.visit_integer_literal(x),
+        Expression::PrefixExpression(x) => v.visit_prefix_expression(x),
+        Expression::InfixExpression(x) => v.visit_infix_expression(x),
+        Expression::Boolean(x) => v.visit_boolean(x),
+        Expression::IfExpression(x) => v.visit_if_expression(x),
+        Expression::FunctionLiteral(x) => v.visit_function_literal(x),
+        Expression::CallExpression(x) => v.visit_call_expression(x),
+        Expression::StringLiteral(x) => v.visit_string_literal(x),
+        Expression::InterpolatedString(x) => v.visit_interpolated_string(x),
+        Expression::ArrayLiteral(x) => v.visit_array_literal(x),
+        Expression::HashLiteral(x) => v.visit_hash_literal(x),
+        Expression::IndexExpression(x) => v.visit_index_expression(x),
+        Expression::AssignExpression(x) => v.visit_assign_expression(x),
+        Expression::TryExpression(x) => v.visit_try_expression(x),
+        Expression::MacroLiteral(x) => v.visit_macro_literal(x),
+    }
+}
+
+pub fn walk_let_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &LetStatement) {
+    if let Some(name) = &stmt.name {
+        v.visit_identifier(name);
+    }
+    if let Some(value) = &stmt.value {
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_return_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &ReturnStatement) {
+    if let Some(value) = &stmt.value {
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_expression_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &ExpressionStatement) {
+    if let Some(expr) = &stmt.expr {
+        v.visit_expression(expr);
+    }
+}
+
+pub fn walk_block_statement<V: Visitor + ?Sized>(v: &mut V, block: &BlockStatement) {
+    for stmt in &block.statements {
+        v.visit_statement(stmt);
+    }
+}
+
+pub fn walk_while_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &WhileStatement) {
+    if let Some(cond) = &stmt.cond {
+        v.visit_expression(cond);
+    }
+    if let Some(body) = &stmt.body {
+        v.visit_statement(body);
+    }
+}
+
+pub fn walk_for_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &ForStatement) {
+    if let Some(ident) = &stmt.ident {
+        v.visit_identifier(ident);
+    }
+    if let Some(iterable) = &stmt.iterable {
+        v.visit_expression(iterable);
+    }
+    if let Some(body) = &stmt.body {
+        v.visit_statement(body);
+    }
+}
+
+pub fn walk_throw_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &ThrowStatement) {
+    if let Some(value) = &stmt.value {
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_infix_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &InfixStatement) {
+    if let Some(value) = &stmt.value {
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_prefix_expression<V: Visitor + ?Sized>(v: &mut V, expr: &PrefixExpression) {
+    if let Some(right) = &expr.right {
+        v.visit_expression(right);
+    }
+}
+
+pub fn walk_infix_expression<V: Visitor + ?Sized>(v: &mut V, expr: &InfixExpression) {
+    if let Some(left) = &expr.left {
+        v.visit_expression(left);
+    }
+    if let Some(right) = &expr.right {
+        v.visit_expression(right);
+    }
+}
+
+pub fn walk_if_expression<V: Visitor + ?Sized>(v: &mut V, expr: &IfExpression) {
+    if let Some(cond) = &expr.cond {
+        v.visit_expression(cond);
+    }
+    if let Some(consequence) = &expr.consequence {
+        v.visit_statement(consequence);
+    }
+    if let Some(alternative) = &expr.alternative {
+        v.visit_statement(alternative);
+    }
+}
+
+pub fn walk_function_literal<V: Visitor + ?Sized>(v: &mut V, lit: &FunctionLiteral) {
+    for param in lit.parameters.as_deref().into_iter().flatten() {
+        v.visit_expression(param);
+    }
+    if let Some(body) = &lit.body {
+        v.visit_block_statement(body);
+    }
+}
+
+pub fn walk_macro_literal<V: Visitor + ?Sized>(v: &mut V, lit: &MacroLiteral) {
+    for param in lit.parameters.as_deref().into_iter().flatten() {
+        v.visit_expression(param);
+    }
+    if let Some(body) = &lit.body {
+        v.visit_block_statement(body);
+    }
+}
+
+pub fn walk_call_expression<V: Visitor + ?Sized>(v: &mut V, expr: &CallExpression) {
+    if let Some(func) = &expr.func {
+        v.visit_expression(func);
+    }
+    for arg in expr.args.iter().flatten() {
+        v.visit_expression(arg);
+    }
+}
+
+pub fn walk_interpolated_string<V: Visitor + ?Sized>(v: &mut V, lit: &InterpolatedString) {
+    for part in &lit.parts {
+        if let InterpolationPart::Expression(expr) = part {
+            v.visit_expression(expr);
+        }
+    }
+}
+
+pub fn walk_array_literal<V: Visitor + ?Sized>(v: &mut V, lit: &ArrayLiteral) {
+    for elem in &lit.elements {
+        v.visit_expression(elem);
+    }
+}
+
+pub fn walk_hash_literal<V: Visitor + ?Sized>(v: &mut V, lit: &HashLiteral) {
+    for (key, value) in &lit.pairs {
+        v.visit_expression(key);
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_index_expression<V: Visitor + ?Sized>(v: &mut V, expr: &IndexExpression) {
+    if let Some(left) = &expr.left {
+        v.visit_expression(left);
+    }
+    if let Some(index) = &expr.index {
+        v.visit_expression(index);
+    }
+}
+
+pub fn walk_assign_expression<V: Visitor + ?Sized>(v: &mut V, expr: &AssignExpression) {
+    if let Some(target) = &expr.target {
+        v.visit_expression(target);
+    }
+    if let Some(value) = &expr.value {
+        v.visit_expression(value);
+    }
+}
+
+pub fn walk_try_expression<V: Visitor + ?Sized>(v: &mut V, expr: &TryExpression) {
+    if let Some(body) = &expr.body {
+        v.visit_statement(body);
+    }
+    if let Some(ident) = &expr.ident {
+        v.visit_identifier(ident);
+    }
+    if let Some(handler) = &expr.handler {
+        v.visit_statement(handler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Collects identifiers in the order they are visited.
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_identifier(&mut self, ident: &Identifier) {
+            self.0.push(ident.value.clone());
+        }
+    }
+
+    /// Counts function literals without looking inside them.
+    struct Functions(usize);
+
+    impl Visitor for Functions {
+        fn visit_function_literal(&mut self, _lit: &FunctionLiteral) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn visit_test() {
+        let input = "let f = fn(a) { let g = fn(b) { a + b }; g(c) };
+            for (x in xs) { try { \"${y}\"; throw z; } catch (e) { {k: e}[k] } }";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let prog = par.parse_program();
+
+        let mut names = Names(vec![]);
+        names.visit_program(&prog);
+        assert_eq!(
+            names.0,
+            vec!["f", "a", "g", "b", "a", "b", "g", "c", "x", "xs", "y", "z", "e", "k", "e", "k"]
+        );
+
+        let mut functions = Functions(0);
+        functions.visit_program(&prog);
+        assert_eq!(functions.0, 1);
+    }
+}
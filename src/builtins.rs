@@ -0,0 +1,195 @@
+use crate::eval::call_function;
+use crate::object::{Environment, ErrorKind, Object, RuntimeError};
+use crate::token::Span;
+
+/// A function provided by the interpreter. A builtin is found only when no
+/// scope binds its name, so scripts can shadow it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Builtin {
+    /// `assert(cond)` fails unless `cond` is `true`.
+    Assert,
+    /// `assert_eq(left, right)` fails unless the values are equal, showing
+    /// both and where they first differ.
+    AssertEq,
+    /// `assert_error(f)` calls `f` with no arguments and fails unless the
+    /// call raises an error. Hitting a limit on the run does not count.
+    AssertError,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 3] = [Builtin::Assert, Builtin::AssertEq, Builtin::AssertError];
+
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Builtin::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Assert => "assert",
+            Builtin::AssertEq => "assert_eq",
+            Builtin::AssertError => "assert_error",
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Builtin::Assert | Builtin::AssertError => 1,
+            Builtin::AssertEq => 2,
+        }
+    }
+
+    pub fn call(&self, args: Vec<Object>, env: &mut Environment) -> Object {
+        if args.len() != self.arity() {
+            return error(
+                ErrorKind::Argument,
+                format!(
+                    "wrong number of arguments to {}: want={}, got={}",
+                    self.name(),
+                    self.arity(),
+                    args.len()
+                ),
+            );
+        }
+        match (self, args.as_slice()) {
+            (Builtin::Assert, [Object::Boolean(true)]) => Object::Null,
+            (Builtin::Assert, [other]) => error(
+                ErrorKind::Assertion,
+                format!("assertion failed: got {}", other.inspect()),
+            ),
+            (Builtin::AssertEq, [left, right]) if left == right => Object::Null,
+            (Builtin::AssertEq, [left, right]) => error(
+                ErrorKind::Assertion,
+                format!(
+                    "assert_eq failed\n{}",
+                    diff(&left.inspect(), &right.inspect())
+                ),
+            ),
+            (Builtin::AssertError, [Object::Function(f)]) if f.parameters.is_empty() => {
+                match call_function(Object::Function(f.clone()), vec![], Span::default(), env) {
+                    // Running out of budget is not the error being tested for.
+                    Object::Error(e) if e.kind.is_limit() => Object::Error(e),
+                    Object::Error(_) => Object::Null,
+                    other => error(
+                        ErrorKind::Assertion,
+                        format!("assert_error failed: no error, got {}", other.inspect()),
+                    ),
+                }
+            }
+            (Builtin::AssertError, [Object::Function(f)]) => error(
+                ErrorKind::Argument,
+                format!(
+                    "assert_error takes a function of no arguments, got one of {}",
+                    f.parameters.len()
+                ),
+            ),
+            (Builtin::AssertError, [other]) => error(
+                ErrorKind::Type,
+                format!("assert_error takes a function, got {}", other.type_()),
+            ),
+            _ => unreachable!("arity checked above"),
+        }
+    }
+}
+
+fn error(kind: ErrorKind, message: String) -> Object {
+    Object::Error(RuntimeError::new(kind, message))
+}
+
+/// Both sides of a failed comparison, with a caret under the first
+/// character where they differ.
+fn diff(left: &str, right: &str) -> String {
+    let same = left
+        .chars()
+        .zip(right.chars())
+        .take_while(|(l, r)| l == r)
+        .count();
+    format!(
+        "  left:  {}\n  right: {}\n         {}^",
+        left,
+        right,
+        " ".repeat(same)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_prog;
+    use crate::lexer::Lexer;
+    use crate::object::Limits;
+    use crate::parser::Parser;
+
+    fn eval(input: &str) -> Object {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        eval_prog(&program, &mut Environment::new())
+    }
+
+    #[test]
+    fn assert_test() {
+        let tests = vec![
+            ("assert(1 < 2)", None),
+            ("assert_eq([1, 2], [1, 2])", None),
+            ("assert_error(fn() { 1 / 0 })", None),
+            ("let f = fn(x) { assert(x) }; f(true)", None),
+            ("assert(1)", Some("AssertionError: assertion failed: got 1")),
+            (
+                "assert_eq([1, 2, 3], [1, 2, 4])",
+                Some(
+                    "AssertionError: assert_eq failed
+  left:  [1, 2, 3]
+  right: [1, 2, 4]
+                ^",
+                ),
+            ),
+            (
+                "assert_error(fn() { 1 })",
+                Some("AssertionError: assert_error failed: no error, got 1"),
+            ),
+            (
+                "assert_error(5)",
+                Some("TypeError: assert_error takes a function, got INTEGER_OBJ"),
+            ),
+            (
+                "assert_error(fn(x) { x })",
+                Some("ArgumentError: assert_error takes a function of no arguments, got one of 1"),
+            ),
+            (
+                "assert(true, 1)",
+                Some("ArgumentError: wrong number of arguments to assert: want=1, got=2"),
+            ),
+            ("let assert = fn(x) { x }; assert(5)", None),
+        ];
+        for (input, expected) in tests {
+            match (eval(input), expected) {
+                (Object::Error(e), Some(expected)) => {
+                    assert_eq!(e.to_string(), expected, "{}", input);
+                    assert!(e.span.is_known(), "{}", input);
+                }
+                (Object::Error(e), None) => panic!("{}: {}", input, e),
+                (other, Some(expected)) => {
+                    panic!("{}: got {}, want {}", input, other.inspect(), expected)
+                }
+                (_, None) => {}
+            }
+        }
+        assert_eq!(eval("assert").inspect(), "builtin assert");
+    }
+
+    #[test]
+    fn assert_error_limit_test() {
+        let mut lex = Lexer::init_lexer("assert_error(fn() { while (true) { } })");
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        let limits = Limits {
+            max_steps: Some(1000),
+            ..Limits::default()
+        };
+        match eval_prog(&program, &mut Environment::with_limits(limits)) {
+            Object::Error(e) => assert_eq!(e.kind, ErrorKind::StepLimit),
+            other => panic!("expected the step limit, got {}", other.inspect()),
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::eval::is_call_to;
+use crate::token::Span;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem `check` found, with where it is in the source.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.span.line, self.span.column, severity, self.message
+        )
+    }
+}
+
+/// Resolves every identifier in `program` against the scopes the evaluator
+/// would create, without running it. Reports undefined names as errors, and
+/// unused `let` bindings and parameters, shadowing and unreachable
+/// statements as warnings. Macros must already be expanded.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = resolve(program).diagnostics;
+    diagnostics.sort_by_key(|d| (d.span.line, d.span.column));
+    diagnostics
+}
+
+/// Every identifier in `program` that names a binding, with where that
+/// binding is made, in no particular order. A binding's own name refers to
+/// itself.
+pub fn references(program: &Program) -> Vec<Reference> {
+    resolve(program).references
+}
+
+fn resolve(program: &Program) -> Checker {
+    let mut checker = Checker {
+        scopes: vec![Scope {
+            parent: None,
+            bindings: vec![],
+        }],
+        current: 0,
+        deferred: VecDeque::new(),
+        diagnostics: vec![],
+        references: vec![],
+    };
+    checker.visit_program(program);
+    while let Some((parent, lit)) = checker.deferred.pop_front() {
+        checker.current = checker.push_scope(parent);
+        for param in lit.parameters.as_deref().into_iter().flatten() {
+            if let Expression::Identifier(x) = param {
+                checker.bind(x, BindingKind::Parameter);
+            }
+        }
+        if let Some(body) = &lit.body {
+            checker.visit_block_statement(body);
+        }
+    }
+    checker.report_unused();
+    checker
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BindingKind {
+    Variable,
+    Parameter,
+    /// A `for` loop variable or the name a `catch` binds.
+    Local,
+}
+
+/// An identifier at `span` naming the binding made at `definition`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reference {
+    pub span: Span,
+    pub name: String,
+    pub definition: Span,
+    pub kind: BindingKind,
+}
+
+struct Binding {
+    name: String,
+    span: Span,
+    kind: BindingKind,
+    used: bool,
+}
+
+struct Scope {
+    parent: Option<usize>,
+    bindings: Vec<Binding>,
+}
+
+struct Checker {
+    /// Every scope seen so far. Scopes outlive the walk over them, since a
+    /// function body is checked after the scope it closes over is complete.
+    scopes: Vec<Scope>,
+    current: usize,
+    /// Function bodies still to check, with the scope each closes over.
+    /// They run only when called, so they may use names bound later.
+    deferred: VecDeque<(usize, FunctionLiteral)>,
+    diagnostics: Vec<Diagnostic>,
+    references: Vec<Reference>,
+}
+
+impl Checker {
+    fn push_scope(&mut self, parent: usize) -> usize {
+        self.scopes.push(Scope {
+            parent: Some(parent),
+            bindings: vec![],
+        });
+        self.scopes.len() - 1
+    }
+
+    /// Runs `f` in a new scope nested in the current one.
+    fn scoped(&mut self, f: impl FnOnce(&mut Checker)) {
+        let outer = self.current;
+        self.current = self.push_scope(outer);
+        f(self);
+        self.current = outer;
+    }
+
+    fn warn(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message,
+        });
+    }
+
+    /// The latest binding of `name` visible from the current scope.
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        let mut scope = Some(self.current);
+        while let Some(id) = scope {
+            if self.scopes[id].bindings.iter().any(|b| b.name == name) {
+                return self.scopes[id]
+                    .bindings
+                    .iter_mut()
+                    .rev()
+                    .find(|b| b.name == name);
+            }
+            scope = self.scopes[id].parent;
+        }
+        None
+    }
+
+    fn bind(&mut self, ident: &Identifier, kind: BindingKind) {
+        let name = &ident.value;
+        if let Some(earlier) = self.lookup(name).map(|b| b.span) {
+            self.warn(
+                ident.token.span,
+                format!("{} shadows the binding at {}", name, earlier),
+            );
+        }
+        self.references.push(Reference {
+            span: ident.token.span,
+            name: name.clone(),
+            definition: ident.token.span,
+            kind,
+        });
+        self.scopes[self.current].bindings.push(Binding {
+            name: name.clone(),
+            span: ident.token.span,
+            kind,
+            used: false,
+        });
+    }
+
+    fn resolve(&mut self, ident: &Identifier, read: bool) {
+        match self.lookup(&ident.value) {
+            Some(binding) => {
+                binding.used |= read;
+                let reference = Reference {
+                    span: ident.token.span,
+                    name: ident.value.clone(),
+                    definition: binding.span,
+                    kind: binding.kind,
+                };
+                self.references.push(reference);
+            }
+            None if Builtin::lookup(&ident.value).is_some() => {}
+            None => self.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                span: ident.token.span,
+                message: format!("undefined variable {}", ident.value),
+            }),
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        let mut exited = false;
+        for stmt in statements {
+            if exited {
+                self.warn(stmt.token().span, "unreachable statement".to_string());
+                exited = false;
+            }
+            self.visit_statement(stmt);
+            exited |= matches!(
+                stmt,
+                Statement::ReturnStatement(_)
+                    | Statement::ThrowStatement(_)
+                    | Statement::BreakStatement(_)
+                    | Statement::ContinueStatement(_)
+            );
+        }
+    }
+
+    fn report_unused(&mut self) {
+        let unused: Vec<Diagnostic> = self
+            .scopes
+            .iter()
+            .flat_map(|s| &s.bindings)
+            .filter(|b| !b.used)
+            .filter_map(|b| {
+                let what = match b.kind {
+                    BindingKind::Variable => "variable",
+                    BindingKind::Parameter => "parameter",
+                    BindingKind::Local => return None,
+                };
+                Some(Diagnostic {
+                    severity: Severity::Warning,
+                    span: b.span,
+                    message: format!("unused {} {}", what, b.name),
+                })
+            })
+            .collect();
+        self.diagnostics.extend(unused);
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_program(&mut self, prog: &Program) {
+        self.statements(prog);
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.statements(&block.statements);
+    }
+
+    fn visit_let_statement(&mut self, stmt: &LetStatement) {
+        if let Some(value) = &stmt.value {
+            self.visit_expression(value);
+        }
+        if let Some(name) = &stmt.name {
+            self.bind(name, BindingKind::Variable);
+        }
+    }
+
+    fn visit_for_statement(&mut self, stmt: &ForStatement) {
+        if let Some(iterable) = &stmt.iterable {
+            self.visit_expression(iterable);
+        }
+        self.scoped(|c| {
+            if let Some(ident) = &stmt.ident {
+                c.bind(ident, BindingKind::Local);
+            }
+            if let Some(body) = &stmt.body {
+                c.visit_statement(body);
+            }
+        });
+    }
+
+    fn visit_try_expression(&mut self, expr: &TryExpression) {
+        if let Some(body) = &expr.body {
+            self.visit_statement(body);
+        }
+        self.scoped(|c| {
+            if let Some(ident) = &expr.ident {
+                c.bind(ident, BindingKind::Local);
+            }
+            if let Some(handler) = &expr.handler {
+                c.visit_statement(handler);
+            }
+        });
+    }
+
+    fn visit_function_literal(&mut self, lit: &FunctionLiteral) {
+        self.deferred.push_back((self.current, lit.clone()));
+    }
+
+    fn visit_identifier(&mut self, ident: &Identifier) {
+        self.resolve(ident, true);
+    }
+
+    fn visit_assign_expression(&mut self, expr: &AssignExpression) {
+        match expr.target.as_deref() {
+            // A plain `=` only writes; `+=` and the like read the old value.
+            Some(Expression::Identifier(x)) => self.resolve(x, expr.operator != "="),
+            Some(target) => self.visit_expression(target),
+            None => (),
+        }
+        if let Some(value) = &expr.value {
+            self.visit_expression(value);
+        }
+    }
+
+    fn visit_call_expression(&mut self, expr: &CallExpression) {
+        if !is_call_to(expr, "quote") {
+            return visit::walk_call_expression(self, expr);
+        }
+        // Quoted code is not evaluated, apart from what it unquotes.
+        let mut unquoted = Unquoted(vec![]);
+        for arg in expr.args.iter().flatten() {
+            unquoted.visit_expression(arg);
+        }
+        for arg in &unquoted.0 {
+            self.visit_expression(arg);
+        }
+    }
+}
+
+/// Collects the arguments of the `unquote` calls in quoted code.
+struct Unquoted(Vec<Expression>);
+
+impl Visitor for Unquoted {
+    fn visit_call_expression(&mut self, expr: &CallExpression) {
+        match is_call_to(expr, "unquote") {
+            true => self.0.extend(expr.args.iter().flatten().cloned()),
+            false => visit::walk_call_expression(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Vec<String> {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        check(&program).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn check_test() {
+        let tests: Vec<(&str, Vec<&str>)> = vec![
+            ("let x = 1; x", vec![]),
+            ("y + 1", vec!["1:1: error: undefined variable y"]),
+            ("assert_eq(1, 1)", vec![]),
+            ("let x = 1;", vec!["1:5: warning: unused variable x"]),
+            (
+                "let f = fn(a, b) { a }; f(1, 2)",
+                vec!["1:15: warning: unused parameter b"],
+            ),
+            (
+                "let x = 1; let f = fn(x) { x }; f(x)",
+                vec!["1:23: warning: x shadows the binding at line 1, column 5"],
+            ),
+            (
+                "let f = fn() { return 1; 2 }; f()",
+                vec!["1:26: warning: unreachable statement"],
+            ),
+            ("let f = fn() { g() }; let g = fn() { 1 }; f()", vec![]),
+            ("x; let x = 1; x", vec!["1:1: error: undefined variable x"]),
+            (
+                "let x = x + 1;",
+                vec![
+                    "1:5: warning: unused variable x",
+                    "1:9: error: undefined variable x",
+                ],
+            ),
+            (
+                "for (i in 0..3) { i }; i",
+                vec!["1:24: error: undefined variable i"],
+            ),
+            (
+                "for (i in 0..3) { let j = 1; }",
+                vec!["1:23: warning: unused variable j"],
+            ),
+            (
+                "try { 1 } catch (e) { e }; e",
+                vec!["1:28: error: undefined variable e"],
+            ),
+            ("let n = 0; n = 1;", vec!["1:5: warning: unused variable n"]),
+            ("let n = 0; n += 1;", vec![]),
+            ("m = 1", vec!["1:1: error: undefined variable m"]),
+            ("let a = [1]; a[0] = 2;", vec![]),
+            (
+                "while (true) { break; 1 }",
+                vec!["1:23: warning: unreachable statement"],
+            ),
+            ("let x = 1; quote(y + unquote(x))", vec![]),
+            (
+                "quote(unquote(z))",
+                vec!["1:15: error: undefined variable z"],
+            ),
+            (
+                "let x = 1; let x = 2; x",
+                vec![
+                    "1:5: warning: unused variable x",
+                    "1:16: warning: x shadows the binding at line 1, column 5",
+                ],
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(run(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn references_test() {
+        let input = "let x = 1; let f = fn(a) { a + x }; f(x)";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        let mut refs: Vec<(usize, usize, &str, BindingKind)> = vec![];
+        let all = references(&program);
+        for r in &all {
+            refs.push((r.span.column, r.definition.column, &r.name, r.kind));
+        }
+        refs.sort_by_key(|r| (r.0, r.1));
+        assert_eq!(
+            refs,
+            vec![
+                (5, 5, "x", BindingKind::Variable),
+                (16, 16, "f", BindingKind::Variable),
+                (23, 23, "a", BindingKind::Parameter),
+                (28, 23, "a", BindingKind::Parameter),
+                (32, 5, "x", BindingKind::Variable),
+                (37, 16, "f", BindingKind::Variable),
+                (39, 5, "x", BindingKind::Variable),
+            ]
+        );
+    }
+}
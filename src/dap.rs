@@ -0,0 +1,595 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::ast::{Program, Statement};
+use crate::debugger::{describe, Stepper};
+use crate::eval::{eval_prog, EvalHook};
+use crate::json::Json;
+use crate::lexer::Lexer;
+use crate::lsp::{number_at, object, read_message, str_at, string, write_message};
+use crate::macros::{define_macros, expand_macros, expansion_limits};
+use crate::object::{Environment, Function, Limits, Object};
+use crate::parser::Parser;
+use crate::token::Span;
+
+/// Serves the Debug Adapter Protocol on `input` and `output` until the
+/// client disconnects or closes the stream. The program named by `launch`
+/// runs once the client sends `configurationDone`, and each pause reads
+/// requests in the evaluator's hook until one resumes it. A client that
+/// disconnects mid-run leaves the program to finish without pausing. The
+/// program runs within `limits`, and so do its macros, with a bound on
+/// their steps and time as well.
+pub fn serve<R: BufRead + 'static, W: Write + 'static>(
+    input: R,
+    output: W,
+    limits: Limits,
+) -> io::Result<()> {
+    let adapter = Rc::new(RefCell::new(Adapter::new(input, output, limits)));
+    loop {
+        match adapter.borrow_mut().next_request()? {
+            None | Some(Next::Disconnect) => return Ok(()),
+            Some(_) => {}
+        }
+        let program = adapter.borrow_mut().ready();
+        if let Some(program) = program {
+            let mut env = Environment::with_limits(limits);
+            env.set_hook(adapter.clone());
+            let result = eval_prog(&program, &mut env);
+            let mut adapter = adapter.borrow_mut();
+            adapter.finish(result)?;
+            if adapter.disconnected {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// What the client asked for, as far as the running program is concerned.
+enum Next {
+    Wait,
+    Resume,
+    Disconnect,
+}
+
+/// A call being run. The top level is the first frame. `span` and `env`
+/// are those of the statement it is running, unknown until the first one.
+struct Frame {
+    name: String,
+    span: Span,
+    env: Option<Environment>,
+}
+
+struct Adapter<R, W> {
+    input: R,
+    output: W,
+    /// Sequence number of the last message sent.
+    seq: i64,
+    stepper: Stepper,
+    path: String,
+    program: Option<Program>,
+    configured: bool,
+    /// Why the next pause happens, unless it is at a breakpoint.
+    reason: &'static str,
+    frames: Vec<Frame>,
+    /// Bindings of the scopes handed out since the last pause, indexed by
+    /// `variablesReference` less one.
+    variables: Vec<Vec<(String, Object)>>,
+    disconnected: bool,
+    /// An I/O error met inside the hook, which cannot return it.
+    failed: Option<io::Error>,
+    limits: Limits,
+}
+
+impl<R: BufRead, W: Write> Adapter<R, W> {
+    fn new(input: R, output: W, limits: Limits) -> Self {
+        Adapter {
+            input,
+            output,
+            seq: 0,
+            stepper: Stepper::default(),
+            path: String::new(),
+            program: None,
+            configured: false,
+            reason: "step",
+            frames: vec![],
+            variables: vec![],
+            disconnected: false,
+            failed: None,
+            limits,
+        }
+    }
+
+    /// Reads and answers one request. Returns `None` at the end of input.
+    fn next_request(&mut self) -> io::Result<Option<Next>> {
+        let body = match read_message(&mut self.input)? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        match Json::parse(&body) {
+            Ok(message) => self.request(&message).map(Some),
+            Err(e) => {
+                self.send_event("output", object(vec![("output", Json::String(e))]))?;
+                Ok(Some(Next::Wait))
+            }
+        }
+    }
+
+    fn request(&mut self, message: &Json) -> io::Result<Next> {
+        let command = str_at(message, &["command"]).unwrap_or("").to_string();
+        let args = message.get("arguments").unwrap_or(&Json::Null);
+        let mut next = Next::Wait;
+        let reply = match command.as_str() {
+            "initialize" => Ok(object(vec![(
+                "supportsConfigurationDoneRequest",
+                Json::Bool(true),
+            )])),
+            "launch" => self.launch(args).map(|_| Json::Null),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(object(vec![(
+                "threads",
+                Json::Array(vec![object(vec![
+                    ("id", Json::Number(1)),
+                    ("name", string("main")),
+                ])]),
+            )])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                match command.as_str() {
+                    "continue" => self.stepper.resume(),
+                    "next" => self.stepper.step_over(),
+                    "stepIn" => self.stepper.step_into(),
+                    _ => self.stepper.step_out(),
+                }
+                self.reason = "step";
+                next = Next::Resume;
+                Ok(object(vec![("allThreadsContinued", Json::Bool(true))]))
+            }
+            "disconnect" => {
+                next = Next::Disconnect;
+                Ok(Json::Null)
+            }
+            other => Err(format!("unsupported request {}", other)),
+        };
+        let request_seq = message.get("seq").cloned().unwrap_or(Json::Null);
+        let mut response = vec![
+            ("type", string("response")),
+            ("request_seq", request_seq),
+            ("success", Json::Bool(reply.is_ok())),
+            ("command", Json::String(command.clone())),
+        ];
+        match reply {
+            Ok(Json::Null) => {}
+            Ok(body) => response.push(("body", body)),
+            Err(message) => response.push(("message", Json::String(message))),
+        }
+        self.send(response)?;
+        if command == "initialize" {
+            self.send_event("initialized", Json::Null)?;
+        }
+        Ok(next)
+    }
+
+    /// Reads, parses and macro-expands the program at `arguments.program`.
+    fn launch(&mut self, args: &Json) -> Result<(), String> {
+        let path = str_at(args, &["program"]).ok_or("launch needs a program")?;
+        let input = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lexer = Lexer::init_lexer(&input);
+        let mut parser = Parser::new(&mut lexer);
+        let mut program = parser.parse_program();
+        if let Some(err) = parser.errors().first() {
+            return Err(format!("{}: {}", path, err));
+        }
+        let mut macro_env = Environment::with_limits(expansion_limits(self.limits));
+        define_macros(&mut program, &mut macro_env);
+        let program = expand_macros(program, &mut macro_env)
+            .map_err(|e| format!("{}:{}:{}: {}", path, e.span.line, e.span.column, e.message))?;
+        if let Some(Json::Bool(true)) = args.get("stopOnEntry") {
+            self.stepper.step_into();
+            self.reason = "entry";
+        }
+        self.path = path.to_string();
+        self.program = Some(program);
+        Ok(())
+    }
+
+    /// Takes the launched program once the client has finished configuring.
+    fn ready(&mut self) -> Option<Program> {
+        match self.configured {
+            true => self.program.take(),
+            false => None,
+        }
+    }
+
+    /// Replaces the breakpoints with those in `arguments.breakpoints`.
+    /// There is one source, so the path is not consulted.
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let lines: Vec<usize> = match args.get("breakpoints") {
+            Some(Json::Array(breakpoints)) => breakpoints
+                .iter()
+                .filter_map(|b| number_at(b, "line"))
+                .collect(),
+            _ => vec![],
+        };
+        self.stepper.breakpoints = lines.iter().copied().collect();
+        let verified = lines
+            .iter()
+            .map(|line| {
+                object(vec![
+                    ("verified", Json::Bool(true)),
+                    ("line", Json::Number(*line as i64)),
+                ])
+            })
+            .collect();
+        object(vec![("breakpoints", Json::Array(verified))])
+    }
+
+    fn stack_trace(&self) -> Json {
+        let frames: Vec<Json> = self
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, frame)| {
+                object(vec![
+                    ("id", Json::Number(i as i64 + 1)),
+                    ("name", Json::String(frame.name.clone())),
+                    ("source", object(vec![("path", string(&self.path))])),
+                    ("line", Json::Number(frame.span.line as i64)),
+                    ("column", Json::Number(frame.span.column as i64)),
+                ])
+            })
+            .collect();
+        object(vec![
+            ("totalFrames", Json::Number(frames.len() as i64)),
+            ("stackFrames", Json::Array(frames)),
+        ])
+    }
+
+    /// The scope chain of a frame, innermost first, ending in the globals.
+    fn scopes(&mut self, args: &Json) -> Result<Json, String> {
+        let env = number_at(args, "frameId")
+            .and_then(|id| self.frames.get(id.checked_sub(1)?))
+            .and_then(|frame| frame.env.clone())
+            .ok_or("unknown frame")?;
+        let chain = env.scopes();
+        let last = chain.len() - 1;
+        let mut scopes = vec![];
+        for (i, bindings) in chain.into_iter().enumerate() {
+            self.variables.push(bindings);
+            let name = match i {
+                _ if i == last => "Globals",
+                0 => "Locals",
+                _ => "Closure",
+            };
+            scopes.push(object(vec![
+                ("name", string(name)),
+                (
+                    "variablesReference",
+                    Json::Number(self.variables.len() as i64),
+                ),
+                ("expensive", Json::Bool(false)),
+            ]));
+        }
+        Ok(object(vec![("scopes", Json::Array(scopes))]))
+    }
+
+    fn variables(&self, args: &Json) -> Result<Json, String> {
+        let bindings = number_at(args, "variablesReference")
+            .and_then(|id| self.variables.get(id.checked_sub(1)?))
+            .ok_or("unknown variables reference")?;
+        let variables = bindings
+            .iter()
+            .map(|(name, value)| {
+                object(vec![
+                    ("name", Json::String(name.clone())),
+                    ("value", Json::String(describe(value))),
+                    ("variablesReference", Json::Number(0)),
+                ])
+            })
+            .collect();
+        Ok(object(vec![("variables", Json::Array(variables))]))
+    }
+
+    /// Tells the client the program stopped and answers requests until one
+    /// resumes it.
+    fn pause(&mut self, span: &Span) -> io::Result<()> {
+        let reason = match self.stepper.breakpoints.contains(&span.line) {
+            true => "breakpoint",
+            false => self.reason,
+        };
+        self.variables.clear();
+        self.send_event(
+            "stopped",
+            object(vec![
+                ("reason", string(reason)),
+                ("threadId", Json::Number(1)),
+                ("allThreadsStopped", Json::Bool(true)),
+            ]),
+        )?;
+        loop {
+            match self.next_request()? {
+                Some(Next::Wait) => {}
+                Some(Next::Resume) => return Ok(()),
+                None | Some(Next::Disconnect) => {
+                    self.disconnect();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.disconnected = true;
+        self.stepper.breakpoints.clear();
+        self.stepper.resume();
+    }
+
+    /// Reports the program's result and that it has ended.
+    fn finish(&mut self, result: Object) -> io::Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        if self.disconnected {
+            return Ok(());
+        }
+        let (category, output, code) = match result {
+            Object::Error(e) => ("stderr", Some(e.traceback()), 1),
+            Object::Null => ("console", None, 0),
+            other => ("console", Some(other.inspect()), 0),
+        };
+        if let Some(output) = output {
+            self.send_event(
+                "output",
+                object(vec![
+                    ("category", string(category)),
+                    ("output", Json::String(output + "\n")),
+                ]),
+            )?;
+        }
+        self.send_event("terminated", Json::Null)?;
+        self.send_event("exited", object(vec![("exitCode", Json::Number(code))]))
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let mut message = vec![("type", string("event")), ("event", string(event))];
+        if body != Json::Null {
+            message.push(("body", body));
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, fields: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        let mut message = vec![("seq", Json::Number(self.seq))];
+        message.extend(fields);
+        write_message(&mut self.output, &object(message))
+    }
+}
+
+impl<R: BufRead, W: Write> EvalHook for Adapter<R, W> {
+    fn before_statement(&mut self, stmt: &Statement, env: &Environment) {
+        if let Statement::BlockStatement(_) = stmt {
+            return;
+        }
+        let span = stmt.token().span;
+        if self.disconnected || !span.is_known() {
+            return;
+        }
+        if self.frames.is_empty() {
+            self.frames.push(Frame {
+                name: "<top level>".to_string(),
+                span,
+                env: None,
+            });
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.span = span;
+            frame.env = Some(env.clone());
+        }
+        if self.stepper.pause_at(span) {
+            if let Err(e) = self.pause(&span) {
+                self.failed = Some(e);
+                self.disconnect();
+            }
+        }
+    }
+
+    fn enter_function(&mut self, function: &Function) {
+        let name = function.name.as_deref().unwrap_or("<anonymous>");
+        self.frames.push(Frame {
+            name: name.to_string(),
+            span: Span::default(),
+            env: None,
+        });
+        self.stepper.enter();
+    }
+
+    fn exit_function(&mut self, _function: &Function) {
+        self.frames.pop();
+        self.stepper.exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer the test can read back after the server has taken it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs a scripted session and returns what the server sent back.
+    fn session(requests: &[String]) -> Vec<Json> {
+        let input: String = requests
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let message = format!(r#"{{"seq":{},"type":"request",{}}}"#, i + 1, r);
+                format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+            })
+            .collect();
+        let output = Shared::default();
+        serve(
+            io::Cursor::new(input.into_bytes()),
+            output.clone(),
+            Limits::default(),
+        )
+        .unwrap();
+        let bytes = output.0.borrow().clone();
+        let mut reader = bytes.as_slice();
+        let mut replies = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            replies.push(Json::parse(&body).unwrap());
+        }
+        replies
+    }
+
+    fn request(command: &str, arguments: &str) -> String {
+        format!(r#""command":"{}","arguments":{}"#, command, arguments)
+    }
+
+    /// Each reply in brief: `command` or `command!` for a failed response,
+    /// `event:name` for events.
+    fn kinds(replies: &[Json]) -> Vec<String> {
+        replies
+            .iter()
+            .map(|r| match str_at(r, &["type"]) {
+                Some("event") => format!("event:{}", str_at(r, &["event"]).unwrap()),
+                _ => match r.get("success") {
+                    Some(Json::Bool(true)) => str_at(r, &["command"]).unwrap().to_string(),
+                    _ => format!("{}!", str_at(r, &["command"]).unwrap()),
+                },
+            })
+            .collect()
+    }
+
+    fn body<'a>(replies: &'a [Json], command: &str) -> Vec<&'a Json> {
+        replies
+            .iter()
+            .filter(|r| str_at(r, &["command"]) == Some(command))
+            .filter_map(|r| r.get("body"))
+            .collect()
+    }
+
+    #[test]
+    fn dap_session_test() {
+        let path = std::env::temp_dir().join(format!("dap_test_{}.mk", std::process::id()));
+        let source = "let double = fn(x) {\n  let y = x * 2;\n  y\n};\nlet a = double(1);\na + 1";
+        std::fs::write(&path, source).unwrap();
+        let launch = format!(r#"{{"program":"{}"}}"#, path.display());
+        let replies = session(&[
+            request("initialize", r#"{"adapterID":"monkey"}"#),
+            request("launch", &launch),
+            request("setBreakpoints", r#"{"breakpoints":[{"line":2}]}"#),
+            request("configurationDone", "{}"),
+            request("threads", "{}"),
+            request("stackTrace", r#"{"threadId":1}"#),
+            request("scopes", r#"{"frameId":2}"#),
+            request("variables", r#"{"variablesReference":1}"#),
+            request("variables", r#"{"variablesReference":2}"#),
+            request("stepOut", r#"{"threadId":1}"#),
+            request("evaluate", r#"{"expression":"a"}"#),
+            request("continue", r#"{"threadId":1}"#),
+            request("disconnect", "{}"),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            kinds(&replies),
+            vec![
+                "initialize",
+                "event:initialized",
+                "launch",
+                "setBreakpoints",
+                "configurationDone",
+                "event:stopped",
+                "threads",
+                "stackTrace",
+                "scopes",
+                "variables",
+                "variables",
+                "stepOut",
+                "event:stopped",
+                "evaluate!",
+                "continue",
+                "event:output",
+                "event:terminated",
+                "event:exited",
+                "disconnect",
+            ]
+        );
+        let frames: Vec<String> = match body(&replies, "stackTrace")[0].get("stackFrames") {
+            Some(Json::Array(frames)) => frames
+                .iter()
+                .map(|f| {
+                    let line = number_at(f, "line").unwrap();
+                    format!("{}:{}", str_at(f, &["name"]).unwrap(), line)
+                })
+                .collect(),
+            _ => panic!("no stack frames"),
+        };
+        assert_eq!(frames, vec!["double:2", "<top level>:5"]);
+        let variables: Vec<String> = body(&replies, "variables")
+            .iter()
+            .map(|b| match b.get("variables") {
+                Some(Json::Array(vars)) => vars
+                    .iter()
+                    .map(|v| {
+                        format!(
+                            "{}={}",
+                            str_at(v, &["name"]).unwrap(),
+                            str_at(v, &["value"]).unwrap()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                _ => panic!("no variables"),
+            })
+            .collect();
+        assert_eq!(variables, vec!["x=1", "double=fn(x)"]);
+        let stops: Vec<&str> = replies
+            .iter()
+            .filter(|r| str_at(r, &["event"]) == Some("stopped"))
+            .map(|r| str_at(r, &["body", "reason"]).unwrap())
+            .collect();
+        assert_eq!(stops, vec!["breakpoint", "step"]);
+        let output = replies.iter().find_map(|r| str_at(r, &["body", "output"]));
+        assert_eq!(output, Some("3\n"));
+    }
+
+    #[test]
+    fn launch_error_test() {
+        let replies = session(&[
+            request("launch", r#"{"program":"/nonexistent/a.mk"}"#),
+            request("configurationDone", "{}"),
+            request("disconnect", "{}"),
+        ]);
+        assert_eq!(
+            kinds(&replies),
+            vec!["launch!", "configurationDone", "disconnect"]
+        );
+
+        let path = std::env::temp_dir().join(format!("dap_spin_{}.mk", std::process::id()));
+        std::fs::write(&path, "let m = macro() { while (true) { } };\nm();").unwrap();
+        let launch = format!(r#"{{"program":"{}"}}"#, path.display());
+        let replies = session(&[request("launch", &launch), request("disconnect", "{}")]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(kinds(&replies), vec!["launch!", "disconnect"]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use crate::ast::Statement;
+use crate::eval::EvalHook;
+use crate::object::{Environment, Function, Object};
+use crate::token::Span;
+
+const HELP: &str = "\
+break N, b N     pause before statements on line N
+delete N, d N    remove the breakpoint on line N
+step, s          run to the next statement, entering calls
+next, n          run to the next statement in this call or its caller
+out, o           run until the current call returns
+continue, c      run to the next breakpoint
+env, e           print the bindings of each scope, innermost first
+print X, p X     print the value of X
+backtrace, bt    print the calls being run, innermost first";
+
+/// How far to run before pausing again. The depths count calls being run.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum Mode {
+    #[default]
+    Continue,
+    StepInto,
+    /// Pause at a statement no more than this many calls deep.
+    StepOver(usize),
+    /// Pause at a statement fewer than this many calls deep.
+    StepOut(usize),
+}
+
+/// Decides where a debugger pauses: where a step ends, and on breakpoint
+/// lines once per visit, rather than before every statement on the line.
+#[derive(Default)]
+pub struct Stepper {
+    pub breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// Calls being run.
+    depth: usize,
+    /// The statement last run, to tell moving along a line from coming
+    /// back to it, as a loop or a call does.
+    last: Span,
+}
+
+impl Stepper {
+    /// Pauses at the next statement, in any call.
+    pub fn step_into(&mut self) {
+        self.mode = Mode::StepInto;
+    }
+
+    /// Pauses at the next statement in the current call or its callers.
+    pub fn step_over(&mut self) {
+        self.mode = Mode::StepOver(self.depth);
+    }
+
+    /// Pauses at the next statement once the current call returns.
+    pub fn step_out(&mut self) {
+        self.mode = Mode::StepOut(self.depth);
+    }
+
+    /// Pauses only at breakpoints.
+    pub fn resume(&mut self) {
+        self.mode = Mode::Continue;
+    }
+
+    pub fn enter(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// Whether to pause before the statement at `span`, which is about to
+    /// run.
+    pub fn pause_at(&mut self, span: Span) -> bool {
+        let stepped = match self.mode {
+            Mode::Continue => false,
+            Mode::StepInto => true,
+            Mode::StepOver(d) => self.depth <= d,
+            Mode::StepOut(d) => self.depth < d,
+        };
+        // Later statements on the same line are part of the same visit;
+        // one that starts no further along begins a new one.
+        let revisited = span.line == self.last.line && span.start > self.last.start;
+        self.last = span;
+        stepped || (self.breakpoints.contains(&span.line) && !revisited)
+    }
+}
+
+/// A step debugger for the evaluator. It pauses before statements on a
+/// breakpoint line or where a step ends, and reads commands with `input`
+/// until one resumes the program.
+pub struct Debugger<R, W> {
+    input: R,
+    output: W,
+    /// Source text by line, shown when pausing.
+    source: BTreeMap<usize, String>,
+    stepper: Stepper,
+    /// Names of the functions being run, outermost first.
+    stack: Vec<String>,
+}
+
+impl<R: FnMut() -> Option<String>, W: Write> Debugger<R, W> {
+    /// `input` gives the next command line, or `None` at the end of input,
+    /// which lets the program run to completion.
+    pub fn new(input: R, output: W) -> Self {
+        Debugger {
+            input,
+            output,
+            source: BTreeMap::new(),
+            stepper: Stepper::default(),
+            stack: vec![],
+        }
+    }
+
+    /// Records the text of `line` to show when pausing there.
+    pub fn add_source(&mut self, line: usize, text: &str) {
+        self.source.insert(line, text.trim_end().to_string());
+    }
+
+    /// Drops any step still in progress, as when a run ends, so the next
+    /// run pauses only at breakpoints.
+    pub fn resume(&mut self) {
+        self.stepper.resume();
+    }
+
+    /// Runs one command. Returns whether it resumes the program.
+    pub fn command(&mut self, line: &str, env: &Environment) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["step" | "s"] => self.stepper.step_into(),
+            ["next" | "n"] => self.stepper.step_over(),
+            ["out" | "o"] => self.stepper.step_out(),
+            ["continue" | "c"] => self.stepper.resume(),
+            ["break" | "b", n] | ["delete" | "d", n] => {
+                match n.parse::<usize>() {
+                    Ok(n) if words[0].starts_with('b') => {
+                        self.stepper.breakpoints.insert(n);
+                        self.say(format!("breakpoint at line {}", n));
+                    }
+                    Ok(n) if self.stepper.breakpoints.remove(&n) => {
+                        self.say(format!("deleted breakpoint at line {}", n))
+                    }
+                    Ok(n) => self.say(format!("no breakpoint at line {}", n)),
+                    Err(_) => self.say(format!("not a line number: {}", n)),
+                }
+                return false;
+            }
+            ["env" | "e"] => {
+                for (i, scope) in env.scopes().iter().enumerate() {
+                    let bindings: Vec<String> = scope
+                        .iter()
+                        .map(|(name, value)| format!("{} = {}", name, describe(value)))
+                        .collect();
+                    match bindings.is_empty() {
+                        true => self.say(format!("scope {}: (empty)", i)),
+                        false => self.say(format!("scope {}: {}", i, bindings.join(", "))),
+                    }
+                }
+                return false;
+            }
+            ["print" | "p", name] => {
+                match env.get(name) {
+                    Some(value) => self.say(format!("{} = {}", name, describe(&value))),
+                    None => self.say(format!("{} is not defined", name)),
+                }
+                return false;
+            }
+            ["backtrace" | "bt"] => {
+                let frames: Vec<String> = self.stack.iter().rev().cloned().collect();
+                for (i, name) in frames.iter().enumerate() {
+                    self.say(format!("#{} {}", i, name));
+                }
+                self.say(format!("#{} <top level>", frames.len()));
+                return false;
+            }
+            ["help" | "h"] => {
+                self.say(HELP.to_string());
+                return false;
+            }
+            [] => return false,
+            _ => {
+                self.say(format!("unknown command: {} (try help)", line.trim()));
+                return false;
+            }
+        }
+        true
+    }
+
+    fn pause(&mut self, span: &Span, env: &Environment) {
+        match self.source.get(&span.line).cloned() {
+            Some(text) => self.say(format!("at {}: {}", span, text)),
+            None => self.say(format!("at {}", span)),
+        }
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+            let line = match (self.input)() {
+                Some(line) => line,
+                None => {
+                    self.stepper.resume();
+                    return;
+                }
+            };
+            if self.command(&line, env) {
+                return;
+            }
+        }
+    }
+
+    fn say(&mut self, text: String) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+}
+
+impl<R: FnMut() -> Option<String>, W: Write> EvalHook for Debugger<R, W> {
+    fn before_statement(&mut self, stmt: &Statement, env: &Environment) {
+        if let Statement::BlockStatement(_) = stmt {
+            return;
+        }
+        let span = stmt.token().span;
+        if !span.is_known() {
+            return;
+        }
+        if self.stepper.pause_at(span) {
+            self.pause(&span, env);
+        }
+    }
+
+    fn enter_function(&mut self, function: &Function) {
+        let name = function.name.as_deref().unwrap_or("<anonymous>");
+        self.stack.push(name.to_string());
+        self.stepper.enter();
+    }
+
+    fn exit_function(&mut self, _function: &Function) {
+        self.stack.pop();
+        self.stepper.exit();
+    }
+}
+
+/// A value in one line. Functions show only their parameters.
+pub fn describe(value: &Object) -> String {
+    match value {
+        Object::Function(f) => {
+            let params: Vec<String> = f
+                .parameters
+                .iter()
+                .map(|p| p.token().literal.clone())
+                .collect();
+            format!("fn({})", params.join(", "))
+        }
+        other => other.inspect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_prog;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Scripted = Debugger<Box<dyn FnMut() -> Option<String>>, Vec<u8>>;
+
+    /// Runs `source` under a debugger fed `commands` and returns what it
+    /// printed. The first command is given before the program starts.
+    fn debug(source: &str, commands: &[&str]) -> String {
+        let mut lines: Vec<String> = commands.iter().rev().map(|c| c.to_string()).collect();
+        let first = lines.pop().unwrap_or_default();
+        let input: Box<dyn FnMut() -> Option<String>> = Box::new(move || lines.pop());
+        let debugger: Rc<RefCell<Scripted>> = Rc::new(RefCell::new(Debugger::new(input, vec![])));
+        let mut env = Environment::new();
+        env.set_hook(debugger.clone());
+        for (i, line) in source.lines().enumerate() {
+            debugger.borrow_mut().add_source(i + 1, line);
+        }
+        debugger.borrow_mut().command(first.as_str(), &env);
+        let mut lex = Lexer::init_lexer(source);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new());
+        eval_prog(&program, &mut env);
+        let output = debugger.borrow().output.clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    const SOURCE: &str = "let double = fn(x) {
+  let y = x * 2;
+  y
+};
+let a = double(1);
+let b = double(a);
+b";
+
+    #[test]
+    fn step_ends_with_run_test() {
+        let mut lines = vec!["o".to_string(), "s".to_string(), "s".to_string()];
+        let input: Box<dyn FnMut() -> Option<String>> = Box::new(move || lines.pop());
+        let debugger: Rc<RefCell<Scripted>> = Rc::new(RefCell::new(Debugger::new(input, vec![])));
+        let mut env = Environment::new();
+        env.set_hook(debugger.clone());
+        debugger.borrow_mut().command("s", &env);
+        for source in ["let f = fn(x) { x };\nf(1);", "let b = 2;"] {
+            let mut lex = Lexer::init_lexer(source);
+            let mut par = Parser::new(&mut lex);
+            eval_prog(&par.parse_program(), &mut env);
+            debugger.borrow_mut().resume();
+        }
+        let output = debugger.borrow().output.clone();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "at line 1, column 1
+(debug) at line 2, column 1
+(debug) at line 1, column 17
+(debug) "
+        );
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let out = debug(SOURCE, &["b 2", "p x", "c", "p x", "bt", "c"]);
+        assert_eq!(
+            out,
+            "breakpoint at line 2
+at line 2, column 3:   let y = x * 2;
+(debug) x = 1
+(debug) at line 2, column 3:   let y = x * 2;
+(debug) x = 2
+(debug) #0 double
+#1 <top level>
+(debug) "
+        );
+
+        let looped = "let i = 0;\nwhile (i < 3) {\n  i += 1; i += 0;\n}\ni";
+        let out = debug(looped, &["b 3", "p i", "c", "p i", "c", "p i", "c"]);
+        assert_eq!(
+            out,
+            "breakpoint at line 3
+at line 3, column 3:   i += 1; i += 0;
+(debug) i = 0
+(debug) at line 3, column 3:   i += 1; i += 0;
+(debug) i = 1
+(debug) at line 3, column 3:   i += 1; i += 0;
+(debug) i = 2
+(debug) "
+        );
+    }
+
+    #[test]
+    fn step_test() {
+        let out = debug(SOURCE, &["step", "n", "n", "s", "s", "o", "e", "c"]);
+        assert_eq!(
+            out,
+            "at line 1, column 1: let double = fn(x) {
+(debug) at line 5, column 1: let a = double(1);
+(debug) at line 6, column 1: let b = double(a);
+(debug) at line 2, column 3:   let y = x * 2;
+(debug) at line 3, column 3:   y
+(debug) at line 7, column 1: b
+(debug) scope 0: a = 2, b = 4, double = fn(x)
+(debug) "
+        );
+    }
+}
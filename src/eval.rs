@@ -1,50 +1,116 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
 use std::vec;
 
 use crate::{
     ast::{
-        BlockStatement, Expression, ExpressionStatement, Identifier, IfExpression, Program,
-        ReturnStatement, Statement,
+        fold, printer, ArrayLiteral, AssignExpression, BlockStatement, Boolean, CallExpression,
+        Expression, ExpressionStatement, Folder, ForStatement, HashLiteral, Identifier,
+        IfExpression, IndexExpression, InfixStatement, IntegerLiteral, InterpolatedString,
+        InterpolationPart, Program, ReturnStatement, Statement, StringLiteral, ThrowStatement,
+        TryExpression, TypeAnnotation, WhileStatement,
     },
-    object::{Environment, Function, Object},
+    builtins::Builtin,
+    object::{
+        Environment, ErrorKind, Frame, Function, Object, RuntimeError, TailCall, MAX_NESTING,
+    },
+    token::{Span, Token, TokenType},
 };
 
-pub fn eval_prog(prog: Program, env: &mut Environment) -> Object {
-    let result: Object = Object::Null;
-    for statement in prog {
-        let result = eval(statement, env);
+/// Watches a program as it runs. A debugger pauses in these calls to read
+/// commands; the evaluator carries on once they return.
+pub trait EvalHook {
+    /// Called before each statement runs.
+    fn before_statement(&mut self, _stmt: &Statement, _env: &Environment) {}
 
-        match result {
+    /// Called before each expression, including those inside statements,
+    /// is evaluated.
+    fn before_expression(&mut self, _expr: &Expression, _env: &Environment) {}
+
+    /// Called when a call to `function` starts running its body. A tail
+    /// call ends the caller's call before the callee's starts.
+    fn enter_function(&mut self, _function: &Function) {}
+
+    /// Called when the call to `function` returns or fails.
+    fn exit_function(&mut self, _function: &Function) {}
+}
+
+/// Runs `f` on the hook watching the run, if there is one and it is not
+/// busy already.
+fn notify(env: &Environment, f: impl FnOnce(&mut dyn EvalHook)) {
+    if let Some(hook) = env.hook() {
+        if let Ok(mut hook) = hook.try_borrow_mut() {
+            f(&mut *hook);
+        }
+    }
+}
+
+pub fn eval_prog(prog: &Program, env: &mut Environment) -> Object {
+    let mut result: Object = Object::Null;
+    env.reset_budget();
+    for statement in prog {
+        match eval(statement, env) {
             Some(Object::Return(x)) => return *x,
             Some(Object::Error(x)) => return Object::Error(x),
-            _ => (),
+            Some(Object::Break) | Some(Object::Continue) => {
+                return locate_error(outside_loop_error(), statement.token().span)
+            }
+            Some(Object::TailCall(call)) => return finish_tail_call(*call, env),
+            Some(x) => result = x,
+            None => result = Object::Null,
         }
     }
     return result;
 }
 
-fn eval(state: Statement, env: &mut Environment) -> Option<Object> {
+fn eval(state: &Statement, env: &mut Environment) -> Option<Object> {
+    notify(env, |hook| hook.before_statement(state, env));
     match state {
         Statement::LetStatement(x) => {
             let mut res = Object::Null;
-            match x.value {
-                Some(x) => res = eval_expr(*x, env),
+            match &x.value {
+                Some(x) => res = eval_expr(x, env),
                 None => {
-                    res = Object::Error("Could not evaluate let statement expression".to_string())
+                    res = new_error(
+                        ErrorKind::Syntax,
+                        "Could not evaluate let statement expression".to_string(),
+                    )
                 }
             }
-            if is_error(res.clone()) {
+            // A `return` in the value, as in an `if` branch, leaves the
+            // function instead of being bound.
+            if is_error(res.clone()) || matches!(res, Object::Return(_)) {
                 return Some(res);
             }
-            match x.name {
+            if let (Some(name), Some(ty), Some(value)) = (&x.name, &x.annotation, &x.value) {
+                if !has_type(&res, ty) {
+                    let message = format!("{} must be {}, got {}", name.value, ty, res.type_());
+                    let err = new_error(ErrorKind::Type, message);
+                    return Some(locate_error(err, value.token().span));
+                }
+            }
+            match &x.name {
                 Some(x) => {
-                    env.set(x.value, res.clone());
+                    if let Object::Function(ref mut f) = res {
+                        if f.name.is_none() {
+                            f.name = Some(x.value.clone());
+                        }
+                    }
+                    if let Err(e) = env.set(x.value.clone(), res.clone()) {
+                        return Some(Object::Error(e));
+                    }
+                }
+                None => {
+                    res = new_error(
+                        ErrorKind::Syntax,
+                        "Could not add identifier to environment".to_string(),
+                    )
                 }
-                None => res = Object::Error("Could not add identifier to environment".to_string()),
             }
             if is_error(res.clone()) {
                 return Some(res);
             }
-            Some(res)
+            None
         }
         Statement::ReturnStatement(x) => {
             let mut res = Object::Null;
@@ -67,131 +133,839 @@ fn eval(state: Statement, env: &mut Environment) -> Option<Object> {
             res = eval_block(x, env);
             Some(res)
         }
+        Statement::WhileStatement(x) => Some(eval_while(x, env)),
+        Statement::BreakStatement(_) => Some(Object::Break),
+        Statement::ContinueStatement(_) => Some(Object::Continue),
+        Statement::ForStatement(x) => Some(eval_for(x, env)),
+        Statement::ThrowStatement(x) => Some(eval_throw(x, env)),
+        Statement::InfixStatement(x) => eval_infix_decl(x, env),
     }
 }
 
-fn eval_ret(ret: ReturnStatement, env: &mut Environment) -> Object {
+/// Binds a declared operator to its function. The symbol is used as the
+/// name, which no identifier can clash with.
+fn eval_infix_decl(stmt: &InfixStatement, env: &mut Environment) -> Option<Object> {
+    let val = match &stmt.value {
+        Some(v) => eval_expr(v, env),
+        None => {
+            return Some(new_error(
+                ErrorKind::Syntax,
+                "Could not evaluate operator declaration".to_string(),
+            ))
+        }
+    };
+    let mut f = match val {
+        Object::Function(f) => f,
+        Object::Error(_) => return Some(val),
+        other => {
+            return Some(new_error(
+                ErrorKind::Type,
+                format!(
+                    "operator {} must be a function, got {}",
+                    stmt.operator,
+                    other.type_()
+                ),
+            ))
+        }
+    };
+    if f.parameters.len() != 2 {
+        return Some(new_error(
+            ErrorKind::Argument,
+            format!(
+                "operator {} must take 2 parameters, got {}",
+                stmt.operator,
+                f.parameters.len()
+            ),
+        ));
+    }
+    if f.name.is_none() {
+        f.name = Some(stmt.operator.clone());
+    }
+    match env.set(stmt.operator.clone(), Object::Function(f)) {
+        Ok(()) => None,
+        Err(e) => Some(Object::Error(e)),
+    }
+}
+
+fn eval_throw(stmt: &ThrowStatement, env: &mut Environment) -> Object {
+    let val = match &stmt.value {
+        Some(v) => eval_expr(v, env),
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "throw statement without a value".to_string(),
+            )
+        }
+    };
+    let mut err = match val {
+        Object::Error(_) => return val,
+        // Re-raising a caught error keeps its original location and stack.
+        Object::ErrorValue(e) => return Object::Error(e),
+        Object::String(s) => RuntimeError::new(ErrorKind::User, s),
+        other => RuntimeError::new(ErrorKind::User, other.inspect()),
+    };
+    err.span = stmt.token.span;
+    Object::Error(err)
+}
+
+fn eval_try(expr: &TryExpression, env: &mut Environment) -> Object {
+    let res = match &expr.body {
+        Some(b) => eval(b, env).unwrap_or(Object::Null),
+        None => return new_error(ErrorKind::Syntax, "try without a body".to_string()),
+    };
+    let err = match res {
+        Object::Error(e) => e,
+        // A `return f(x)` inside the body has to be called here, where its
+        // errors can still be caught, and its value then returned.
+        Object::TailCall(call) => match finish_tail_call(*call, env) {
+            Object::Error(e) => e,
+            val => return Object::Return(Box::new(val)),
+        },
+        _ => return res,
+    };
+    let name = match &expr.ident {
+        Some(i) => i.value.clone(),
+        None => return new_error(ErrorKind::Syntax, "catch without a name".to_string()),
+    };
+    let handler = match &expr.handler {
+        Some(h) => h,
+        None => return new_error(ErrorKind::Syntax, "catch without a body".to_string()),
+    };
+    let mut scope = env.new_enclosed(env.clone());
+    if let Err(e) = scope.set(name, Object::ErrorValue(err)) {
+        return Object::Error(e);
+    }
+    eval(handler, &mut scope).unwrap_or(Object::Null)
+}
+
+fn eval_for(stmt: &ForStatement, env: &mut Environment) -> Object {
+    let name = match &stmt.ident {
+        Some(i) => &i.value,
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "for loop without a loop variable".to_string(),
+            )
+        }
+    };
+    let body = match &stmt.body {
+        Some(b) => b,
+        None => return new_error(ErrorKind::Syntax, "for loop without a body".to_string()),
+    };
+    let iterable = match &stmt.iterable {
+        Some(i) => eval_expr(i, env),
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "for loop without a collection".to_string(),
+            )
+        }
+    };
+    if is_error(iterable.clone()) {
+        return iterable;
+    }
+    let items: Box<dyn Iterator<Item = Object>> = match iterable {
+        Object::Array(x) => Box::new(x.into_iter()),
+        Object::String(x) => Box::new(
+            x.chars()
+                .map(|c| Object::String(c.to_string()))
+                .collect::<Vec<Object>>()
+                .into_iter(),
+        ),
+        Object::Hash(x) => Box::new(x.into_keys().map(|k| k.to_object())),
+        Object::Range(start, end) => Box::new((start..end).map(Object::Integer)),
+        _ => {
+            return new_error(
+                ErrorKind::Type,
+                format!("cannot iterate over {}", iterable.type_()),
+            )
+        }
+    };
+    for item in items {
+        let mut scope = env.new_enclosed(env.clone());
+        if let Err(e) = scope.set(name.clone(), item) {
+            return Object::Error(e);
+        }
+        match eval(body, &mut scope) {
+            Some(Object::Break) => break,
+            Some(Object::Continue) => continue,
+            Some(res @ Object::Return(_))
+            | Some(res @ Object::Error(_))
+            | Some(res @ Object::TailCall(_)) => return res,
+            _ => (),
+        }
+    }
+    Object::Null
+}
+
+fn eval_while(stmt: &WhileStatement, env: &mut Environment) -> Object {
+    let cond = match &stmt.cond {
+        Some(c) => c,
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "while loop without a condition".to_string(),
+            )
+        }
+    };
+    let body = match &stmt.body {
+        Some(b) => b,
+        None => return new_error(ErrorKind::Syntax, "while loop without a body".to_string()),
+    };
+    loop {
+        let check = eval_expr(cond, env);
+        if is_error(check.clone()) {
+            return check;
+        }
+        if !is_truthy(check) {
+            break;
+        }
+        match eval(body, env) {
+            Some(Object::Break) => break,
+            Some(Object::Continue) => continue,
+            Some(res @ Object::Return(_))
+            | Some(res @ Object::Error(_))
+            | Some(res @ Object::TailCall(_)) => return res,
+            _ => (),
+        }
+    }
+    Object::Null
+}
+
+fn eval_ret(ret: &ReturnStatement, env: &mut Environment) -> Object {
     let mut val = Object::Null;
-    match ret.value {
-        Some(x) => val = eval_expr(*x, env),
+    match ret.value.as_deref() {
+        Some(Expression::CallExpression(call)) => return tail_call(call, env),
+        Some(x) => val = eval_expr(x, env),
         None => return val,
     }
     Object::Return(Box::new(val))
 }
 
-fn eval_block(block: BlockStatement, env: &mut Environment) -> Object {
-    let val = Object::Null;
-    for statement in block.statements {
-        let val = eval(statement, env);
-        let v: Object;
-        match val {
-            Some(s) => v = s,
-            None => v = Object::Null,
+/// Evaluates a function body. A call in tail position, at the end of the
+/// body or of an `if` branch at the end of the body, becomes a tail call.
+fn eval_tail_block(block: &BlockStatement, env: &mut Environment) -> Object {
+    let (last, init) = match block.statements.split_last() {
+        Some(x) => x,
+        None => return Object::Null,
+    };
+    let val = eval_statements(init, env);
+    if is_signal(&val) {
+        return val;
+    }
+    eval_tail(last, env)
+}
+
+fn eval_tail(stmt: &Statement, env: &mut Environment) -> Object {
+    let expr = match stmt {
+        Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+        Statement::BlockStatement(b) => return eval_tail_block(b, env),
+        other => return eval(other, env).unwrap_or(Object::Null),
+    };
+    notify(env, |hook| hook.before_statement(stmt, env));
+    if let Expression::CallExpression(_) | Expression::IfExpression(_) = expr.as_ref() {
+        notify(env, |hook| hook.before_expression(expr, env));
+    }
+    match expr.as_ref() {
+        Expression::CallExpression(call) => tail_call(call, env),
+        Expression::IfExpression(x) => {
+            let span = x.token.span;
+            if let Err(e) = env.step() {
+                return locate_error(Object::Error(e), span);
+            }
+            let cond = match &x.cond {
+                Some(c) => eval_expr(c, env),
+                None => Object::Null,
+            };
+            if is_error(cond.clone()) {
+                return cond;
+            }
+            let branch = if is_truthy(cond) {
+                &x.consequence
+            } else {
+                &x.alternative
+            };
+            match branch {
+                Some(b) => eval_tail(b, env),
+                None => Object::Null,
+            }
+        }
+        other => eval_expr(other, env),
+    }
+}
+
+/// Evaluates the callee and arguments of a call and packages them as a
+/// tail call instead of making it.
+fn tail_call(call: &CallExpression, env: &mut Environment) -> Object {
+    let span = call.token.span;
+    if let Err(e) = env.step() {
+        return locate_error(Object::Error(e), span);
+    }
+    if is_call_to(call, "quote") {
+        return eval_quote(call, env);
+    }
+    let (function, args) = eval_callee(call, env);
+    if is_error(function.clone()) {
+        return function;
+    }
+    // Builtins run no Monkey code of their own, so there is no stack to
+    // save by deferring them.
+    if let Object::Builtin(b) = function {
+        return locate_error(b.call(args, env), span);
+    }
+    Object::TailCall(Box::new(TailCall {
+        function,
+        args,
+        span,
+    }))
+}
+
+/// Makes a tail call that reached a place with no enclosing function
+/// loop to run it, such as the top level.
+fn finish_tail_call(call: TailCall, env: &mut Environment) -> Object {
+    locate_error(apply_function(call.function, call.args, env), call.span)
+}
+
+fn eval_block(block: &BlockStatement, env: &mut Environment) -> Object {
+    eval_statements(&block.statements, env)
+}
+
+fn eval_statements(statements: &[Statement], env: &mut Environment) -> Object {
+    let mut val = Object::Null;
+    for statement in statements {
+        match eval(statement, env) {
+            Some(s) => val = s,
+            None => val = Object::Null,
         };
-        if v != Object::Null
-            && (v.type_() == "RETURN_VALUE_OBJ".to_string() || v.type_() == "ERROR".to_string())
-        {
-            return v;
+        if is_signal(&val) {
+            return val;
         }
     }
     return val;
 }
 
-fn eval_expression_stmt(expr: ExpressionStatement, env: &mut Environment) -> Object {
+/// Whether `val` ends the enclosing block early.
+#[inline(always)]
+fn is_signal(val: &Object) -> bool {
+    matches!(
+        val,
+        Object::Return(_)
+            | Object::Error(_)
+            | Object::Break
+            | Object::Continue
+            | Object::TailCall(_)
+    )
+}
+
+fn eval_expression_stmt(expr: &ExpressionStatement, env: &mut Environment) -> Object {
     let val = Object::Null;
-    match expr.expr {
-        Some(x) => return eval_expr(*x, env),
+    match &expr.expr {
+        Some(x) => return eval_expr_unfinished(x, env),
         None => return val,
     }
 }
-fn eval_expr(expr: Expression, env: &mut Environment) -> Object {
+
+/// Evaluates an expression used as a value. A `return f(x)` inside it, as
+/// in an `if` branch, has no function loop here to hand its call to, so
+/// the call is made and its value returned.
+fn eval_expr(expr: &Expression, env: &mut Environment) -> Object {
+    match eval_expr_unfinished(expr, env) {
+        Object::TailCall(call) => match finish_tail_call(*call, env) {
+            Object::Error(e) => Object::Error(e),
+            val => Object::Return(Box::new(val)),
+        },
+        val => val,
+    }
+}
+
+/// Evaluates an expression, leaving a tail call made inside it for the
+/// enclosing function to run.
+fn eval_expr_unfinished(expr: &Expression, env: &mut Environment) -> Object {
+    let span = expr.token().span;
+    if let Err(e) = env.step() {
+        return locate_error(Object::Error(e), span);
+    }
+    notify(env, |hook| hook.before_expression(expr, env));
+    locate_error(eval_expr_node(expr, env), span)
+}
+
+fn eval_expr_node(expr: &Expression, env: &mut Environment) -> Object {
     match expr {
         Expression::Identifier(x) => return eval_identifier(x, env),
         Expression::IntegerLiteral(x) => return Object::Integer(x.value),
         Expression::PrefixExpression(x) => {
             let mut right = Object::Null;
-            match x.right {
-                Some(x) => right = eval_expr(*x, env),
+            match &x.right {
+                Some(x) => right = eval_expr(x, env),
                 None => (),
             }
             if is_error(right.clone()) {
                 return right;
             }
-            return eval_prefix(x.operator, right);
+            return eval_prefix(&x.operator, right);
         }
         Expression::InfixExpression(x) => {
             let mut left = Object::Null;
-            match x.left {
-                Some(l) => left = eval_expr(*l, env),
+            match &x.left {
+                Some(l) => left = eval_expr(l, env),
                 None => (),
             }
             if is_error(left.clone()) {
                 return left;
             }
             let mut right = Object::Null;
-            match x.right {
-                Some(r) => right = eval_expr(*r, env),
+            match &x.right {
+                Some(r) => right = eval_expr(r, env),
                 None => (),
             }
             if is_error(right.clone()) {
                 return right;
             }
-            return eval_infix_expr(x.operator, left, right);
+            if x.token.type_ == TokenType::OPERATOR {
+                return eval_operator_call(&x.operator, x.token.span, left, right, env);
+            }
+            return eval_infix_expr(&x.operator, left, right, env);
         }
         Expression::Boolean(x) => return Object::Boolean(x.value),
         Expression::IfExpression(x) => return eval_if_expr(x, env),
+        Expression::TryExpression(x) => eval_try(x, env),
         Expression::FunctionLiteral(x) => {
-            let param: Vec<Expression>;
-            match x.parameters {
-                Some(x) => param = x,
-                None => param = vec![],
-            };
-            let bod: BlockStatement;
-            match x.body {
-                Some(b) => match *b {
-                    Statement::BlockStatement(x) => bod = x,
-                    _ => return Object::Error("not a block statement".to_string()),
-                },
+            let param = x.parameters.clone().unwrap_or_default();
+            let bod: Rc<BlockStatement>;
+            match &x.body {
+                Some(b) => bod = b.clone(),
                 None => {
-                    return Object::Error("expected statement but got something else".to_string())
+                    return new_error(
+                        ErrorKind::Syntax,
+                        "expected statement but got something else".to_string(),
+                    )
                 }
             }
             return Object::Function(Function {
+                name: None,
                 parameters: param,
+                param_types: x.param_types.clone(),
+                return_type: x.return_type.clone(),
                 body: bod,
                 env: env.clone(),
             });
         }
-        Expression::CallExpression(x) => {
-            let function: Object;
-            match x.func {
-                Some(x) => function = eval_expr(*x, env),
+        Expression::CallExpression(x) if is_call_to(x, "quote") => eval_quote(x, env),
+        Expression::CallExpression(x) => eval_call(x, env),
+        Expression::MacroLiteral(_) => new_error(
+            ErrorKind::Syntax,
+            "macros must be bound by a top-level let".to_string(),
+        ),
+        Expression::StringLiteral(x) => Object::String(x.value.clone()),
+        Expression::InterpolatedString(x) => eval_interpolated_string(x, env),
+        Expression::ArrayLiteral(x) => {
+            let elements = eval_exprs(&x.elements, env);
+            if elements.len() == 1 && is_error(elements[0].clone()) {
+                return elements[0].clone();
+            }
+            reserved(Object::Array(elements), env)
+        }
+        Expression::HashLiteral(x) => eval_hash_literal(x, env),
+        Expression::IndexExpression(x) => eval_index_expr(x, env),
+        Expression::AssignExpression(x) => eval_assign_expr(x, env),
+    }
+}
+
+/// Whether `call` calls the identifier `name` directly, as the special
+/// forms `quote` and `unquote` are written.
+pub fn is_call_to(call: &CallExpression, name: &str) -> bool {
+    matches!(call.func.as_deref(), Some(Expression::Identifier(x)) if x.value == name)
+}
+
+/// `quote(expr)` evaluates to `expr` itself, unevaluated, except that each
+/// `unquote(x)` inside it is replaced by the value of `x`.
+fn eval_quote(call: &CallExpression, env: &mut Environment) -> Object {
+    let expr = match call.args.as_deref() {
+        Some([expr]) => expr.clone(),
+        args => {
+            return new_error(
+                ErrorKind::Argument,
+                format!(
+                    "wrong number of arguments to quote: want=1, got={}",
+                    args.map_or(0, |a| a.len())
+                ),
+            )
+        }
+    };
+    let mut unquoter = Unquoter { env, error: None };
+    let quoted = unquoter.fold_expression(expr);
+    match unquoter.error {
+        Some(err) => err,
+        None => Object::Quote(Box::new(quoted)),
+    }
+}
+
+/// Replaces each `unquote(x)` in quoted code with the value of `x`.
+struct Unquoter<'a> {
+    env: &'a mut Environment,
+    error: Option<Object>,
+}
+
+impl Folder for Unquoter<'_> {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        let expr = fold::walk_expression(self, expr);
+        let unquote = match &expr {
+            Expression::CallExpression(c) if self.error.is_none() && is_call_to(c, "unquote") => c,
+            _ => return expr,
+        };
+        let val = match unquote.args.as_deref() {
+            Some([arg]) => eval_expr(arg, self.env),
+            _ => new_error(
+                ErrorKind::Argument,
+                "wrong number of arguments to unquote: want=1".to_string(),
+            ),
+        };
+        match quoted_node(val, unquote.token.span) {
+            Ok(node) => node,
+            Err(err) => {
+                self.error = Some(locate_error(err, unquote.token.span));
+                expr
+            }
+        }
+    }
+}
+
+/// Turns an unquoted value back into code. The new nodes take the span of
+/// the `unquote` call.
+fn quoted_node(val: Object, span: Span) -> Result<Expression, Object> {
+    let token = |type_, literal: String| Token {
+        type_,
+        literal,
+        span,
+    };
+    match val {
+        Object::Integer(x) => Ok(Expression::IntegerLiteral(IntegerLiteral {
+            token: token(TokenType::INT, x.to_string()),
+            value: x,
+        })),
+        Object::Boolean(x) => Ok(Expression::Boolean(Boolean {
+            token: token(
+                if x { TokenType::TRUE } else { TokenType::FALSE },
+                x.to_string(),
+            ),
+            value: x,
+        })),
+        Object::String(x) => Ok(Expression::StringLiteral(StringLiteral {
+            token: token(TokenType::STRING, x.clone()),
+            value: x,
+        })),
+        Object::Array(xs) => {
+            let elements = xs
+                .into_iter()
+                .map(|x| quoted_node(x, span))
+                .collect::<Result<Vec<Expression>, Object>>()?;
+            Ok(Expression::ArrayLiteral(ArrayLiteral {
+                token: token(TokenType::LBRACKET, "[".to_string()),
+                elements,
+            }))
+        }
+        Object::Quote(x) => Ok(*x),
+        Object::Error(_) => Err(val),
+        other => Err(new_error(
+            ErrorKind::Type,
+            format!("cannot unquote {}", other.type_()),
+        )),
+    }
+}
+
+fn eval_assign_expr(expr: &AssignExpression, env: &mut Environment) -> Object {
+    let target = match &expr.target {
+        Some(t) => t,
+        None => return new_error(ErrorKind::Syntax, "assignment without a target".to_string()),
+    };
+    let mut value = match &expr.value {
+        Some(v) => eval_expr(v, env),
+        None => return new_error(ErrorKind::Syntax, "assignment without a value".to_string()),
+    };
+    if is_error(value.clone()) {
+        return value;
+    }
+    let mut indices = vec![];
+    let root = match eval_place(target, &mut indices, env) {
+        Ok(root) => root,
+        Err(e) => return e,
+    };
+    if expr.operator == "=" && indices.is_empty() {
+        return assign_to(root, value, env);
+    }
+    let container = eval_expr(root, env);
+    if is_error(container.clone()) {
+        return container;
+    }
+    if expr.operator != "=" {
+        let mut current = container.clone();
+        for index in &indices {
+            current = index_value(&current, index);
+            if is_error(current.clone()) {
+                return current;
+            }
+        }
+        let oper = expr.operator.trim_end_matches('=');
+        value = eval_infix_expr(oper, current, value, env);
+        if is_error(value.clone()) {
+            return value;
+        }
+    }
+    let updated = match store(container, &indices, value.clone(), env) {
+        Ok(updated) => updated,
+        Err(e) => return e,
+    };
+    if let Err(e) = check_nesting(&updated) {
+        return e;
+    }
+    let res = assign_to(root, updated, env);
+    if is_error(res.clone()) {
+        return res;
+    }
+    value
+}
+
+/// Evaluates the indices of an assignment target such as `xs[i][j]` into
+/// `indices`, each once and from left to right, and returns the variable
+/// the target is rooted at.
+fn eval_place<'a>(
+    target: &'a Expression,
+    indices: &mut Vec<Object>,
+    env: &mut Environment,
+) -> Result<&'a Expression, Object> {
+    match target {
+        Expression::Identifier(_) => Ok(target),
+        Expression::IndexExpression(x) => {
+            let left = match &x.left {
+                Some(l) => l,
+                None => {
+                    return Err(new_error(
+                        ErrorKind::Syntax,
+                        "index expression without a target".to_string(),
+                    ))
+                }
+            };
+            let root = eval_place(left, indices, env)?;
+            let index = match &x.index {
+                Some(i) => eval_expr(i, env),
                 None => {
-                    function = Object::Error("expected function got something else".to_string())
+                    return Err(new_error(
+                        ErrorKind::Syntax,
+                        "index expression without an index".to_string(),
+                    ))
                 }
+            };
+            if is_error(index.clone()) {
+                return Err(index);
             }
-            if is_error(function.clone()) {
-                return function;
+            indices.push(index);
+            Ok(root)
+        }
+        _ => Err(new_error(
+            ErrorKind::Syntax,
+            format!(
+                "invalid assignment target: {}",
+                printer::print_expression(target)
+            ),
+        )),
+    }
+}
+
+/// Stores `value` into `target`, which must be a variable.
+fn assign_to(target: &Expression, value: Object, env: &mut Environment) -> Object {
+    match target {
+        Expression::Identifier(x) => match env.assign(&x.value, value.clone()) {
+            Ok(true) => value,
+            Ok(false) => new_error(
+                ErrorKind::Name,
+                format!("assignment to undeclared identifier: {}", x.value),
+            ),
+            Err(e) => Object::Error(e),
+        },
+        _ => new_error(
+            ErrorKind::Syntax,
+            format!(
+                "invalid assignment target: {}",
+                printer::print_expression(target)
+            ),
+        ),
+    }
+}
+
+/// Stores `value` at `indices` within `container`. Collections are values,
+/// so this builds an updated copy of `container` for the caller to assign
+/// back.
+fn store(
+    container: Object,
+    indices: &[Object],
+    value: Object,
+    env: &mut Environment,
+) -> Result<Object, Object> {
+    let (index, rest) = match indices.split_first() {
+        Some(first) => first,
+        None => return Ok(value),
+    };
+    let inner = match rest.is_empty() {
+        true => Object::Null,
+        false => index_value(&container, index),
+    };
+    if is_error(inner.clone()) {
+        return Err(inner);
+    }
+    let value = store(inner, rest, value, env)?;
+    match (container, index) {
+        (Object::Array(mut elems), Object::Integer(i)) => {
+            match usize::try_from(*i) {
+                Ok(i) if i < elems.len() => elems[i] = value,
+                _ => {
+                    return Err(new_error(
+                        ErrorKind::Index,
+                        format!("index out of range: {}", i),
+                    ))
+                }
             }
-            let mut arg: Vec<Expression> = vec![];
-            match x.args {
-                Some(x) => arg = x,
-                None => (),
+            Ok(Object::Array(elems))
+        }
+        (Object::Hash(mut pairs), _) => match index.hash_key() {
+            Some(k) => {
+                if !pairs.contains_key(&k) {
+                    let grown = k.to_object().size() + value.size();
+                    if let Err(e) = env.reserve(grown) {
+                        return Err(Object::Error(e));
+                    }
+                }
+                pairs.insert(k, value);
+                Ok(Object::Hash(pairs))
+            }
+            None => Err(new_error(
+                ErrorKind::Type,
+                format!("unusable as hash key: {}", index.type_()),
+            )),
+        },
+        (container, _) => Err(new_error(
+            ErrorKind::Type,
+            format!(
+                "index assignment not supported: {}[{}]",
+                container.type_(),
+                index.type_()
+            ),
+        )),
+    }
+}
+
+fn eval_hash_literal(hash: &HashLiteral, env: &mut Environment) -> Object {
+    let mut pairs = BTreeMap::new();
+    for (key_expr, value_expr) in &hash.pairs {
+        let key = eval_expr(key_expr, env);
+        if is_error(key.clone()) {
+            return key;
+        }
+        let hash_key = match key.hash_key() {
+            Some(k) => k,
+            None => {
+                return new_error(
+                    ErrorKind::Type,
+                    format!("unusable as hash key: {}", key.type_()),
+                )
             }
-            let args = eval_exprs(arg, env.clone());
-            if args.len() == 1 && is_error(args[0].clone()) {
-                return args[0].clone();
+        };
+        let value = eval_expr(value_expr, env);
+        if is_error(value.clone()) {
+            return value;
+        }
+        pairs.insert(hash_key, value);
+    }
+    reserved(Object::Hash(pairs), env)
+}
+
+fn eval_index_expr(expr: &IndexExpression, env: &mut Environment) -> Object {
+    let left = match &expr.left {
+        Some(l) => eval_expr(l, env),
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "index expression without a target".to_string(),
+            )
+        }
+    };
+    if is_error(left.clone()) {
+        return left;
+    }
+    let index = match &expr.index {
+        Some(i) => eval_expr(i, env),
+        None => {
+            return new_error(
+                ErrorKind::Syntax,
+                "index expression without an index".to_string(),
+            )
+        }
+    };
+    if is_error(index.clone()) {
+        return index;
+    }
+    index_value(&left, &index)
+}
+
+/// The element of `left` at `index`, as `left[index]` evaluates to.
+fn index_value(left: &Object, index: &Object) -> Object {
+    match (left, index) {
+        (Object::Array(x), Object::Integer(i)) => match usize::try_from(*i) {
+            Ok(i) if i < x.len() => x[i].clone(),
+            _ => Object::Null,
+        },
+        (Object::String(x), Object::Integer(i)) => match usize::try_from(*i) {
+            Ok(i) => match x.chars().nth(i) {
+                Some(c) => Object::String(c.to_string()),
+                None => Object::Null,
+            },
+            Err(_) => Object::Null,
+        },
+        (Object::ErrorValue(e), Object::String(field)) => e.field(field),
+        (Object::Hash(x), _) => match index.hash_key() {
+            Some(k) => match x.get(&k) {
+                Some(v) => v.clone(),
+                None => Object::Null,
+            },
+            None => new_error(
+                ErrorKind::Type,
+                format!("unusable as hash key: {}", index.type_()),
+            ),
+        },
+        _ => new_error(
+            ErrorKind::Type,
+            format!(
+                "index operator not supported: {}[{}]",
+                left.type_(),
+                index.type_()
+            ),
+        ),
+    }
+}
+
+fn eval_interpolated_string(template: &InterpolatedString, env: &mut Environment) -> Object {
+    let mut result = String::new();
+    for part in &template.parts {
+        match part {
+            InterpolationPart::Literal(x) => result.push_str(x),
+            InterpolationPart::Expression(x) => {
+                let val = eval_expr(x, env);
+                if is_error(val.clone()) {
+                    return val;
+                }
+                result.push_str(&val.inspect());
+                if let Err(e) = env.reserve(result.len()) {
+                    return Object::Error(e);
+                }
             }
-            return apply_function(function, args, env);
         }
-        Expression::StringLiteral(x) => Object::String(x.value),
     }
+    Object::String(result)
 }
 
-fn eval_exprs(exps: Vec<Expression>, env: Environment) -> Vec<Object> {
+fn eval_exprs(exps: &[Expression], env: &mut Environment) -> Vec<Object> {
     let mut result = Vec::<Object>::new();
     for exp in exps {
-        let evaluated = eval_expr(exp, &mut env.clone());
+        let evaluated = eval_expr(exp, env);
         if is_error(evaluated.clone()) {
             return vec![evaluated];
         }
@@ -200,19 +974,28 @@ fn eval_exprs(exps: Vec<Expression>, env: Environment) -> Vec<Object> {
     return result;
 }
 
-fn eval_identifier(ident: Identifier, env: &mut Environment) -> Object {
-    let check = env.get(ident.value.clone());
+fn eval_identifier(ident: &Identifier, env: &mut Environment) -> Object {
+    let check = env.get(&ident.value);
     match check {
-        Some(x) => return x.clone(),
-        None => return Object::Error(format!("identifier not found: {}", ident.value.clone())),
+        Some(x) => return x,
+        None => match Builtin::lookup(&ident.value) {
+            Some(b) => Object::Builtin(b),
+            None => new_error(
+                ErrorKind::Name,
+                format!("identifier not found: {}", ident.value),
+            ),
+        },
     }
 }
 
-fn eval_prefix(oper: String, right: Object) -> Object {
-    match oper.as_str() {
+pub fn eval_prefix(oper: &str, right: Object) -> Object {
+    match oper {
         "!" => return eval_bang_oper(right),
         "-" => return eval_minus_oper(right),
-        _ => Object::Error(format!("unknown operator: {} {}", oper, right.type_())),
+        _ => new_error(
+            ErrorKind::Type,
+            format!("unknown operator: {} {}", oper, right.type_()),
+        ),
     }
 }
 
@@ -227,22 +1010,28 @@ fn eval_bang_oper(obj: Object) -> Object {
 
 fn eval_minus_oper(obj: Object) -> Object {
     match obj {
-        Object::Integer(x) => return Object::Integer(-x),
-        _ => Object::Error(format!("Don't know yet")),
+        Object::Integer(x) => return overflow_checked(x.checked_neg()),
+        _ => new_error(
+            ErrorKind::Type,
+            format!("unknown operator: -{}", obj.type_()),
+        ),
     }
 }
 
-fn eval_infix_expr(oper: String, left: Object, right: Object) -> Object {
+pub fn eval_infix_expr(oper: &str, left: Object, right: Object, env: &Environment) -> Object {
     if left.type_().as_str() == "INTEGER_OBJ" && right.type_().as_str() == "INTEGER_OBJ" {
         return eval_integer_infix(oper, left, right);
     } else if left.type_().as_str() == "STRING" && right.type_().as_str() == "STRING" {
-        return eval_string_infix(oper, left, right);
+        return eval_string_infix(oper, left, right, env);
     }
-    Object::Error(format!("type mismatch: {} {}", left.type_(), right.type_()))
+    new_error(
+        ErrorKind::Type,
+        format!("type mismatch: {} {}", left.type_(), right.type_()),
+    )
 }
 
-fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
-    match oper.as_str() {
+fn eval_integer_infix(oper: &str, left: Object, right: Object) -> Object {
+    match oper {
         "+" => {
             let mut leftval: i64 = 0;
             let mut rightval: i64 = 0;
@@ -254,7 +1043,7 @@ fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
                 Object::Integer(x) => rightval = x,
                 _ => (),
             }
-            return Object::Integer(leftval + rightval);
+            return overflow_checked(leftval.checked_add(rightval));
         }
         "-" => {
             let mut leftval: i64 = 0;
@@ -267,7 +1056,7 @@ fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
                 Object::Integer(x) => rightval = x,
                 _ => (),
             }
-            return Object::Integer(leftval - rightval);
+            return overflow_checked(leftval.checked_sub(rightval));
         }
         "*" => {
             let mut leftval: i64 = 0;
@@ -280,7 +1069,7 @@ fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
                 Object::Integer(x) => rightval = x,
                 _ => (),
             }
-            return Object::Integer(leftval * rightval);
+            return overflow_checked(leftval.checked_mul(rightval));
         }
         "/" => {
             let mut leftval: i64 = 0;
@@ -293,7 +1082,10 @@ fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
                 Object::Integer(x) => rightval = x,
                 _ => (),
             }
-            return Object::Integer(leftval / rightval);
+            if rightval == 0 {
+                return new_error(ErrorKind::ZeroDivision, "division by zero".to_string());
+            }
+            return overflow_checked(leftval.checked_div(rightval));
         }
         "<" => {
             let mut leftval: i64 = 0;
@@ -347,23 +1139,38 @@ fn eval_integer_infix(oper: String, left: Object, right: Object) -> Object {
             }
             return Object::Boolean(leftval != rightval);
         }
-        _ => Object::Error(format!(
-            "unknown operator: {} {} {}",
-            left.type_(),
-            oper,
-            right.type_()
-        )),
+        ".." => match (left, right) {
+            (Object::Integer(l), Object::Integer(r)) => Object::Range(l, r),
+            _ => Object::Null,
+        },
+        _ => new_error(
+            ErrorKind::Type,
+            format!(
+                "unknown operator: {} {} {}",
+                left.type_(),
+                oper,
+                right.type_()
+            ),
+        ),
     }
 }
 
-fn eval_string_infix(oper: String, left: Object, right: Object) -> Object {
-    if oper != "+".to_string() {
-        return new_error(format!(
-            "unknown operator: {} {} {}",
-            left.type_(),
-            oper,
-            right.type_()
-        ));
+fn eval_string_infix(oper: &str, left: Object, right: Object, env: &Environment) -> Object {
+    match oper {
+        "==" => return Object::Boolean(left == right),
+        "!=" => return Object::Boolean(left != right),
+        _ => (),
+    }
+    if oper != "+" {
+        return new_error(
+            ErrorKind::Type,
+            format!(
+                "unknown operator: {} {} {}",
+                left.type_(),
+                oper,
+                right.type_()
+            ),
+        );
     }
     let mut left_val: String;
     match left {
@@ -375,14 +1182,17 @@ fn eval_string_infix(oper: String, left: Object, right: Object) -> Object {
         Object::String(x) => right_val = x,
         _ => todo!(),
     }
+    if let Err(e) = env.reserve(left_val.len() + right_val.len()) {
+        return Object::Error(e);
+    }
     left_val.push_str(right_val.as_str());
     return Object::String(left_val);
 }
 
-fn eval_if_expr(expr: IfExpression, env: &mut Environment) -> Object {
+fn eval_if_expr(expr: &IfExpression, env: &mut Environment) -> Object {
     let mut cond = Object::Null;
-    match expr.cond {
-        Some(c) => cond = eval_expr(*c, env),
+    match &expr.cond {
+        Some(c) => cond = eval_expr(c, env),
         None => (),
     }
     if is_error(cond.clone()) {
@@ -390,13 +1200,14 @@ fn eval_if_expr(expr: IfExpression, env: &mut Environment) -> Object {
     }
     if is_truthy(cond) {
         let mut cons = Object::Null;
-        match expr.consequence {
+        match &expr.consequence {
             Some(c) => {
-                let con = eval(*c, env);
+                let con = eval(c, env);
                 match con {
                     Some(c) => cons = c,
                     None => {
-                        cons = Object::Error(
+                        cons = new_error(
+                            ErrorKind::Syntax,
                             "Could not evaluate consequence part of if expression".to_string(),
                         )
                     }
@@ -406,13 +1217,14 @@ fn eval_if_expr(expr: IfExpression, env: &mut Environment) -> Object {
         }
         return cons;
     } else {
-        match expr.alternative {
+        match &expr.alternative {
             Some(a) => {
-                let e = eval(*a, env);
+                let e = eval(a, env);
                 match e {
                     Some(e) => return e,
                     None => {
-                        return Object::Error(
+                        return new_error(
+                            ErrorKind::Syntax,
                             "could not evaluated alternative part of the if expression".to_string(),
                         )
                     }
@@ -433,8 +1245,52 @@ fn is_truthy(obj: Object) -> bool {
 }
 
 #[inline(always)]
-fn new_error(format: String) -> Object {
-    Object::Error(format)
+fn new_error(kind: ErrorKind, format: String) -> Object {
+    Object::Error(RuntimeError::new(kind, format))
+}
+
+/// Gives an error that does not know where it happened the span of the
+/// expression being evaluated. Errors keep the innermost span they were
+/// given on their way out.
+#[inline(always)]
+fn locate_error(obj: Object, span: Span) -> Object {
+    match obj {
+        Object::Error(mut e) if !e.span.is_known() => {
+            e.span = span;
+            Object::Error(e)
+        }
+        _ => obj,
+    }
+}
+
+/// Passes a newly built value through if it is not nested too deeply and
+/// fits in the memory budget.
+fn reserved(obj: Object, env: &Environment) -> Object {
+    if let Err(e) = check_nesting(&obj) {
+        return e;
+    }
+    match env.reserve(obj.size()) {
+        Ok(()) => obj,
+        Err(e) => Object::Error(e),
+    }
+}
+
+fn check_nesting(obj: &Object) -> Result<(), Object> {
+    match obj.nesting() > MAX_NESTING {
+        true => Err(new_error(
+            ErrorKind::DepthLimit,
+            format!("maximum nesting depth of {} exceeded", MAX_NESTING),
+        )),
+        false => Ok(()),
+    }
+}
+
+#[inline(always)]
+fn overflow_checked(val: Option<i64>) -> Object {
+    match val {
+        Some(x) => Object::Integer(x),
+        None => new_error(ErrorKind::Overflow, "integer overflow".to_string()),
+    }
 }
 
 #[inline(always)]
@@ -442,42 +1298,252 @@ fn is_error(obj: Object) -> bool {
     obj.type_() == "ERROR".to_string()
 }
 
+fn eval_call(call: &CallExpression, env: &mut Environment) -> Object {
+    let (function, args) = eval_callee(call, env);
+    if is_error(function.clone()) {
+        return function;
+    }
+    call_function(function, args, call.token.span, env)
+}
+
+/// Applies a user-declared operator by calling the function bound to it.
+fn eval_operator_call(
+    oper: &str,
+    span: Span,
+    left: Object,
+    right: Object,
+    env: &mut Environment,
+) -> Object {
+    match env.get(oper) {
+        Some(function) => call_function(function, vec![left, right], span, env),
+        None => new_error(ErrorKind::Name, format!("operator not found: {}", oper)),
+    }
+}
+
+/// Applies `function` for a call made at `span`, adding that call to the
+/// stack of any error raised inside it.
+pub fn call_function(
+    function: Object,
+    args: Vec<Object>,
+    span: Span,
+    env: &mut Environment,
+) -> Object {
+    let name = match &function {
+        Object::Function(f) => f.name.clone(),
+        _ => return apply_function(function, args, env),
+    };
+    match apply_function(function, args, env) {
+        // Errors raised inside the body already carry a span; one
+        // without is about the call itself and belongs to the caller.
+        Object::Error(mut e) if e.span.is_known() => {
+            e.stack.push(Frame {
+                function: name.unwrap_or_else(|| "<anonymous>".to_string()),
+                span,
+            });
+            Object::Error(e)
+        }
+        res => res,
+    }
+}
+
+/// Evaluates the callee and arguments of a call. An error from either is
+/// returned in place of the callee.
+fn eval_callee(call: &CallExpression, env: &mut Environment) -> (Object, Vec<Object>) {
+    let function: Object;
+    match &call.func {
+        Some(x) => function = eval_expr(x, env),
+        None => {
+            function = new_error(
+                ErrorKind::Syntax,
+                "expected function got something else".to_string(),
+            )
+        }
+    }
+    if is_error(function.clone()) {
+        return (function, vec![]);
+    }
+    let args = match &call.args {
+        Some(x) => eval_exprs(x, env),
+        None => vec![],
+    };
+    if args.len() == 1 && is_error(args[0].clone()) {
+        return (args[0].clone(), vec![]);
+    }
+    (function, args)
+}
+
 fn apply_function(fun: Object, args: Vec<Object>, env: &mut Environment) -> Object {
-    match fun.clone() {
-        Object::Function(f) => {
-            let mut extended_env = extended_func_env(fun, args, env);
-            let evaluated = eval_block(f.body, &mut extended_env);
-            return unwrap_return_value(evaluated);
+    if let Object::Builtin(b) = fun {
+        return b.call(args, env);
+    }
+    if !matches!(fun, Object::Function(_)) {
+        return new_error(ErrorKind::Type, format!("not a function: {}", fun.type_()));
+    }
+    if let Err(e) = env.enter_call() {
+        return Object::Error(e);
+    }
+    let res = run_calls(fun, args, env);
+    env.exit_call();
+    res
+}
+
+/// Runs a call, then each call it makes in tail position, one after the
+/// other, so tail recursion uses no native stack and no call depth. Only
+/// the latest tail call is kept for tracebacks.
+fn run_calls(mut fun: Object, mut args: Vec<Object>, env: &mut Environment) -> Object {
+    // Where the current call was made from, once it is a tail call. The
+    // first call is located by its caller.
+    let mut span = Span::default();
+    let mut frame: Option<Frame> = None;
+    // The annotated return types the result must have: the current
+    // function's, and those of the functions that tail called it, each
+    // once with the latest last.
+    let mut returns: Vec<(String, Rc<TypeAnnotation>)> = vec![];
+    loop {
+        let f = match fun {
+            Object::Function(f) => f,
+            other => {
+                return locate_error(
+                    new_error(
+                        ErrorKind::Type,
+                        format!("not a function: {}", other.type_()),
+                    ),
+                    span,
+                )
+            }
+        };
+        if f.parameters.len() != args.len() {
+            return locate_error(
+                new_error(
+                    ErrorKind::Argument,
+                    format!(
+                        "wrong number of arguments: want={}, got={}",
+                        f.parameters.len(),
+                        args.len()
+                    ),
+                ),
+                span,
+            );
+        }
+        let name = f.name.clone().unwrap_or_else(|| "<anonymous>".to_string());
+        for ((param, ty), arg) in f.parameters.iter().zip(f.param_types.iter()).zip(&args) {
+            match (param, ty) {
+                (Expression::Identifier(p), Some(ty)) if !has_type(arg, ty) => {
+                    let message = format!(
+                        "argument {} of {} must be {}, got {}",
+                        p.value,
+                        name,
+                        ty,
+                        arg.type_()
+                    );
+                    return locate_error(new_error(ErrorKind::Type, message), span);
+                }
+                _ => (),
+            }
+        }
+        if let Some(ty) = &f.return_type {
+            // Functions tail calling each other add nothing new to check
+            // once each has been seen.
+            if let Some(i) = returns.iter().position(|(n, t)| *n == name && t == ty) {
+                returns.remove(i);
+            }
+            returns.push((name, ty.clone()));
+        }
+        let mut extended_env = match extended_func_env(&f, args, env) {
+            Ok(extended_env) => extended_env,
+            Err(e) => return locate_error(e, span),
+        };
+        notify(env, |hook| hook.enter_function(&f));
+        let result = eval_tail_block(&f.body, &mut extended_env);
+        notify(env, |hook| hook.exit_function(&f));
+        let evaluated = match result {
+            Object::TailCall(call) => {
+                fun = call.function;
+                args = call.args;
+                span = call.span;
+                if let Object::Function(g) = &fun {
+                    frame = Some(Frame {
+                        function: g.name.clone().unwrap_or_else(|| "<anonymous>".to_string()),
+                        span,
+                    });
+                }
+                continue;
+            }
+            Object::Break | Object::Continue => outside_loop_error(),
+            evaluated => unwrap_return_value(evaluated),
+        };
+        let mismatch = match evaluated {
+            Object::Error(_) => None,
+            _ => returns
+                .iter()
+                .rev()
+                .find(|(_, ty)| !has_type(&evaluated, ty)),
+        };
+        let evaluated = match mismatch {
+            Some((name, ty)) => new_error(
+                ErrorKind::Type,
+                format!("{} must return {}, got {}", name, ty, evaluated.type_()),
+            ),
+            None => evaluated,
+        };
+        return match (evaluated, frame) {
+            (Object::Error(mut e), Some(frame)) if e.span.is_known() => {
+                e.stack.push(frame);
+                Object::Error(e)
+            }
+            (evaluated, _) => evaluated,
+        };
+    }
+}
+
+/// Whether `obj` has the annotated type. A function only has to take as
+/// many parameters as the annotation lists; its own annotations are
+/// checked when it is called.
+fn has_type(obj: &Object, ty: &TypeAnnotation) -> bool {
+    match (ty, obj) {
+        (TypeAnnotation::Named(t), _) => match t.literal.as_str() {
+            "int" => matches!(obj, Object::Integer(_)),
+            "string" => matches!(obj, Object::String(_)),
+            "bool" => matches!(obj, Object::Boolean(_)),
+            "null" => matches!(obj, Object::Null),
+            "range" => matches!(obj, Object::Range(..)),
+            "error" => matches!(obj, Object::ErrorValue(_)),
+            _ => true,
+        },
+        (TypeAnnotation::Array(_, item), Object::Array(xs)) => xs.iter().all(|x| has_type(x, item)),
+        (TypeAnnotation::Hash(_, key, value), Object::Hash(pairs)) => pairs
+            .iter()
+            .all(|(k, v)| has_type(&k.to_object(), key) && has_type(v, value)),
+        (TypeAnnotation::Function(_, params, _), Object::Function(f)) => {
+            f.parameters.len() == params.len()
         }
-        _ => Object::Error(format!("not a function: {}", fun.type_())),
+        (TypeAnnotation::Function(_, params, _), Object::Builtin(b)) => b.arity() == params.len(),
+        _ => false,
     }
 }
 
-fn extended_func_env(fun: Object, args: Vec<Object>, env: &mut Environment) -> Environment {
-    match fun {
-        Object::Function(x) => {
-            let func = x;
-            let mut envex = env.new_enclosed(func.env);
-            for (index, param) in func.parameters.iter().enumerate() {
-                match param.clone() {
-                    Expression::Identifier(x) => {
-                        envex.set(x.value, args[index].clone());
-                    }
-                    Expression::IntegerLiteral(_) => todo!(),
-                    Expression::PrefixExpression(_) => todo!(),
-                    Expression::InfixExpression(_) => todo!(),
-                    Expression::Boolean(_) => todo!(),
-                    Expression::IfExpression(_) => todo!(),
-                    Expression::FunctionLiteral(_) => todo!(),
-                    Expression::CallExpression(_) => todo!(),
-                    Expression::StringLiteral(_) => todo!(),
+fn extended_func_env(
+    func: &Function,
+    args: Vec<Object>,
+    env: &mut Environment,
+) -> Result<Environment, Object> {
+    let mut envex = env.new_enclosed(func.env.clone());
+    for (param, arg) in func.parameters.iter().zip(args) {
+        match param {
+            Expression::Identifier(x) => {
+                if let Err(e) = envex.set(x.value.clone(), arg) {
+                    return Err(Object::Error(e));
                 }
             }
-            return envex;
+            other => {
+                return Err(new_error(
+                    ErrorKind::Type,
+                    format!("not a function parameter: {}", other.token().literal),
+                ))
+            }
         }
-        _ => (),
     }
-    Environment::new()
+    return Ok(envex);
 }
 
 fn unwrap_return_value(obj: Object) -> Object {
@@ -487,17 +1553,36 @@ fn unwrap_return_value(obj: Object) -> Object {
         Object::Null => obj,
         Object::Return(x) => *x,
         Object::Error(_) => obj,
+        Object::ErrorValue(_) => obj,
         Object::Function(_) => obj,
         Object::String(_) => obj,
+        Object::Break => obj,
+        Object::Continue => obj,
+        Object::Array(_) => obj,
+        Object::Hash(_) => obj,
+        Object::Range(_, _) => obj,
+        Object::TailCall(_) => obj,
+        Object::Quote(_) => obj,
+        Object::Macro(_) => obj,
+        Object::Builtin(_) => obj,
     }
 }
 
+#[inline(always)]
+fn outside_loop_error() -> Object {
+    new_error(
+        ErrorKind::Syntax,
+        "break or continue outside of a loop".to_string(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use crate::eval::*;
     use crate::lexer::Lexer;
-    use crate::object::Environment;
+    use crate::object::{Environment, Limits};
     use crate::parser::Parser;
+    use std::time::Duration;
 
     #[test]
     fn string_test() {
@@ -510,7 +1595,7 @@ mod tests {
         let mut lex = Lexer::init_lexer(input);
         let mut par = Parser::new(&mut lex);
         let program: Program = par.parse_program();
-        let evaluated = eval_prog(program, &mut env);
+        let evaluated = eval_prog(&program, &mut env);
         println!("{}", evaluated.inspect());
     }
 
@@ -526,7 +1611,7 @@ mod tests {
         let mut lex = Lexer::init_lexer(input);
         let mut par = Parser::new(&mut lex);
         let program: Program = par.parse_program();
-        let evaluated = eval_prog(program, &mut env);
+        let evaluated = eval_prog(&program, &mut env);
         println!("{}", evaluated.inspect());
     }
 
@@ -537,7 +1622,700 @@ mod tests {
         let mut lex = Lexer::init_lexer(input);
         let mut par = Parser::new(&mut lex);
         let program: Program = par.parse_program();
-        let evaluated = eval_prog(program, &mut env);
+        let evaluated = eval_prog(&program, &mut env);
         println!("{}", evaluated.inspect());
     }
+
+    #[test]
+    fn interpolation_test() {
+        let mut env = Environment::new();
+        let input = "let name = \"Ann\"; let count = 2;
+         return \"hello ${name}, you have ${count + 1} items\";";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        let evaluated = eval_prog(&program, &mut env);
+        assert_eq!(evaluated.inspect(), "hello Ann, you have 3 items");
+    }
+
+    #[test]
+    fn while_test() {
+        let mut env = Environment::new();
+        let input = "let i = 0; let sum = 0;
+         while (i < 10) {
+            let i = i + 1;
+            if (i == 5) { continue; }
+            if (i > 8) { break; }
+            let sum = sum + i;
+         }
+         return sum;";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        let evaluated = eval_prog(&program, &mut env);
+        assert_eq!(evaluated.inspect(), "31");
+    }
+
+    #[test]
+    fn break_outside_loop_test() {
+        let mut env = Environment::new();
+        let input = "let f = fn() { break; }; f();";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        let evaluated = eval_prog(&program, &mut env);
+        assert_eq!(evaluated.type_(), "ERROR");
+    }
+
+    #[test]
+    fn collections_test() {
+        let tests = [
+            ("return [1, 2 * 2, 3][1];", "4"),
+            ("return [1, 2][5];", "null"),
+            ("return \"abc\"[2];", "c"),
+            (
+                "let h = {\"b\": 2, \"a\": 1, 3: true}; return h[\"b\"];",
+                "2",
+            ),
+            ("return {\"b\": 2, \"a\": 1};", "{a: 1, b: 2}"),
+            ("return 2..5;", "2..5"),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn for_test() {
+        let tests = [
+            ("for (x in [1, 5, 7]) { if (x > 2) { return x; } }", "5"),
+            (
+                "for (c in \"abc\") { if (c == \"b\") { return c + c; } }",
+                "bb",
+            ),
+            ("for (k in {\"y\": 1, \"x\": 2}) { return k; }", "x"),
+            ("for (i in 0..100) { if (i * i > 50) { return i; } }", "8"),
+            (
+                "for (i in 0..3) { let j = i; } return j;",
+                "Error: identifier not found: j",
+            ),
+            ("for (i in 5) { }", "Error: cannot iterate over INTEGER_OBJ"),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn assign_test() {
+        let tests = [
+            ("let x = 1; x = x + 1; x += 10; return x;", "12"),
+            (
+                "let sum = 0; for (x in [1, 2, 3]) { sum += x; } return sum;",
+                "6",
+            ),
+            ("let i = 0; while (i < 5) { i += 1; } return i;", "5"),
+            (
+                "let xs = [1, 2, 3]; xs[1] = 20; xs[2] *= 3; return xs;",
+                "[1, 20, 9]",
+            ),
+            (
+                "let h = {\"a\": [1]}; h[\"a\"][0] = 5; h[\"b\"] = 2; return h;",
+                "{a: [5], b: 2}",
+            ),
+            ("let a = 1; let b = 2; a = b = 3; return a + b;", "6"),
+            ("y = 1;", "Error: assignment to undeclared identifier: y"),
+            ("[1][0] = 2;", "Error: invalid assignment target: [1]"),
+            ("let xs = [1]; xs[3] = 1;", "Error: index out of range: 3"),
+            (
+                "let xs = [1, 2, 3]; let i = 0; let g = fn() { i += 1; i };
+              xs[g()] += 10; return [xs, i];",
+                "[[1, 12, 3], 1]",
+            ),
+            (
+                "let m = [[1, 2], [3, 4]]; let n = 0; let g = fn() { n += 1; n - 1 };
+              m[g()][g()] *= 10; return [m, n];",
+                "[[[1, 20], [3, 4]], 2]",
+            ),
+            (
+                "let counter = fn() { let c = 0; fn() { c += 1; c } };
+              let next = counter(); next(); next(); return next();",
+                "3",
+            ),
+            (
+                "let x = 1; let f = fn() { let x = 5; x = 6; }; f(); return x;",
+                "1",
+            ),
+            (
+                "let fact = fn(n) { if (n < 2) { return 1; } return n * fact(n - 1); };
+              return fact(5);",
+                "120",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn error_test() {
+        let tests = [
+            ("1 / 0", ErrorKind::ZeroDivision, 3),
+            ("9223372036854775807 + 1", ErrorKind::Overflow, 21),
+            ("-true", ErrorKind::Type, 1),
+            ("nope + 1", ErrorKind::Name, 1),
+            ("let a = [1]; a[4] = 2", ErrorKind::Index, 19),
+            ("let f = fn(x) { x }; f(1, 2)", ErrorKind::Argument, 23),
+            ("let x = 1; break;", ErrorKind::Syntax, 12),
+            ("if (true) { continue; }", ErrorKind::Syntax, 1),
+        ];
+        for (input, kind, column) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            match eval_prog(&program, &mut env) {
+                Object::Error(e) => {
+                    assert_eq!(e.kind, kind, "{}", input);
+                    assert_eq!(e.span.line, 1, "{}", input);
+                    assert_eq!(e.span.column, column, "{}", input);
+                    assert!(e.stack.is_empty(), "{}", input);
+                }
+                other => panic!("{}: expected an error, got {}", input, other.inspect()),
+            }
+        }
+    }
+
+    #[test]
+    fn traceback_test() {
+        let input = "let inner = fn(x) {
+  x / 0
+};
+let outer = fn(y) { inner(y) };
+outer(1)";
+        let mut env = Environment::new();
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        let err = match eval_prog(&program, &mut env) {
+            Object::Error(e) => e,
+            other => panic!("expected an error, got {}", other.inspect()),
+        };
+        assert_eq!(
+            err.traceback(),
+            "Traceback (most recent call last):
+  at line 5, column 6, in outer
+  at line 4, column 26, in inner
+  at line 2, column 5
+ZeroDivisionError: division by zero"
+        );
+    }
+
+    #[test]
+    fn try_test() {
+        let tests = [
+            ("try { 1 } catch (e) { 2 }", "1"),
+            ("try { nope } catch (e) { e[\"kind\"] }", "NameError"),
+            (
+                "try { 1 / 0 } catch (e) { e[\"message\"] }",
+                "division by zero",
+            ),
+            (
+                "try { throw \"boom\"; 1 } catch (e) { e }",
+                "UserError: boom",
+            ),
+            ("try { throw 42 } catch (e) { e[\"message\"] }", "42"),
+            (
+                "let h = {}; let v = try { h[\"k\"] + 1 } catch (e) { 0 }; return v;",
+                "0",
+            ),
+            (
+                "let f = fn() { throw \"inner\" };
+              try { try { f() } catch (e) { throw e } } catch (e) { e[\"line\"] }",
+                "1",
+            ),
+            (
+                "try { nope } catch (e) { 1 }; e",
+                "Error: identifier not found: e",
+            ),
+            ("throw \"uncaught\";", "Error: uncaught"),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn type_annotation_test() {
+        let tests = [
+            ("let x: int = 5; x", "5"),
+            ("let x: int = \"5\";", "Error: x must be int, got STRING"),
+            ("let xs: [int] = [1, 2]; xs", "[1, 2]"),
+            (
+                "let xs: [int] = [1, \"2\"];",
+                "Error: xs must be [int], got ARRAY",
+            ),
+            ("let h: {string: any} = {\"a\": [1]}; h[\"a\"]", "[1]"),
+            (
+                "let f = fn(a: string, b: int) -> bool { a == \"x\" }; f(\"x\", 1)",
+                "true",
+            ),
+            (
+                "let f = fn(a: string, b: int) -> bool { true }; f(1, 1)",
+                "Error: argument a of f must be string, got INTEGER_OBJ",
+            ),
+            (
+                "let f = fn(n) -> int { \"n\" }; f(1)",
+                "Error: f must return int, got STRING",
+            ),
+            (
+                "let f = fn(n) -> int { if (n == 0) { return \"done\"; } f(n - 1) }; f(3)",
+                "Error: f must return int, got STRING",
+            ),
+            (
+                "let g = fn() { \"s\" }; let f = fn() -> int { g() }; f()",
+                "Error: f must return int, got STRING",
+            ),
+            (
+                "let even = fn(n) -> bool { if (n == 0) { true } else { odd(n - 1) } };
+                 let odd = fn(n) -> bool { if (n == 0) { false } else { even(n - 1) } };
+                 even(100001)",
+                "false",
+            ),
+            (
+                "let even = fn(n) -> bool { if (n == 0) { true } else { odd(n - 1) } };
+                 let odd = fn(n) -> bool { if (n == 0) { 0 } else { even(n - 1) } };
+                 even(101)",
+                "Error: odd must return bool, got INTEGER_OBJ",
+            ),
+            (
+                "let ap = fn(f: fn(int) -> int, x: int) { f(x) }; ap(fn(a, b) { a }, 1)",
+                "Error: argument f of ap must be fn(int) -> int, got FUNCTION",
+            ),
+            (
+                "let f = fn(e: error) -> string { e[\"kind\"] }; try { 1 / 0 } catch (e) { f(e) }",
+                "ZeroDivisionError",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rethrow_keeps_stack_test() {
+        let input = "let f = fn() { throw \"bad\" };
+try { f() } catch (e) { throw e }";
+        let mut env = Environment::new();
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        match eval_prog(&program, &mut env) {
+            Object::Error(e) => {
+                assert_eq!(e.kind, ErrorKind::User);
+                assert_eq!(e.stack.len(), 1);
+                assert_eq!(e.stack[0].function, "f");
+                assert_eq!((e.span.line, e.span.column), (1, 16));
+            }
+            other => panic!("expected an error, got {}", other.inspect()),
+        }
+    }
+
+    fn eval_limited(input: &str, limits: Limits) -> Object {
+        let mut env = Environment::with_limits(limits);
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        eval_prog(&program, &mut env)
+    }
+
+    #[test]
+    fn limits_test() {
+        let spin = "let i = 0; while (true) { i += 1; }";
+        let recurse = "let f = fn(n) { 1 + f(n + 1) }; f(0)";
+        let tests = [
+            (
+                spin,
+                Limits {
+                    max_steps: Some(1000),
+                    ..Limits::default()
+                },
+                ErrorKind::StepLimit,
+            ),
+            (
+                spin,
+                Limits {
+                    timeout: Some(Duration::from_millis(20)),
+                    ..Limits::default()
+                },
+                ErrorKind::Timeout,
+            ),
+            (
+                recurse,
+                Limits {
+                    max_depth: Some(20),
+                    ..Limits::default()
+                },
+                ErrorKind::DepthLimit,
+            ),
+        ];
+        for (input, limits, kind) in tests {
+            match eval_limited(input, limits) {
+                Object::Error(e) => assert_eq!(e.kind, kind, "{}", input),
+                other => panic!("{}: expected an error, got {}", input, other.inspect()),
+            }
+        }
+
+        let limits = Limits {
+            max_depth: Some(20),
+            ..Limits::default()
+        };
+        let caught = "let f = fn(n) { 1 + f(n + 1) };
+          let k = try { f(0) } catch (e) { e[\"kind\"] };
+          let g = fn(n) { if (n == 0) { return 0; } return g(n - 1); };
+          return k + \" \" + \"${g(15)}\";";
+        assert_eq!(eval_limited(caught, limits).inspect(), "RecursionError 0");
+
+        let limits = Limits {
+            max_steps: Some(50),
+            ..Limits::default()
+        };
+        let exhausted = "try { while (true) { 1 } } catch (e) { 0 }";
+        match eval_limited(exhausted, limits) {
+            Object::Error(e) => assert_eq!(e.kind, ErrorKind::StepLimit),
+            other => panic!("expected the step limit to hold, got {}", other.inspect()),
+        }
+
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        };
+        let escape = "let spin = fn() { while (true) { } };
+          while (true) { try { spin() } catch (e) { } }";
+        let started = std::time::Instant::now();
+        match eval_limited(escape, limits) {
+            Object::Error(e) => assert_eq!(e.kind, ErrorKind::Timeout),
+            other => panic!("expected the time limit to hold, got {}", other.inspect()),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn default_depth_limit_test() {
+        let runaway = std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(
+                || match eval_limited("let f = fn() { 1 + f() }; f()", Limits::default()) {
+                    Object::Error(e) => {
+                        assert_eq!(e.kind, ErrorKind::DepthLimit);
+                        assert!(e
+                            .traceback()
+                            .contains("[previous frame repeated 198 more times]"));
+                    }
+                    other => panic!("expected an error, got {}", other.inspect()),
+                },
+            )
+            .unwrap();
+        runaway.join().unwrap();
+    }
+
+    #[test]
+    fn nesting_limit_test() {
+        let nested = std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(|| {
+                let limits = Limits {
+                    max_steps: Some(5_000_000),
+                    max_memory: Some(64 * 1024 * 1024),
+                    ..Limits::default()
+                };
+                let tests = [
+                    "let a = []; let i = 0; while (i < 200000) { a = [a]; i += 1; }",
+                    "let h = {}; let i = 0; while (i < 200000) { h = {\"h\": h}; i += 1; }",
+                    "let a = [0]; let i = 0; while (i < 200000) { a[0] = a; i += 1; }",
+                ];
+                for input in tests {
+                    match eval_limited(input, limits) {
+                        Object::Error(e) => assert_eq!(e.kind, ErrorKind::DepthLimit, "{}", input),
+                        other => panic!("{}: expected an error, got {}", input, other.inspect()),
+                    }
+                }
+                let caught = "let a = []; let i = 0;
+                  let n = try { while (true) { a = [a]; i += 1; } } catch (e) { i };
+                  [n, \"${a}\" == \"${a}\"]";
+                assert_eq!(eval_limited(caught, limits).inspect(), "[999, true]");
+            })
+            .unwrap();
+        nested.join().unwrap();
+    }
+
+    #[test]
+    fn memory_limit_test() {
+        let limits = Limits {
+            max_memory: Some(64 * 1024),
+            ..Limits::default()
+        };
+        let tests = [
+            "let s = \"x\"; while (true) { s = s + s; }",
+            "let s = \"x\"; while (true) { s = \"${s}${s}\"; }",
+            "let h = {}; let i = 0; while (true) { h[i] = i; i += 1; }",
+            "let xs = [\"abc\"]; while (true) { xs = [xs, xs]; }",
+            "let wrap = fn(h) { fn() { h() } }; let g = wrap(0); while (true) { g = wrap(g); }",
+        ];
+        for input in tests {
+            match eval_limited(input, limits) {
+                Object::Error(e) => {
+                    assert_eq!(e.kind, ErrorKind::Memory, "{}", input);
+                    assert_eq!(e.message, "out of memory budget of 65536 bytes");
+                }
+                other => panic!("{}: expected an error, got {}", input, other.inspect()),
+            }
+        }
+
+        // Scopes give their bindings back when they go out of use, so a
+        // loop that builds and drops values stays within the budget.
+        let churn = "let f = fn(n) { let s = \"${n}\" + \"....................\"; s };
+          let i = 0; while (i < 5000) { let t = f(i); i += 1; } return i;";
+        assert_eq!(eval_limited(churn, limits).inspect(), "5000");
+
+        // A closure bound in the scope it closes over must not keep that
+        // scope alive once the call returns.
+        let closures = "let f = fn(n) { let g = fn() { n }; g() };
+          let i = 0; while (i < 5000) { f(i); i += 1; } \"a\" + \"b\"";
+        let mut env = Environment::with_limits(limits);
+        let mut lex = Lexer::init_lexer(closures);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        assert_eq!(eval_prog(&program, &mut env).inspect(), "ab");
+        assert!(env.memory_used() < 1024, "{}", env.memory_used());
+    }
+
+    #[test]
+    fn memory_accounting_test() {
+        let mut env = Environment::new();
+        let mut lex =
+            Lexer::init_lexer("let s = \"${1000}\"; let f = fn() { let t = s + s; t }; f();");
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        eval_prog(&program, &mut env);
+        let held = env.memory_used();
+        assert!(held >= 2 * std::mem::size_of::<Object>() + 5, "{}", held);
+
+        let mut lex = Lexer::init_lexer("s = \"\";");
+        let mut par = Parser::new(&mut lex);
+        eval_prog(&par.parse_program(), &mut env);
+        assert_eq!(env.memory_used(), held - 4);
+    }
+
+    #[test]
+    fn tail_call_test() {
+        let tests = [
+            (
+                "let count = fn(n, acc) { if (n == 0) { return acc; } return count(n - 1, acc + 1); };
+              count(1000000, 0)",
+                "1000000",
+            ),
+            (
+                "let down = fn(n) { if (n == 0) { \"done\" } else { down(n - 1) } }; down(100000)",
+                "done",
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+              let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+              even(100001)",
+                "false",
+            ),
+            (
+                "let sum = fn(xs, i, acc) { if (i == 3) { return acc; } let x = xs[i]; sum(xs, i + 1, acc + x) };
+              sum([1, 2, 3], 0, 0)",
+                "6",
+            ),
+            (
+                "let f = fn(n) { return 1 / n; };
+              let g = fn() { try { return f(0); } catch (e) { return e[\"kind\"]; } };
+              g()",
+                "ZeroDivisionError",
+            ),
+            ("let f = fn(a) { a }; let g = fn() { f(1, 2) }; g()",
+                "Error: wrong number of arguments: want=1, got=2"),
+            ("let f = fn(n) { n * 2 }; return f(21);", "42"),
+            (
+                "let down = fn(n) { if (n > 0) { return down(n - 1); } \"done\" }; down(100000)",
+                "done",
+            ),
+            (
+                "let f = fn(n) { n }; let y = if (true) { return f(1); } else { 2 }; y + 10",
+                "1",
+            ),
+            (
+                "let f = fn(n) { n };
+              let g = fn(c) { let y = if (c) { return f(1); } else { 2 }; y + 10 };
+              [g(true), g(false)]",
+                "[1, 12]",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn user_operator_test() {
+        let tests = vec![
+            (
+                "infixl 5 <+> = fn(a, b) { [a[0] + b[0], a[1] + b[1]] };
+              [1, 2] <+> [3, 4] <+> [10, 20]",
+                "[14, 26]",
+            ),
+            (
+                "infixr 6 ^ = fn(a, b) { if (b == 0) { 1 } else { a * (a ^ (b - 1)) } };
+              2 ^ 3 ^ 2",
+                "512",
+            ),
+            ("infixl 6 -- = fn(a, b) { a - b }; 10 -- 3 -- 2", "5"),
+            (
+                "let f = fn() { infixl 5 <+> = fn(a, b) { a }; 1 }; f(); 1 <+> 2",
+                "Error: operator not found: <+>",
+            ),
+            (
+                "infixl 5 <+> = fn(a) { a };",
+                "Error: operator <+> must take 2 parameters, got 1",
+            ),
+            (
+                "infixl 5 <+> = 1;",
+                "Error: operator <+> must be a function, got INTEGER_OBJ",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut env = Environment::new();
+            let mut lex = Lexer::init_lexer(input);
+            let mut par = Parser::new(&mut lex);
+            let program: Program = par.parse_program();
+            assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+            assert_eq!(
+                eval_prog(&program, &mut env).inspect(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        let input = "infixl 4 %% = fn(a, b) { a / b };\n1 %% 0";
+        let mut env = Environment::new();
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program: Program = par.parse_program();
+        match eval_prog(&program, &mut env) {
+            Object::Error(e) => {
+                assert_eq!(e.stack.len(), 1);
+                assert_eq!(e.stack[0].function, "%%");
+                assert_eq!((e.stack[0].span.line, e.stack[0].span.column), (2, 3));
+            }
+            other => panic!("expected error, got {}", other.inspect()),
+        }
+    }
+
+    #[test]
+    fn invalid_parameter_test() {
+        // The parser only makes identifier parameters, so build the
+        // function by hand.
+        let mut lex = Lexer::init_lexer("fn(x) { x }; 1");
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        let mut env = Environment::new();
+        let mut f = match eval_prog(&program[..1].to_vec(), &mut env) {
+            Object::Function(f) => f,
+            other => panic!("expected function, got {}", other.inspect()),
+        };
+        let literal = match &program[1] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            other => panic!("expected expression, got {}", other.print()),
+        };
+        f.parameters = Rc::new(vec![literal.as_ref().clone()]);
+        let result = call_function(
+            Object::Function(f),
+            vec![Object::Integer(2)],
+            Span::default(),
+            &mut env,
+        );
+        match result {
+            Object::Error(e) => assert_eq!(e.to_string(), "TypeError: not a function parameter: 1"),
+            other => panic!("expected error, got {}", other.inspect()),
+        }
+    }
+
+    /// Times making closures, whose literals are evaluated on every call.
+    /// Run with `cargo test --release closure_benchmark -- --ignored
+    /// --nocapture`.
+    #[test]
+    #[ignore]
+    fn closure_benchmark() {
+        let input = "let adder = fn(x: int) {
+            fn(a: int, b: int, c: int, d: int) -> int { x + a + b + c + d }
+          };
+          let fib = fn(n) { if (n < 2) { n } else { adder(fib(n - 1))(fib(n - 2), 0, 0, 0) } };
+          fib(24)";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        let started = std::time::Instant::now();
+        let result = eval_prog(&program, &mut Environment::new());
+        println!("fib(24) with closures: {:?}", started.elapsed());
+        assert_eq!(result.inspect(), "46368");
+    }
 }
@@ -1,4 +1,4 @@
-use crate::token::{Token, TokenType};
+use crate::token::{Comment, Span, Token, TokenType};
 use std::collections::HashMap;
 use std::str;
 
@@ -10,6 +10,13 @@ pub struct Lexer<'a> {
     pub ch: char,
     pub keywords: HashMap<&'static str, TokenType>,
     pub end: bool,
+    line: usize,
+    line_start: usize,
+    scanned: usize,
+    origin: Span,
+    operators: Vec<String>,
+    comments: Vec<Comment>,
+    errors: Vec<(String, Span)>,
 }
 
 impl Lexer<'_> {
@@ -19,14 +26,22 @@ impl Lexer<'_> {
             input: input,
             position: 0,
             read_position: 1,
-            ch: input.as_bytes()[0] as char,
+            ch: input.bytes().next().unwrap_or(0) as char,
             keywords: key,
-            end: false,
+            end: input.is_empty(),
+            line: 1,
+            line_start: 0,
+            scanned: 0,
+            origin: Span::default(),
+            operators: Vec::new(),
+            comments: Vec::new(),
+            errors: Vec::new(),
         }
     }
     pub fn read_char(&mut self) -> () {
         if self.read_position >= self.input.len() {
             self.end = true;
+            self.ch = '\0';
             return;
         } else {
             self.ch = self.input.as_bytes()[self.read_position] as char;
@@ -39,12 +54,27 @@ impl Lexer<'_> {
         let mut tok: Token = Token {
             type_: TokenType::UNDEFINED,
             literal: "".to_string(),
+            span: Span::default(),
         };
         if self.end == true {
-            return new_token(TokenType::EOF, "\0");
+            return self.eof_token();
         }
 
         self.skip_whitespace();
+        while self.ch == '/' && self.peek_char() == '/' && !self.end {
+            self.read_comment();
+            self.skip_whitespace();
+        }
+        if self.end {
+            return self.eof_token();
+        }
+        let start = self.position;
+        if let Some(op) = self.read_operator() {
+            let mut tok = new_token(TokenType::OPERATOR, &op);
+            tok.span = self.span(start, self.position + 1);
+            self.read_char();
+            return tok;
+        }
         match self.ch {
             '=' => {
                 if self.peek_char() == '=' {
@@ -54,7 +84,17 @@ impl Lexer<'_> {
                     tok = new_token(TokenType::ASSIGN, "=")
                 }
             }
-            '-' => tok = new_token(TokenType::MINUS, "-"),
+            '-' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    tok = new_token(TokenType::MINUSASSIGN, "-=");
+                } else if self.peek_char() == '>' {
+                    self.read_char();
+                    tok = new_token(TokenType::ARROW, "->");
+                } else {
+                    tok = new_token(TokenType::MINUS, "-");
+                }
+            }
             '!' => {
                 if self.peek_char() == '=' {
                     self.read_char();
@@ -63,23 +103,60 @@ impl Lexer<'_> {
                     tok = new_token(TokenType::BANG, "!");
                 }
             }
-            '/' => tok = new_token(TokenType::SLASH, "/"),
-            '*' => tok = new_token(TokenType::ASTERICK, "*"),
+            '/' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    tok = new_token(TokenType::SLASHASSIGN, "/=");
+                } else {
+                    tok = new_token(TokenType::SLASH, "/");
+                }
+            }
+            '*' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    tok = new_token(TokenType::ASTERICKASSIGN, "*=");
+                } else {
+                    tok = new_token(TokenType::ASTERICK, "*");
+                }
+            }
             '<' => tok = new_token(TokenType::LT, "<"),
             '>' => tok = new_token(TokenType::GT, ">"),
             ';' => tok = new_token(TokenType::SEMICOLON, ";"),
             '(' => tok = new_token(TokenType::LPAREN, "("),
             ')' => tok = new_token(TokenType::RPAREN, ")"),
             ',' => tok = new_token(TokenType::COMMA, ","),
-            '+' => tok = new_token(TokenType::PLUS, "+"),
+            '+' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    tok = new_token(TokenType::PLUSASSIGN, "+=");
+                } else {
+                    tok = new_token(TokenType::PLUS, "+");
+                }
+            }
             '{' => tok = new_token(TokenType::LBRACE, "{"),
             '}' => tok = new_token(TokenType::RBRACE, "}"),
+            '[' => tok = new_token(TokenType::LBRACKET, "["),
+            ']' => tok = new_token(TokenType::RBRACKET, "]"),
+            ':' => tok = new_token(TokenType::COLON, ":"),
+            '.' if self.peek_char() == '.' => {
+                self.read_char();
+                tok = new_token(TokenType::DOTDOT, "..");
+            }
             '"' => {
-                tok.type_ = TokenType::STRING;
-                tok.literal = self.read_string();
+                let (literal, template) = self.read_string();
+                if self.end {
+                    let span = self.span(start, self.input.len());
+                    self.errors.push(("unterminated string".to_string(), span));
+                }
+                if template {
+                    tok.type_ = TokenType::TEMPLATE;
+                } else {
+                    tok.type_ = TokenType::STRING;
+                }
+                tok.literal = literal;
             }
             _default => {
-                if self.ch.is_alphabetic() {
+                if self.ch.is_alphabetic() || self.ch == '_' {
                     tok.literal = self.read_identifier();
                     tok.type_ = self.lookup_identifier(tok.literal.as_str());
                 } else if self.is_num() {
@@ -89,23 +166,150 @@ impl Lexer<'_> {
                     tok = Token {
                         type_: TokenType::ILLEGAL,
                         literal: self.ch.to_string(),
+                        span: Span::default(),
                     };
                 }
             }
         }
+        tok.span = self.span(start, self.position + 1);
         self.read_char();
         tok
     }
 
+    fn eof_token(&mut self) -> Token {
+        let mut tok = new_token(TokenType::EOF, "\0");
+        tok.span = self.span(self.input.len(), self.input.len());
+        tok
+    }
+
+    /// Builds the span for `start..end`. Token starts only move forward, so
+    /// newlines are counted incrementally from the previous token.
+    fn span(&mut self, start: usize, end: usize) -> Span {
+        let bytes = self.input.as_bytes();
+        while self.scanned < start && self.scanned < bytes.len() {
+            if bytes[self.scanned] == b'\n' {
+                self.line += 1;
+                self.line_start = self.scanned + 1;
+            }
+            self.scanned += 1;
+        }
+        let column = start - self.line_start + 1;
+        if !self.origin.is_known() {
+            return Span {
+                start,
+                end,
+                line: self.line,
+                column,
+            };
+        }
+        Span {
+            start: self.origin.start + start,
+            end: self.origin.start + end,
+            line: self.origin.line + self.line - 1,
+            column: if self.line == 1 {
+                self.origin.column + column - 1
+            } else {
+                column
+            },
+        }
+    }
+
+    /// Makes spans relative to `origin`, for lexing a fragment that is
+    /// embedded in a larger source, such as an interpolated expression.
+    pub fn set_origin(&mut self, origin: Span) {
+        self.origin = origin;
+    }
+
+    /// Lexes `symbol` as a single `OPERATOR` token. Longer symbols are tried
+    /// first, so `<+>` wins over a registered `<+`.
+    pub fn add_operator(&mut self, symbol: &str) {
+        if self.operators.iter().any(|op| op == symbol) {
+            return;
+        }
+        self.operators.push(symbol.to_string());
+        self.operators.sort_by_key(|op| std::cmp::Reverse(op.len()));
+    }
+
+    /// Moves back to the token starting at `start`, a position as reported in
+    /// its span, so the following tokens are lexed again.
+    pub fn rewind(&mut self, start: usize) {
+        let local = if self.origin.is_known() {
+            start - self.origin.start
+        } else {
+            start
+        };
+        self.position = local;
+        self.read_position = local + 1;
+        self.end = local >= self.input.len();
+        self.ch = self.input.as_bytes().get(local).copied().unwrap_or(0) as char;
+        self.line = 1;
+        self.line_start = 0;
+        self.scanned = 0;
+        self.comments.retain(|c| c.span.start < start);
+        self.errors.retain(|(_, span)| span.start < start);
+    }
+
+    /// The comments skipped so far, in source order.
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Takes the errors found since the last call, such as unterminated strings.
+    pub fn take_errors(&mut self) -> Vec<(String, Span)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn read_comment(&mut self) {
+        let start = self.position;
+        while self.ch != '\n' && !self.end {
+            self.read_char();
+        }
+        let end = if self.end {
+            self.input.len()
+        } else {
+            self.position
+        };
+        let own_line = self.input[..start]
+            .rsplit('\n')
+            .next()
+            .is_some_and(|before| before.trim().is_empty());
+        let span = self.span(start, end);
+        self.comments.push(Comment {
+            text: self.input[start..end].trim_end().to_string(),
+            span,
+            own_line,
+        });
+    }
+
+    fn read_operator(&mut self) -> Option<String> {
+        let rest = &self.input.as_bytes()[self.position..];
+        let op = self
+            .operators
+            .iter()
+            .find(|op| rest.starts_with(op.as_bytes()))?
+            .clone();
+        for _ in 1..op.len() {
+            self.read_char();
+        }
+        Some(op)
+    }
+
     fn read_identifier(&mut self) -> String {
         let pos = self.position;
-        while self.ch.is_ascii_alphanumeric() {
+        while self.ch.is_ascii_alphanumeric() || self.ch == '_' {
             self.read_char();
         }
-        let buf = &(self.input.as_bytes()[pos..self.position]);
+        let end = if self.end {
+            self.input.len()
+        } else {
+            self.position
+        };
+        let buf = &(self.input.as_bytes()[pos..end]);
         let x = std::str::from_utf8(buf);
-        self.position -= 1;
-        self.read_position -= 1;
+        if !self.end {
+            self.position -= 1;
+            self.read_position -= 1;
+        }
         match x {
             Ok(s) => return s.to_string(),
             Err(err) => panic!("Paniced read_identifier: {}", err),
@@ -117,35 +321,63 @@ impl Lexer<'_> {
         while self.is_num() {
             self.read_char();
         }
-        let buf = &(self.input.as_bytes()[pos..self.position]);
+        let end = if self.end {
+            self.input.len()
+        } else {
+            self.position
+        };
+        let buf = &(self.input.as_bytes()[pos..end]);
         let x = std::str::from_utf8(buf);
-        self.position -= 1;
-        self.read_position -= 1;
+        if !self.end {
+            self.position -= 1;
+            self.read_position -= 1;
+        }
         match x {
             Ok(s) => return s.to_string(),
             Err(err) => panic!("Paniced read_number: {}", err),
         }
     }
 
-    fn read_string(&mut self) -> String {
+    /// Reads a string literal and reports whether it contains `${...}`
+    /// interpolations. Quotes and braces inside an interpolation belong to the
+    /// embedded expression, so they do not terminate the string.
+    fn read_string(&mut self) -> (String, bool) {
         let position = self.position + 1;
+        let mut template = false;
+        let mut depth = 0;
         self.read_char();
         loop {
-            if self.ch == '"' || self.end {
+            if self.end || (depth == 0 && self.ch == '"') {
                 break;
             }
+            if self.ch == '$' && self.peek_char() == '{' {
+                template = true;
+                depth += 1;
+                self.read_char();
+            } else if depth > 0 && self.ch == '{' {
+                depth += 1;
+            } else if depth > 0 && self.ch == '}' {
+                depth -= 1;
+            } else if depth > 0 && self.ch == '"' {
+                self.read_char();
+                while self.ch != '"' && !self.end {
+                    self.read_char();
+                }
+            }
             self.read_char();
         }
-        let buf = &(self.input.as_bytes()[position..self.position]);
-        let x = std::str::from_utf8(buf);
+        // An unterminated string runs to the end of the input, where
+        // `position` still points at the start of the last character.
+        let end = if self.end {
+            self.input.len()
+        } else {
+            self.position
+        };
         //INFO: the below were removed since the in the next_token function
         //after the match statement it would read_char and the end quote would be consumed
         //self.position -= 1;
         //self.read_position -= 1;
-        match x {
-            Ok(s) => return s.to_string(),
-            Err(err) => panic!("Paniced read_string: {}", err),
-        }
+        (self.input[position.min(end)..end].to_string(), template)
     }
 
     fn is_num(&mut self) -> bool {
@@ -181,10 +413,11 @@ fn new_token<'a>(token_type: TokenType, literal: &'a str) -> Token {
     Token {
         type_: token_type,
         literal: literal.to_string(),
+        span: Span::default(),
     }
 }
 
-fn generate_keywords() -> HashMap<&'static str, TokenType> {
+pub fn generate_keywords() -> HashMap<&'static str, TokenType> {
     let keywords = HashMap::from([
         ("fn", TokenType::FUNCTION),
         ("let", TokenType::LET),
@@ -193,6 +426,17 @@ fn generate_keywords() -> HashMap<&'static str, TokenType> {
         ("if", TokenType::IF),
         ("else", TokenType::ELSE),
         ("return", TokenType::RETURN),
+        ("while", TokenType::WHILE),
+        ("break", TokenType::BREAK),
+        ("continue", TokenType::CONTINUE),
+        ("for", TokenType::FOR),
+        ("in", TokenType::IN),
+        ("throw", TokenType::THROW),
+        ("try", TokenType::TRY),
+        ("catch", TokenType::CATCH),
+        ("infixl", TokenType::INFIXL),
+        ("infixr", TokenType::INFIXR),
+        ("macro", TokenType::MACRO),
     ]);
     keywords
 }
@@ -238,6 +482,7 @@ mod tests {
         ve.push(Token {
             type_: TokenType::EOF,
             literal: "EOF".to_string(),
+            span: Span::default(),
         });
         //println!("{:?}", ve);
         for index in 0..lex.input.len() {
@@ -306,6 +551,7 @@ mod tests {
         ve.push(Token {
             type_: TokenType::EOF,
             literal: "\0".to_string(),
+            span: Span::default(),
         });
         // for item in &ve {
         //     println!("{:?}", item);
@@ -318,6 +564,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LET,
                         literal: "let".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -325,6 +572,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "five".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -332,6 +580,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ASSIGN,
                         literal: "=".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -339,6 +588,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "5".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -346,6 +596,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -353,6 +604,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LET,
                         literal: "let".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -360,6 +612,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "ten".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -367,6 +620,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ASSIGN,
                         literal: "=".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -374,6 +628,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -381,6 +636,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -388,6 +644,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LET,
                         literal: "let".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -395,6 +652,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "add".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -402,6 +660,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ASSIGN,
                         literal: "=".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -409,6 +668,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::FUNCTION,
                         literal: "fn".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -416,6 +676,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LPAREN,
                         literal: "(".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -423,6 +684,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "x".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -430,6 +692,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::COMMA,
                         literal: ",".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -437,6 +700,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "y".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -444,6 +708,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RPAREN,
                         literal: ")".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -451,6 +716,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LBRACE,
                         literal: "{".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -458,6 +724,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "x".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -465,6 +732,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::PLUS,
                         literal: "+".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -472,6 +740,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "y".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -479,6 +748,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -486,6 +756,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RBRACE,
                         literal: "}".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -493,6 +764,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -500,6 +772,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LET,
                         literal: "let".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -507,6 +780,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "result".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -514,6 +788,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ASSIGN,
                         literal: "=".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -521,6 +796,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "add".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -528,6 +804,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LPAREN,
                         literal: "(".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -535,6 +812,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "five".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -542,6 +820,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::COMMA,
                         literal: ",".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -549,6 +828,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IDENT,
                         literal: "ten".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -556,6 +836,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RPAREN,
                         literal: ")".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -563,6 +844,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -570,6 +852,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::EOF,
                         literal: "\0".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -606,6 +889,7 @@ mod tests {
         ve.push(Token {
             type_: TokenType::EOF,
             literal: "\0".to_string(),
+            span: Span::default(),
         });
 
         //print!("{}\n", ve.len());
@@ -624,6 +908,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::BANG,
                         literal: "!".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -631,6 +916,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::MINUS,
                         literal: "-".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -638,6 +924,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SLASH,
                         literal: "/".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -645,6 +932,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ASTERICK,
                         literal: "*".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -652,6 +940,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "5".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -659,6 +948,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -666,6 +956,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "5".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true);
                 }
@@ -673,6 +964,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LT,
                         literal: "<".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -680,6 +972,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -687,6 +980,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::GT,
                         literal: ">".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -694,6 +988,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "5".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -701,6 +996,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -708,6 +1004,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::IF,
                         literal: "if".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -715,6 +1012,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LPAREN,
                         literal: "(".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -722,6 +1020,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "5".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -729,6 +1028,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LT,
                         literal: "<".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -736,6 +1036,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -743,6 +1044,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RPAREN,
                         literal: ")".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -750,6 +1052,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LBRACE,
                         literal: "{".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -757,6 +1060,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RETURN,
                         literal: "return".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -764,6 +1068,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::TRUE,
                         literal: "true".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -771,6 +1076,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -778,6 +1084,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RBRACE,
                         literal: "}".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -785,6 +1092,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::ELSE,
                         literal: "else".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -792,6 +1100,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::LBRACE,
                         literal: "{".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -799,6 +1108,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RETURN,
                         literal: "return".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -806,6 +1116,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::FALSE,
                         literal: "false".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -813,6 +1124,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -820,6 +1132,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::RBRACE,
                         literal: "}".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -827,6 +1140,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -834,6 +1148,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::EQ,
                         literal: "==".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -841,6 +1156,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -848,6 +1164,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -855,6 +1172,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "10".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -862,6 +1180,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::NEQ,
                         literal: "!=".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -869,6 +1188,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::INT,
                         literal: "9".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -876,6 +1196,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::SEMICOLON,
                         literal: ";".to_string(),
+                        span: Span::default(),
                     };
                     assert_eq!(ve[index] == tok, true)
                 }
@@ -883,6 +1204,7 @@ mod tests {
                     tok = Token {
                         type_: TokenType::EOF,
                         literal: "\0".to_string(),
+                        span: Span::default(),
                     };
                     //print!("\t{:?}", ve[index]);
                     assert_eq!(ve[index] == tok, true);
@@ -894,4 +1216,230 @@ mod tests {
             // println!("{:?}", tok);
         }
     }
+
+    #[test]
+    fn test_template_string() {
+        let s = "\"hi ${name}\"; \"sum ${add(1, \"}\")}\"; \"$5 {x}\";";
+        let mut lex = Lexer::init_lexer(s);
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        assert_eq!(ve[0].type_, TokenType::TEMPLATE);
+        assert_eq!(ve[0].literal, "hi ${name}");
+        assert_eq!(ve[1].type_, TokenType::SEMICOLON);
+        assert_eq!(ve[2].type_, TokenType::TEMPLATE);
+        assert_eq!(ve[2].literal, "sum ${add(1, \"}\")}");
+        assert_eq!(ve[4].type_, TokenType::STRING);
+        assert_eq!(ve[4].literal, "$5 {x}");
+    }
+
+    #[test]
+    fn test_collections_and_ranges() {
+        let s = "for (x in [1, 2]) {} {\"a\": 0..n}.";
+        let mut lex = Lexer::init_lexer(s);
+        let mut ve = Vec::<TokenType>::new();
+        while !lex.end {
+            ve.push(lex.next_token().type_);
+        }
+        assert_eq!(
+            ve,
+            vec![
+                TokenType::FOR,
+                TokenType::LPAREN,
+                TokenType::IDENT,
+                TokenType::IN,
+                TokenType::LBRACKET,
+                TokenType::INT,
+                TokenType::COMMA,
+                TokenType::INT,
+                TokenType::RBRACKET,
+                TokenType::RPAREN,
+                TokenType::LBRACE,
+                TokenType::RBRACE,
+                TokenType::LBRACE,
+                TokenType::STRING,
+                TokenType::COLON,
+                TokenType::INT,
+                TokenType::DOTDOT,
+                TokenType::IDENT,
+                TokenType::RBRACE,
+                TokenType::ILLEGAL,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assignment_operators() {
+        let s = "x = 1; x += 2; x -= 3; x *= 4; x /= 5; x == 6;";
+        let mut lex = Lexer::init_lexer(s);
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        let ops: Vec<TokenType> = ve.iter().skip(1).step_by(4).map(|t| t.type_).collect();
+        assert_eq!(
+            ops,
+            vec![
+                TokenType::ASSIGN,
+                TokenType::PLUSASSIGN,
+                TokenType::MINUSASSIGN,
+                TokenType::ASTERICKASSIGN,
+                TokenType::SLASHASSIGN,
+                TokenType::EQ,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let mut lex = Lexer::init_lexer("fn(a: int) -> [int] { a - -1 }");
+        let mut ve = Vec::<TokenType>::new();
+        while !lex.end {
+            ve.push(lex.next_token().type_);
+        }
+        assert_eq!(
+            ve,
+            vec![
+                TokenType::FUNCTION,
+                TokenType::LPAREN,
+                TokenType::IDENT,
+                TokenType::COLON,
+                TokenType::IDENT,
+                TokenType::RPAREN,
+                TokenType::ARROW,
+                TokenType::LBRACKET,
+                TokenType::IDENT,
+                TokenType::RBRACKET,
+                TokenType::LBRACE,
+                TokenType::IDENT,
+                TokenType::MINUS,
+                TokenType::MINUS,
+                TokenType::INT,
+                TokenType::RBRACE,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_underscore_identifiers() {
+        let mut lex = Lexer::init_lexer("test_add _x assert_eq(a_1)");
+        let mut ve = Vec::<(TokenType, String)>::new();
+        while !lex.end {
+            let tok = lex.next_token();
+            ve.push((tok.type_, tok.literal));
+        }
+        assert_eq!(
+            ve,
+            vec![
+                (TokenType::IDENT, "test_add".to_string()),
+                (TokenType::IDENT, "_x".to_string()),
+                (TokenType::IDENT, "assert_eq".to_string()),
+                (TokenType::LPAREN, "(".to_string()),
+                (TokenType::IDENT, "a_1".to_string()),
+                (TokenType::RPAREN, ")".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registered_operators() {
+        let mut lex = Lexer::init_lexer("a <+> b <+ c < d");
+        lex.add_operator("<+");
+        lex.add_operator("<+>");
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        let toks: Vec<(TokenType, &str)> =
+            ve.iter().map(|t| (t.type_, t.literal.as_str())).collect();
+        assert_eq!(
+            toks,
+            vec![
+                (TokenType::IDENT, "a"),
+                (TokenType::OPERATOR, "<+>"),
+                (TokenType::IDENT, "b"),
+                (TokenType::OPERATOR, "<+"),
+                (TokenType::IDENT, "c"),
+                (TokenType::LT, "<"),
+                (TokenType::IDENT, "d"),
+            ]
+        );
+        assert_eq!(ve[3].span.column, 9);
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut lex = Lexer::init_lexer("let s = \"é");
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        assert_eq!(ve[3].type_, TokenType::STRING);
+        assert_eq!(ve[3].literal, "é");
+        let errors: Vec<(String, usize, usize)> = lex
+            .take_errors()
+            .into_iter()
+            .map(|(message, span)| (message, span.line, span.column))
+            .collect();
+        assert_eq!(errors, vec![("unterminated string".to_string(), 1, 9)]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut lex = Lexer::init_lexer("// top\nx / y // tail\n  //\n");
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        let toks: Vec<TokenType> = ve.iter().map(|t| t.type_).collect();
+        assert_eq!(
+            toks,
+            vec![
+                TokenType::IDENT,
+                TokenType::SLASH,
+                TokenType::IDENT,
+                TokenType::EOF
+            ]
+        );
+        let comments: Vec<(&str, usize, bool)> = lex
+            .comments()
+            .iter()
+            .map(|c| (c.text.as_str(), c.span.line, c.own_line))
+            .collect();
+        assert_eq!(
+            comments,
+            vec![("// top", 1, true), ("// tail", 2, false), ("//", 3, true)]
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let s = "let x = 5;\n  x + \"ab\";";
+        let mut lex = Lexer::init_lexer(s);
+        let mut ve = Vec::<Token>::new();
+        while !lex.end {
+            ve.push(lex.next_token());
+        }
+        ve.push(lex.next_token());
+        let spans: Vec<(usize, usize, usize)> = ve
+            .iter()
+            .map(|t| (t.span.line, t.span.column, t.span.end - t.span.start))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (1, 1, 3),
+                (1, 5, 1),
+                (1, 7, 1),
+                (1, 9, 1),
+                (1, 10, 1),
+                (2, 3, 1),
+                (2, 5, 1),
+                (2, 7, 4),
+                (2, 11, 1),
+                (2, 12, 0),
+            ]
+        );
+    }
 }
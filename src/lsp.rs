@@ -0,0 +1,712 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic;
+
+use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::check::{check, references, BindingKind, Reference, Severity};
+use crate::json::Json;
+use crate::lexer::{generate_keywords, Lexer};
+use crate::macros::{define_macros, expand_macros, expansion_limits};
+use crate::object::{Environment, Limits};
+use crate::parser::Parser;
+use crate::token::Span;
+use crate::types::{typecheck, Typecheck};
+
+/// Serves the Language Server Protocol on `input` and `output` until the
+/// client sends `exit` or closes the stream. Returns whether the client
+/// asked for a `shutdown` first, as a clean exit requires.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error_response(Json::Null, -32700, e)],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(server.shut_down)
+}
+
+/// Reads one message body framed by a `Content-Length` header. Returns
+/// `None` at the end of the stream.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message without Content-Length",
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+    exited: bool,
+}
+
+/// An open file and what was found in it the last time it changed.
+struct Document {
+    text: String,
+    /// The program as parsed, before macro expansion. `None` if it does
+    /// not parse.
+    program: Option<Program>,
+    references: Vec<Reference>,
+    types: Option<Typecheck>,
+    diagnostics: Vec<Json>,
+}
+
+impl Server {
+    /// Handles one request or notification, giving the messages to send
+    /// back.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = str_at(message, &["method"]).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // A notification; nothing is sent back except diagnostics.
+            None => return self.notification(method, params),
+        };
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err(format!("method not found: {}", method)),
+        };
+        match result {
+            Ok(result) => vec![object(vec![
+                ("jsonrpc", string("2.0")),
+                ("id", id),
+                ("result", result),
+            ])],
+            Err(message) => vec![error_response(id, -32601, message)],
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = str_at(params, &["textDocument", "uri"]).unwrap_or_default();
+        let text = match method {
+            "exit" => {
+                self.exited = true;
+                return vec![];
+            }
+            "textDocument/didOpen" => str_at(params, &["textDocument", "text"]),
+            // Only full-text sync is offered, so the last change holds the
+            // whole document.
+            "textDocument/didChange" => match params.get("contentChanges") {
+                Some(Json::Array(changes)) => changes.last().and_then(|c| str_at(c, &["text"])),
+                _ => None,
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, vec![])];
+            }
+            _ => return vec![],
+        };
+        let document = match text {
+            Some(text) => analyze(text),
+            None => return vec![],
+        };
+        let reply = publish_diagnostics(uri, document.diagnostics.clone());
+        self.documents.insert(uri.to_string(), document);
+        vec![reply]
+    }
+
+    /// The open document and the reference under the cursor, if any.
+    fn reference_at<'a>(
+        &'a self,
+        params: &'a Json,
+    ) -> Option<(&'a str, &'a Document, &'a Reference)> {
+        let uri = str_at(params, &["textDocument", "uri"])?;
+        let document = self.documents.get(uri)?;
+        let offset = offset_at(&document.text, params.get("position")?)?;
+        let reference = document
+            .references
+            .iter()
+            .find(|r| r.span.start <= offset && offset <= r.span.end)?;
+        Some((uri, document, reference))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        match self.reference_at(params) {
+            Some((uri, document, reference)) => object(vec![
+                ("uri", string(uri)),
+                ("range", range(&document.text, reference.definition)),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (_, document, reference) = match self.reference_at(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let kind = match reference.kind {
+            BindingKind::Variable => "let",
+            BindingKind::Parameter => "parameter",
+            BindingKind::Local => "local",
+        };
+        let value = match document.type_of(reference.definition) {
+            Some(ty) => format!("{} {}: {}", kind, reference.name, ty),
+            None => format!("{} {}", kind, reference.name),
+        };
+        object(vec![
+            (
+                "contents",
+                object(vec![
+                    ("kind", string("plaintext")),
+                    ("value", string(&value)),
+                ]),
+            ),
+            ("range", range(&document.text, reference.span)),
+        ])
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = str_at(params, &["textDocument", "uri"]).unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(document) => match &document.program {
+                Some(program) => Json::Array(document.symbols(program)),
+                None => Json::Null,
+            },
+            None => Json::Null,
+        }
+    }
+
+    /// Offers every name bound in the document and every keyword. The
+    /// client narrows the list down as more is typed.
+    fn completion(&self, params: &Json) -> Json {
+        let uri = str_at(params, &["textDocument", "uri"]).unwrap_or_default();
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Json::Array(vec![]),
+        };
+        let mut seen = Vec::new();
+        let mut items = Vec::new();
+        for r in document
+            .references
+            .iter()
+            .filter(|r| r.span == r.definition)
+        {
+            if seen.contains(&r.name) {
+                continue;
+            }
+            seen.push(r.name.clone());
+            let ty = document.type_of(r.definition);
+            let kind = match &ty {
+                Some(ty) if ty.starts_with("fn(") => FUNCTION,
+                _ => VARIABLE,
+            };
+            let mut fields = vec![("label", string(&r.name)), ("kind", Json::Number(kind))];
+            if let Some(ty) = ty {
+                fields.push(("detail", string(&ty)));
+            }
+            items.push(object(fields));
+        }
+        for builtin in Builtin::ALL {
+            items.push(object(vec![
+                ("label", string(builtin.name())),
+                ("kind", Json::Number(FUNCTION)),
+                ("detail", string("builtin")),
+            ]));
+        }
+        let mut words: Vec<&str> = generate_keywords().into_keys().collect();
+        words.sort_unstable();
+        for word in words {
+            items.push(object(vec![
+                ("label", string(word)),
+                ("kind", Json::Number(KEYWORD)),
+            ]));
+        }
+        Json::Array(items)
+    }
+}
+
+// Symbol and completion item kinds from the protocol.
+const FUNCTION: i64 = 3;
+const VARIABLE: i64 = 6;
+const KEYWORD: i64 = 14;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const SYMBOL_OPERATOR: i64 = 25;
+
+fn capabilities() -> Json {
+    object(vec![
+        (
+            "capabilities",
+            object(vec![
+                ("textDocumentSync", Json::Number(1)),
+                ("definitionProvider", Json::Bool(true)),
+                ("hoverProvider", Json::Bool(true)),
+                ("documentSymbolProvider", Json::Bool(true)),
+                ("completionProvider", object(vec![])),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object(vec![("name", string(env!("CARGO_PKG_NAME")))]),
+        ),
+    ])
+}
+
+/// Parses, expands, checks and type checks `text` the way `check` and
+/// `typecheck` do, keeping what the editor features need. Buffers are
+/// analyzed as they are typed, so a panic anywhere in the front end is
+/// reported as a diagnostic instead of taking the server down.
+fn analyze(text: &str) -> Document {
+    match panic::catch_unwind(|| analyze_unguarded(text)) {
+        Ok(document) => document,
+        Err(payload) => {
+            let reason = match (
+                payload.downcast_ref::<&str>(),
+                payload.downcast_ref::<String>(),
+            ) {
+                (Some(s), _) => s.to_string(),
+                (_, Some(s)) => s.clone(),
+                _ => "unknown error".to_string(),
+            };
+            let mut document = Document::empty(text);
+            let message = format!("internal error: {}", reason);
+            let diagnostic = document.diagnostic(Span::default(), ERROR, &message);
+            document.diagnostics.push(diagnostic);
+            document
+        }
+    }
+}
+
+fn analyze_unguarded(text: &str) -> Document {
+    let mut document = Document::empty(text);
+    let mut lexer = Lexer::init_lexer(text);
+    let mut parser = Parser::new(&mut lexer);
+    let program = parser.parse_program();
+    if !parser.located_errors().is_empty() {
+        for (message, span) in parser.located_errors() {
+            let diagnostic = document.diagnostic(*span, ERROR, message);
+            document.diagnostics.push(diagnostic);
+        }
+        return document;
+    }
+    document.program = Some(program.clone());
+    let mut program = program;
+    let mut macro_env = Environment::with_limits(expansion_limits(Limits::default()));
+    define_macros(&mut program, &mut macro_env);
+    let program = match expand_macros(program, &mut macro_env) {
+        Ok(program) => program,
+        Err(e) => {
+            let diagnostic = document.diagnostic(e.span, ERROR, &e.message);
+            document.diagnostics.push(diagnostic);
+            return document;
+        }
+    };
+    let mut errors = vec![];
+    for d in check(&program) {
+        let severity = match d.severity {
+            Severity::Error => ERROR,
+            Severity::Warning => WARNING,
+        };
+        if d.severity == Severity::Error {
+            errors.push(d.span);
+        }
+        let diagnostic = document.diagnostic(d.span, severity, &d.message);
+        document.diagnostics.push(diagnostic);
+    }
+    let types = typecheck(&program);
+    // Type checking is optional, so its findings are only warnings. Names
+    // it cannot find have already been reported.
+    for e in types.errors.iter().filter(|e| !errors.contains(&e.span)) {
+        let diagnostic = document.diagnostic(e.span, WARNING, &e.detail());
+        document.diagnostics.push(diagnostic);
+    }
+    document.references = references(&program);
+    document.types = Some(types);
+    document
+}
+
+const ERROR: i64 = 1;
+const WARNING: i64 = 2;
+
+impl Document {
+    fn empty(text: &str) -> Document {
+        Document {
+            text: text.to_string(),
+            program: None,
+            references: vec![],
+            types: None,
+            diagnostics: vec![],
+        }
+    }
+
+    fn diagnostic(&self, span: Span, severity: i64, message: &str) -> Json {
+        object(vec![
+            ("range", range(&self.text, span)),
+            ("severity", Json::Number(severity)),
+            ("source", string(env!("CARGO_PKG_NAME"))),
+            ("message", string(message)),
+        ])
+    }
+
+    /// The inferred type of the binding made at `definition`.
+    fn type_of(&self, definition: Span) -> Option<String> {
+        let types = self.types.as_ref()?;
+        types
+            .bindings
+            .iter()
+            .chain(&types.parameters)
+            .find(|b| b.span == definition)
+            .map(|b| b.ty.clone())
+    }
+
+    /// The `let` bindings and operator declarations in `statements`, with
+    /// those inside function bodies as children.
+    fn symbols(&self, statements: &[Statement]) -> Vec<Json> {
+        let mut symbols = vec![];
+        for stmt in statements {
+            let (name, selection, value) = match stmt {
+                Statement::LetStatement(LetStatement {
+                    name: Some(name),
+                    value,
+                    ..
+                }) => (&name.value, name.token.span, value),
+                Statement::InfixStatement(x) => (&x.operator, x.token.span, &x.value),
+                _ => continue,
+            };
+            let body = match value.as_deref() {
+                Some(Expression::FunctionLiteral(f)) => f.body.as_deref(),
+                _ => None,
+            };
+            let kind = match (stmt, body) {
+                (Statement::InfixStatement(_), _) => SYMBOL_OPERATOR,
+                (_, Some(_)) => SYMBOL_FUNCTION,
+                _ => SYMBOL_VARIABLE,
+            };
+            let mut whole = stmt.token().span;
+            whole.end = body.map_or(selection.end, |b| b.end.span.end);
+            let mut fields = vec![
+                ("name", string(name)),
+                ("kind", Json::Number(kind)),
+                ("range", range(&self.text, whole)),
+                ("selectionRange", range(&self.text, selection)),
+            ];
+            if let Some(ty) = self.type_of(selection) {
+                fields.push(("detail", string(&ty)));
+            }
+            if let Some(body) = body {
+                fields.push(("children", Json::Array(self.symbols(&body.statements))));
+            }
+            symbols.push(object(fields));
+        }
+        symbols
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    object(vec![
+        ("jsonrpc", string("2.0")),
+        ("method", string("textDocument/publishDiagnostics")),
+        (
+            "params",
+            object(vec![
+                ("uri", string(uri)),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+    object(vec![
+        ("jsonrpc", string("2.0")),
+        ("id", id),
+        (
+            "error",
+            object(vec![
+                ("code", Json::Number(code)),
+                ("message", Json::String(message)),
+            ]),
+        ),
+    ])
+}
+
+pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+pub fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+/// The string at `path` inside `json`.
+pub fn str_at<'a>(json: &'a Json, path: &[&str]) -> Option<&'a str> {
+    match path.iter().try_fold(json, |j, key| j.get(key))? {
+        Json::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+pub fn number_at(json: &Json, key: &str) -> Option<usize> {
+    match json.get(key)? {
+        Json::Number(n) => usize::try_from(*n).ok(),
+        _ => None,
+    }
+}
+
+/// A span as a protocol range. An unknown span is the start of the file.
+fn range(text: &str, span: Span) -> Json {
+    let (start, end) = match span.is_known() {
+        true => (span.start, span.end),
+        false => (0, 0),
+    };
+    object(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+/// The protocol position of a byte offset: a 0-based line and a column
+/// counted in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Json {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character = before[line_start..].encode_utf16().count();
+    object(vec![
+        ("line", Json::Number(line as i64)),
+        ("character", Json::Number(character as i64)),
+    ])
+}
+
+/// The byte offset of a protocol position.
+fn offset_at(text: &str, position: &Json) -> Option<usize> {
+    let line = number_at(position, "line")?;
+    let character = number_at(position, "character")?;
+    let line_start = match line {
+        0 => 0,
+        n => text.match_indices('\n').nth(n - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+    }
+
+    /// Runs a scripted session and returns what the server sent back.
+    fn session(messages: &[&str]) -> (bool, Vec<Json>) {
+        let input: String = messages.iter().map(|m| frame(m)).collect();
+        let mut output = Vec::new();
+        let clean = serve(input.as_bytes(), &mut output).unwrap();
+        let mut reader = output.as_slice();
+        let mut replies = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            replies.push(Json::parse(&body).unwrap());
+        }
+        (clean, replies)
+    }
+
+    fn request(id: i64, method: &str, line: usize, character: usize) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{{"textDocument":{{"uri":"file:///a.mk"}},"position":{{"line":{},"character":{}}}}}}}"#,
+            id, method, line, character
+        )
+    }
+
+    #[test]
+    fn lsp_session_test() {
+        let source = "let add = fn(a, b) { a + b };\\nlet n = add(1, 2);\\nn + m";
+        let open = format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.mk","languageId":"monkey","version":1,"text":"{}"}}}}}}"#,
+            source
+        );
+        let change = r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.mk","version":2},"contentChanges":[{"text":"let = 1;"}]}}"#;
+        let (clean, replies) = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            &open,
+            &request(2, "textDocument/definition", 1, 8),
+            &request(3, "textDocument/hover", 0, 21),
+            &request(4, "textDocument/hover", 1, 5),
+            &request(5, "textDocument/documentSymbol", 0, 0),
+            &request(6, "textDocument/completion", 2, 1),
+            &request(7, "textDocument/formatting", 0, 0),
+            change,
+            r#"{"jsonrpc":"2.0","id":8,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        assert!(clean);
+        let replies: Vec<String> = replies.iter().map(|r| r.to_string()).collect();
+        assert_eq!(replies.len(), 10);
+        assert!(
+            replies[0].contains(r#""hoverProvider":true"#),
+            "{}",
+            replies[0]
+        );
+        assert_eq!(
+            replies[1],
+            r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.mk","diagnostics":[{"range":{"start":{"line":2,"character":4},"end":{"line":2,"character":5}},"severity":1,"source":"monkey_lang","message":"undefined variable m"}]}}"#
+        );
+        assert_eq!(
+            replies[2],
+            r#"{"jsonrpc":"2.0","id":2,"result":{"uri":"file:///a.mk","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":7}}}}"#
+        );
+        assert!(
+            replies[3].contains(r#""value":"parameter a: 'a where 'a: int | string""#),
+            "{}",
+            replies[3]
+        );
+        assert!(
+            replies[4].contains(r#""value":"let n: int""#),
+            "{}",
+            replies[4]
+        );
+        assert!(
+            replies[5].contains(r#""name":"add","kind":12"#)
+                && replies[5].contains(r#""name":"n","kind":13"#),
+            "{}",
+            replies[5]
+        );
+        assert!(
+            replies[6].contains(
+                r#"{"label":"add","kind":3,"detail":"fn('a, 'a) -> 'a where 'a: int | string"}"#
+            ) && replies[6].contains(r#"{"label":"while","kind":14}"#),
+            "{}",
+            replies[6]
+        );
+        assert!(replies[7].contains(r#""code":-32601"#), "{}", replies[7]);
+        assert_eq!(
+            replies[8],
+            r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.mk","diagnostics":[{"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}},"severity":1,"source":"monkey_lang","message":"expected next token to be TokenType: IDENT, got TokenType: ASSIGN"}]}}"#
+        );
+        assert_eq!(replies[9], r#"{"jsonrpc":"2.0","id":8,"result":null}"#);
+    }
+
+    #[test]
+    fn incomplete_input_test() {
+        let open = |text: &str| {
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.mk","languageId":"monkey","version":1,"text":"{}"}}}}}}"#,
+                text
+            )
+        };
+        let (_, replies) = session(&[
+            &open("if ("),
+            &open("let x = if ("),
+            &open("if () { 1 }"),
+            &open("let s = \\\"é"),
+            &request(1, "textDocument/hover", 0, 0),
+        ]);
+        let replies: Vec<String> = replies.iter().map(|r| r.to_string()).collect();
+        assert_eq!(replies.len(), 5);
+        for reply in &replies[..4] {
+            assert!(
+                reply.contains(r#""method":"textDocument/publishDiagnostics""#)
+                    && reply.contains(r#""severity":1"#),
+                "{}",
+                reply
+            );
+        }
+        assert!(
+            replies[0].contains(r#""message":"could not parse if condition""#),
+            "{}",
+            replies[0]
+        );
+        assert!(
+            replies[3].contains(r#""message":"unterminated string""#)
+                && replies[3].contains(r#""start":{"line":0,"character":8}"#),
+            "{}",
+            replies[3]
+        );
+        assert_eq!(replies[4], r#"{"jsonrpc":"2.0","id":1,"result":null}"#);
+    }
+
+    #[test]
+    fn macro_limit_test() {
+        let document = analyze("let m = macro() { while (true) { } };\nm();");
+        let messages: Vec<String> = document.diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(messages.len(), 1);
+        assert!(
+            messages[0].contains(r#""message":"step limit of 1000000 exceeded""#),
+            "{}",
+            messages[0]
+        );
+    }
+
+    #[test]
+    fn position_test() {
+        let text = "a\nλb = 1";
+        let pos = position(text, text.find('b').unwrap());
+        assert_eq!(pos.to_string(), r#"{"line":1,"character":1}"#);
+        let back = offset_at(text, &pos).unwrap();
+        assert_eq!(&text[back..back + 1], "b");
+        assert_eq!(
+            offset_at(text, &Json::parse(r#"{"line":0,"character":9}"#).unwrap()),
+            Some(1)
+        );
+        assert_eq!(
+            offset_at(text, &Json::parse(r#"{"line":5,"character":0}"#).unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::ast::*;
+use crate::eval::call_function;
+use crate::object::{Environment, ErrorKind, Function, Limits, Object, RuntimeError};
+use crate::token::Span;
+
+/// Steps and time given to macro expansion by tools that must stay
+/// responsive, so a macro that never returns cannot hang them.
+const EXPANSION_MAX_STEPS: u64 = 1_000_000;
+const EXPANSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `limits` with macro expansion bounded in steps and time.
+pub fn expansion_limits(limits: Limits) -> Limits {
+    Limits {
+        max_steps: Some(EXPANSION_MAX_STEPS),
+        timeout: Some(EXPANSION_TIMEOUT),
+        ..limits
+    }
+}
+
+/// Removes each top-level `let name = macro(...) { ... };` from the program
+/// and binds the macro in `env` instead.
+pub fn define_macros(program: &mut Program, env: &mut Environment) {
+    program.retain(|stmt| {
+        let (name, lit) = match stmt {
+            Statement::LetStatement(LetStatement {
+                name: Some(name),
+                value: Some(value),
+                ..
+            }) => match value.as_ref() {
+                Expression::MacroLiteral(lit) => (name, lit),
+                _ => return true,
+            },
+            _ => return true,
+        };
+        let body = match &lit.body {
+            Some(b) => b.clone(),
+            None => return true,
+        };
+        let mac = Function {
+            name: Some(name.value.clone()),
+            parameters: Rc::new(lit.parameters.clone().unwrap_or_default()),
+            param_types: Rc::default(),
+            return_type: None,
+            body,
+            env: env.clone(),
+        };
+        // A macro that does not fit in the memory budget is left in the
+        // program, where evaluating it fails.
+        env.set(name.value.clone(), Object::Macro(mac)).is_err()
+    });
+}
+
+/// Replaces each call to a macro bound in `env` with the code the macro
+/// returns. The macro is given its arguments quoted, so they are not
+/// evaluated, and must return a quote. A macro literal that
+/// `define_macros` did not take out of the program is an error.
+pub fn expand_macros(program: Program, env: &mut Environment) -> Result<Program, RuntimeError> {
+    let mut stray = StrayMacro(None);
+    stray.visit_program(&program);
+    if let Some(span) = stray.0 {
+        let mut err = RuntimeError::new(
+            ErrorKind::Syntax,
+            "macros must be bound by a top-level let".to_string(),
+        );
+        err.span = span;
+        return Err(err);
+    }
+    let mut expander = MacroExpander { env, error: None };
+    let program = expander.fold_program(program);
+    match expander.error {
+        Some(e) => Err(e),
+        None => Ok(program),
+    }
+}
+
+/// Finds the first macro literal in a program.
+struct StrayMacro(Option<Span>);
+
+impl Visitor for StrayMacro {
+    fn visit_macro_literal(&mut self, lit: &MacroLiteral) {
+        self.0.get_or_insert(lit.token.span);
+    }
+}
+
+struct MacroExpander<'a> {
+    env: &'a mut Environment,
+    error: Option<RuntimeError>,
+}
+
+impl Folder for MacroExpander<'_> {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        let expr = fold::walk_expression(self, expr);
+        let call = match &expr {
+            Expression::CallExpression(c) if self.error.is_none() => c,
+            _ => return expr,
+        };
+        let mac = match call.func.as_deref() {
+            Some(Expression::Identifier(x)) => match self.env.get(&x.value) {
+                Some(Object::Macro(mac)) => mac,
+                _ => return expr,
+            },
+            _ => return expr,
+        };
+        let args = call
+            .args
+            .iter()
+            .flatten()
+            .map(|arg| Object::Quote(Box::new(arg.clone())))
+            .collect();
+        let span = call.token.span;
+        let mut err = match call_function(Object::Function(mac), args, span, self.env) {
+            Object::Quote(code) => return *code,
+            Object::Error(e) => e,
+            other => RuntimeError::new(
+                ErrorKind::Type,
+                format!("macro must return a quote, got {}", other.type_()),
+            ),
+        };
+        if !err.span.is_known() {
+            err.span = span;
+        }
+        self.error = Some(err);
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_prog;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Object {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let mut program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        let mut macro_env = Environment::new();
+        define_macros(&mut program, &mut macro_env);
+        match expand_macros(program, &mut macro_env) {
+            Ok(program) => eval_prog(&program, &mut Environment::new()),
+            Err(e) => Object::Error(e),
+        }
+    }
+
+    #[test]
+    fn quote_test() {
+        let tests = vec![
+            ("quote(5)", "5"),
+            ("quote(foobar + barfoo)", "foobar + barfoo"),
+            ("let x = 8; quote(unquote(x) + 1)", "8 + 1"),
+            ("quote(unquote(4 + 4) * unquote(true))", "8 * true"),
+            ("let q = quote(4 + 4); quote(unquote(q) * 2)", "4 + 4 * 2"),
+            ("quote(unquote([1, \"a\"]))", "[1, a]"),
+        ];
+        for (input, expected) in tests {
+            let quoted = match run(input) {
+                Object::Quote(x) => *x,
+                other => panic!("expected quote for {}, got {}", input, other.inspect()),
+            };
+            assert_eq!(source(&quoted), expected, "{}", input);
+        }
+        assert_eq!(
+            run("quote(unquote(fn(x) { x }))").inspect(),
+            "Error: cannot unquote FUNCTION"
+        );
+        assert_eq!(
+            run("quote(1, 2)").inspect(),
+            "Error: wrong number of arguments to quote: want=1, got=2"
+        );
+    }
+
+    /// A compact rendering of the expressions the tests quote.
+    fn source(expr: &Expression) -> String {
+        match expr {
+            Expression::InfixExpression(x) => format!(
+                "{} {} {}",
+                source(x.left.as_deref().unwrap()),
+                x.operator,
+                source(x.right.as_deref().unwrap())
+            ),
+            Expression::ArrayLiteral(x) => {
+                let elems: Vec<String> = x.elements.iter().map(source).collect();
+                format!("[{}]", elems.join(", "))
+            }
+            Expression::Identifier(x) => x.value.clone(),
+            Expression::StringLiteral(x) => x.value.clone(),
+            other => other.token().literal.clone(),
+        }
+    }
+
+    #[test]
+    fn define_macros_test() {
+        let input = "let number = 1; let function = fn(x, y) { x + y };
+            let mymacro = macro(x, y) { x + y; };";
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let mut program = par.parse_program();
+        let mut env = Environment::new();
+        define_macros(&mut program, &mut env);
+        assert_eq!(program.len(), 2);
+        assert!(env.get("number").is_none());
+        assert!(env.get("function").is_none());
+        match env.get("mymacro") {
+            Some(Object::Macro(m)) => assert_eq!(m.parameters.len(), 2),
+            other => panic!("expected macro, got {:?}", other.map(|o| o.inspect())),
+        }
+    }
+
+    #[test]
+    fn expand_macros_test() {
+        let tests = vec![
+            (
+                "let unless = macro(cond, cons, alt) {
+                    quote(if (!(unquote(cond))) { unquote(cons); } else { unquote(alt); });
+                };
+                unless(10 > 5, \"not greater\", \"greater\");",
+                "greater",
+            ),
+            (
+                "let twice = macro(e) { quote([unquote(e), unquote(e)]) };
+                let n = 0;
+                twice(n += 1);
+                n",
+                "2",
+            ),
+            (
+                "let infix = macro(a, b) { quote(unquote(b) - unquote(a)) };
+                let f = fn() { infix(2, 10) };
+                f()",
+                "8",
+            ),
+            (
+                "let bad = macro() { 1 }; bad()",
+                "Error: macro must return a quote, got INTEGER_OBJ",
+            ),
+            (
+                "let f = fn() { macro(x) { x } }; 1",
+                "Error: macros must be bound by a top-level let",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(run(input).inspect(), expected, "{}", input);
+        }
+    }
+}
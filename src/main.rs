@@ -1,37 +1,576 @@
+use std::cell::RefCell;
 use std::io::*;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Duration;
 mod ast;
+mod builtins;
+mod check;
+mod dap;
+mod debugger;
 mod eval;
+mod json;
 mod lexer;
+mod lsp;
+mod macros;
 mod object;
+mod optimize;
 mod parser;
+mod profile;
+mod runner;
 mod token;
+mod types;
+use crate::ast::json::{program_to_json, ToJson};
+use crate::ast::printer::format_source;
+use crate::ast::Program;
+use crate::check::{check, Severity};
+use crate::debugger::Debugger;
 use crate::eval::*;
 use crate::lexer::*;
+use crate::macros::*;
 use crate::object::*;
+use crate::optimize::fold_constants;
 use crate::parser::*;
+use crate::profile::Profiler;
+use crate::runner::run_tests;
+use crate::token::{Span, TokenType};
+use crate::types::typecheck;
+
+/// The REPL evaluates on its own thread so deep recursion has room to run
+/// before the call-depth limit stops it.
+const REPL_STACK_SIZE: usize = 512 * 1024 * 1024;
+const REPL_MAX_DEPTH: usize = 10_000;
+
+/// How long `test` lets each test run.
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The memory limit set with `--max-memory`, if any.
+static MAX_MEMORY: OnceLock<usize> = OnceLock::new();
+
+/// The limits for programs run on the REPL's stack.
+fn repl_limits() -> Limits {
+    Limits {
+        max_depth: Some(REPL_MAX_DEPTH),
+        max_memory: MAX_MEMORY.get().copied(),
+        ..Limits::default()
+    }
+}
+
+/// Removes a leading `--max-memory BYTES` from `args`. The limit applies to
+/// every command; without it memory use is unbounded.
+fn take_max_memory(args: &mut Vec<String>) -> std::result::Result<Option<usize>, String> {
+    if args.first().map(String::as_str) != Some("--max-memory") {
+        return Ok(None);
+    }
+    match args.get(1).map(|a| a.parse::<usize>()) {
+        Some(Ok(bytes)) => {
+            args.drain(..2);
+            Ok(Some(bytes))
+        }
+        _ => Err("usage: --max-memory BYTES [command...]".to_string()),
+    }
+}
+
 fn main() {
-    let mut env = Environment::new();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    match take_max_memory(&mut args) {
+        Ok(Some(bytes)) => {
+            MAX_MEMORY.set(bytes).ok();
+        }
+        Ok(None) => {}
+        Err(usage) => {
+            eprintln!("{}", usage);
+            std::process::exit(1);
+        }
+    }
+    match args.first().map(String::as_str) {
+        Some("fmt") => std::process::exit(fmt(&args[1..])),
+        Some("--emit") => std::process::exit(emit(&args[1..])),
+        Some("check") => std::process::exit(check_files(&args[1..])),
+        Some("typecheck") => std::process::exit(typecheck_files(&args[1..])),
+        Some("lsp") => std::process::exit(lsp()),
+        Some("dap") => std::process::exit(dap()),
+        Some("--profile") => std::process::exit(profile(&args[1..])),
+        Some("test") => std::process::exit(test_files(&args[1..])),
+        _ => {}
+    }
+    let debug = args.first().is_some_and(|a| a == "--debug");
+    let repl = std::thread::Builder::new()
+        .stack_size(REPL_STACK_SIZE)
+        .spawn(move || repl(debug))
+        .unwrap();
+    repl.join().unwrap();
+}
+
+/// `fmt [--check] [files...]` rewrites each file in canonical form. With
+/// `--check` no file is written and the exit status is 1 if any would
+/// change. Without files it formats standard input to standard output.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+    if files.is_empty() {
+        let mut input = String::new();
+        if let Err(e) = stdin().read_to_string(&mut input) {
+            eprintln!("<stdin>: {}", e);
+            return 1;
+        }
+        return match format_source(&input) {
+            Ok(out) if check => (out != input) as i32,
+            Ok(out) => {
+                print!("{}", out);
+                0
+            }
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("<stdin>: {}", err);
+                }
+                1
+            }
+        };
+    }
+    let mut status = 0;
+    for path in files {
+        let input = match std::fs::read_to_string(path) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                status = 1;
+                continue;
+            }
+        };
+        let out = match format_source(&input) {
+            Ok(out) => out,
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("{}: {}", path, err);
+                }
+                status = 1;
+                continue;
+            }
+        };
+        if out == input {
+            continue;
+        }
+        if check {
+            println!("{}: not formatted", path);
+            status = 1;
+        } else if let Err(e) = std::fs::write(path, out) {
+            eprintln!("{}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// `--emit tokens-json|ast-json [file]` prints the token stream or the
+/// parsed program of a file, or of standard input, as JSON.
+fn emit(args: &[String]) -> i32 {
+    let (kind, path) = match args {
+        [kind] => (kind, None),
+        [kind, path] => (kind, Some(path)),
+        _ => {
+            eprintln!("usage: --emit tokens-json|ast-json [file]");
+            return 1;
+        }
+    };
+    let name = path.map_or("<stdin>", String::as_str);
+    let input = match read_source(path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            return 1;
+        }
+    };
+    let mut lexer = Lexer::init_lexer(&input);
+    match kind.as_str() {
+        "tokens-json" => {
+            let mut tokens = Vec::new();
+            loop {
+                let tok = lexer.next_token();
+                let eof = tok.type_ == TokenType::EOF;
+                tokens.push(tok);
+                if eof {
+                    break;
+                }
+            }
+            println!("{}", tokens.to_json());
+        }
+        "ast-json" => {
+            let mut parser = Parser::new(&mut lexer);
+            let program = parser.parse_program();
+            if !parser.errors().is_empty() {
+                for err in parser.errors() {
+                    eprintln!("{}: {}", name, err);
+                }
+                return 1;
+            }
+            println!("{}", program_to_json(&program));
+        }
+        other => {
+            eprintln!("unknown --emit kind {}", other);
+            return 1;
+        }
+    }
+    0
+}
+
+/// `check [files...]` reports undefined names, unused bindings, shadowing
+/// and unreachable statements without running anything. Without files it
+/// checks standard input. The exit status is 1 if any error was found.
+fn check_files(paths: &[String]) -> i32 {
+    let mut status = 0;
+    for path in source_paths(paths) {
+        let name = path.map_or("<stdin>", String::as_str);
+        let program = match load_program(path) {
+            Some(program) => program,
+            None => {
+                status = 1;
+                continue;
+            }
+        };
+        for diagnostic in check(&program) {
+            println!("{}:{}", name, diagnostic);
+            if diagnostic.severity == Severity::Error {
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// `typecheck [files...]` infers the type of every `let` binding and
+/// reports expressions whose types conflict. Without files it checks
+/// standard input. The exit status is 1 if any type error was found.
+fn typecheck_files(paths: &[String]) -> i32 {
+    let mut status = 0;
+    for path in source_paths(paths) {
+        let name = path.map_or("<stdin>", String::as_str);
+        let program = match load_program(path) {
+            Some(program) => program,
+            None => {
+                status = 1;
+                continue;
+            }
+        };
+        let result = typecheck(&program);
+        for binding in &result.bindings {
+            println!(
+                "{}:{}:{}: {}: {}",
+                name, binding.span.line, binding.span.column, binding.name, binding.ty
+            );
+        }
+        for error in &result.errors {
+            println!("{}:{}", name, error);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// `lsp` runs a language server on standard input and output. The exit
+/// status is 0 only if the client shut it down before it exited.
+fn lsp() -> i32 {
+    match lsp::serve(stdin().lock(), stdout().lock()) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("lsp: {}", e);
+            1
+        }
+    }
+}
+
+/// Runs `f` on a thread with the REPL's stack, so programs it evaluates
+/// have the same room for deep recursion.
+fn with_repl_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(REPL_STACK_SIZE)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// `dap` serves the Debug Adapter Protocol on standard input and output.
+fn dap() -> i32 {
+    match with_repl_stack(|| dap::serve(stdin().lock(), stdout(), repl_limits())) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("dap: {}", e);
+            1
+        }
+    }
+}
+
+/// `--profile file [folded]` runs a file and prints its result, then a
+/// report of calls and time per function and evaluations per node on
+/// standard error. Stacks for flame graph tools go to `folded`, by default
+/// the file's name with `.folded` added.
+fn profile(args: &[String]) -> i32 {
+    let (path, folded) = match args {
+        [path] => (path.clone(), format!("{}.folded", path)),
+        [path, folded] => (path.clone(), folded.clone()),
+        _ => {
+            eprintln!("usage: --profile file [folded]");
+            return 1;
+        }
+    };
+    let run = move || {
+        let program = match load_program(Some(&path)) {
+            Some(program) => program,
+            None => return 1,
+        };
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut env = Environment::with_limits(repl_limits());
+        env.set_hook(profiler.clone());
+        let evaluated = eval_prog(&program, &mut env);
+        let status = match evaluated {
+            Object::Error(e) => {
+                println!("{}", e.traceback());
+                1
+            }
+            Object::Null => 0,
+            _ => {
+                println!("{}", evaluated.inspect());
+                0
+            }
+        };
+        let mut profiler = profiler.borrow_mut();
+        profiler.finish();
+        eprint!("{}", profiler.report());
+        if let Err(e) = std::fs::write(&folded, profiler.folded()) {
+            eprintln!("{}: {}", folded, e);
+            return 1;
+        }
+        eprintln!("\nfolded stacks written to {}", folded);
+        status
+    };
+    with_repl_stack(run)
+}
+
+/// `test [paths...]` runs the `test_` functions of every `*_test.mk` file
+/// under the given files and directories, by default the current one, and
+/// prints which passed and why the others failed. A test that runs past
+/// `TEST_TIMEOUT` fails. The exit status is 1 unless every test passed.
+fn test_files(args: &[String]) -> i32 {
+    let roots = match args {
+        [] => vec![".".to_string()],
+        args => args.to_vec(),
+    };
+    let mut files = vec![];
+    for root in &roots {
+        if let Err(e) = find_tests(std::path::Path::new(root), &mut files) {
+            eprintln!("{}: {}", root, e);
+            return 1;
+        }
+    }
+    if files.is_empty() {
+        eprintln!("no *_test.mk files found");
+        return 1;
+    }
+    let limits = Limits {
+        timeout: Some(TEST_TIMEOUT),
+        ..repl_limits()
+    };
+    with_repl_stack(move || {
+        let (mut passed, mut failed) = (0, 0);
+        for file in &files {
+            let name = file.display().to_string();
+            println!("{}", name);
+            let results = match load_program(Some(&name)).map(|p| run_tests(&p, limits)) {
+                Some(Ok(results)) => results,
+                Some(Err(traceback)) => {
+                    println!("{}", indent(&traceback));
+                    failed += 1;
+                    continue;
+                }
+                None => {
+                    failed += 1;
+                    continue;
+                }
+            };
+            for result in results {
+                match result.failure {
+                    None => {
+                        println!("  ok    {}", result.name);
+                        passed += 1;
+                    }
+                    Some(traceback) => {
+                        println!("  FAIL  {}", result.name);
+                        println!("{}", indent(&traceback));
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        println!("{} passed, {} failed", passed, failed);
+        (failed > 0) as i32
+    })
+}
+
+/// Collects the `*_test.mk` files under `path`, or `path` itself if it
+/// names a file, in a stable order.
+fn find_tests(path: &std::path::Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        std::fs::metadata(path)?;
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<_> = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        let is_test = entry
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.ends_with("_test.mk"));
+        if entry.is_dir() {
+            find_tests(&entry, files)?;
+        } else if is_test {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn indent(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(|l| format!("      {}", l)).collect();
+    lines.join("\n")
+}
+
+/// The files named on the command line, or standard input when there are
+/// none.
+fn source_paths(paths: &[String]) -> Vec<Option<&String>> {
+    match paths {
+        [] => vec![None],
+        paths => paths.iter().map(Some).collect(),
+    }
+}
+
+/// Reads, parses and macro-expands a source file, printing any errors.
+fn load_program(path: Option<&String>) -> Option<Program> {
+    let name = path.map_or("<stdin>", String::as_str);
+    let input = match read_source(path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            return None;
+        }
+    };
+    let mut lexer = Lexer::init_lexer(&input);
+    let mut parser = Parser::new(&mut lexer);
+    let mut program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        for err in parser.errors() {
+            eprintln!("{}: {}", name, err);
+        }
+        return None;
+    }
+    // Expansion runs macro code, so it is bounded like the language
+    // server's, whichever command asked for the program.
+    let mut macro_env = Environment::with_limits(expansion_limits(repl_limits()));
+    define_macros(&mut program, &mut macro_env);
+    match expand_macros(program, &mut macro_env) {
+        Ok(program) => Some(program),
+        Err(e) => {
+            eprintln!(
+                "{}:{}:{}: error: {}",
+                name, e.span.line, e.span.column, e.message
+            );
+            None
+        }
+    }
+}
+
+/// Reads a source file, or standard input when there is no path.
+fn read_source(path: Option<&String>) -> std::io::Result<String> {
+    match path {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut input = String::new();
+            stdin().read_to_string(&mut input).map(|_| input)
+        }
+    }
+}
+
+/// With `debug` each input counts as one source line, so breakpoints can
+/// name it, and inputs starting with `:` are debugger commands.
+fn repl(debug: bool) {
+    let limits = repl_limits();
+    let mut env = Environment::with_limits(limits);
+    let mut macro_env = Environment::with_limits(expansion_limits(limits));
+    let mut operators = OperatorTable::new();
+    let debugger = debug.then(|| {
+        let read = || {
+            let mut line = String::new();
+            match stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line),
+            }
+        };
+        let debugger = Rc::new(RefCell::new(Debugger::new(read, stdout())));
+        env.set_hook(debugger.clone());
+        debugger
+    });
+    let mut line = 0;
     loop {
         let mut input = String::new();
-        let var1 = std::io::stdout().write(">> ".as_bytes()).unwrap();
+        let prompt = match debug {
+            true => format!("{}>> ", line + 1),
+            false => ">> ".to_string(),
+        };
+        let var1 = std::io::stdout().write(prompt.as_bytes()).unwrap();
         std::io::stdout().flush();
         let x = stdin().read_line(&mut input).unwrap();
         match x {
+            0 => break,
             _ => {
+                if let Some(debugger) = &debugger {
+                    if let Some(command) = input.strip_prefix(':') {
+                        debugger.borrow_mut().command(command, &env);
+                        continue;
+                    }
+                    line += 1;
+                    debugger.borrow_mut().add_source(line, &input);
+                }
                 let mut lexer = Lexer::init_lexer(&input);
+                if debug {
+                    lexer.set_origin(Span {
+                        line,
+                        column: 1,
+                        ..Span::default()
+                    });
+                }
                 let mut parser = Parser::new(&mut lexer);
+                parser.register_operators(&operators);
                 let mut program = parser.parse_program();
                 if parser.errors().len() != 0 {
-                    println!("error");
+                    for err in parser.errors() {
+                        println!("\t{}", err);
+                    }
                     continue;
                 }
-                let mut evaluated = eval_prog(program, &mut env);
-                if evaluated != Object::Null {
-                    std::io::stdout().write(format!("{}", evaluated.inspect()).as_bytes());
-                    std::io::stdout().flush();
+                operators = parser.operators().clone();
+                define_macros(&mut program, &mut macro_env);
+                let program = match expand_macros(program, &mut macro_env) {
+                    Ok(program) => program,
+                    Err(e) => {
+                        println!("{}", e.traceback());
+                        continue;
+                    }
+                };
+                let program = fold_constants(program);
+                let mut evaluated = eval_prog(&program, &mut env);
+                if let Some(debugger) = &debugger {
+                    debugger.borrow_mut().resume();
+                }
+                match evaluated {
+                    Object::Error(e) => println!("{}", e.traceback()),
+                    Object::Null => {}
+                    _ => println!("{}", evaluated.inspect()),
                 }
             }
-            0 => println!("Problem"),
         };
     }
 }
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 
-use crate::ast::{BlockStatement, Expression};
+use crate::ast::{printer, BlockStatement, Expression, TypeAnnotation};
+use crate::builtins::Builtin;
+use crate::eval::EvalHook;
+use crate::token::Span;
 
 #[derive(PartialEq, Clone)]
 pub enum Object {
@@ -8,9 +15,26 @@ pub enum Object {
     Boolean(bool),
     Null,
     Return(Box<Object>),
-    Error(String),
+    Error(RuntimeError),
+    /// An error caught by `try`/`catch`. Unlike `Error` it is an ordinary
+    /// value and does not propagate.
+    ErrorValue(RuntimeError),
     Function(Function),
     String(String),
+    Break,
+    Continue,
+    Array(Vec<Object>),
+    Hash(BTreeMap<HashKey, Object>),
+    Range(i64, i64),
+    /// A call in tail position, handed back to the `apply_function` that
+    /// is running the current function so it can make the call in its own
+    /// loop instead of on the native stack.
+    TailCall(Box<TailCall>),
+    /// Unevaluated code, made by `quote` and spliced back into the program
+    /// by macros.
+    Quote(Box<Expression>),
+    Macro(Function),
+    Builtin(Builtin),
 }
 
 impl Object {
@@ -20,15 +44,37 @@ impl Object {
             Object::Boolean(x) => format!("{}", x),
             Object::Null => format!("null"),
             Object::Return(x) => format!("{}", x.inspect()),
-            Object::Error(x) => format!("Error: {}", x),
+            Object::Error(x) => format!("Error: {}", x.message),
+            Object::ErrorValue(x) => format!("{}", x),
             Object::Function(x) => {
                 let mut params = Vec::<String>::new();
-                for par in &x.parameters {
+                for par in x.parameters.iter() {
                     params.push(par.print());
                 }
                 format!("fn( {:#?} ) {{\n {} \n}}", params, x.body.print())
             }
             Object::String(x) => format!("{}", x),
+            Object::Break => "break".to_string(),
+            Object::Continue => "continue".to_string(),
+            Object::Array(x) => {
+                let elems: Vec<String> = x.iter().map(|e| e.inspect()).collect();
+                format!("[{}]", elems.join(", "))
+            }
+            Object::Hash(x) => {
+                let pairs: Vec<String> = x
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_object().inspect(), v.inspect()))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Object::Range(start, end) => format!("{}..{}", start, end),
+            Object::TailCall(x) => format!("tail call to {}", x.function.type_()),
+            Object::Quote(x) => format!("QUOTE({})", printer::print_expression(x)),
+            Object::Macro(x) => {
+                let params: Vec<String> = x.parameters.iter().map(|p| p.print()).collect();
+                format!("macro({}) {{\n {} \n}}", params.join(", "), x.body.print())
+            }
+            Object::Builtin(x) => format!("builtin {}", x.name()),
         }
     }
 
@@ -39,61 +85,603 @@ impl Object {
             Object::Null => "NULL".to_string(),
             Object::Return(_) => "RETURN_VALUE_OBJ".to_string(),
             Object::Error(_) => "ERROR".to_string(),
+            Object::ErrorValue(_) => "ERROR_VALUE".to_string(),
             Object::Function(_) => "FUNCTION".to_string(),
             Object::String(_) => "STRING".to_string(),
+            Object::Break => "BREAK_OBJ".to_string(),
+            Object::Continue => "CONTINUE_OBJ".to_string(),
+            Object::Array(_) => "ARRAY".to_string(),
+            Object::Hash(_) => "HASH".to_string(),
+            Object::Range(_, _) => "RANGE".to_string(),
+            Object::TailCall(_) => "TAIL_CALL".to_string(),
+            Object::Quote(_) => "QUOTE".to_string(),
+            Object::Macro(_) => "MACRO".to_string(),
+            Object::Builtin(_) => "BUILTIN".to_string(),
+        }
+    }
+
+    /// Rough number of bytes this value keeps alive, used for memory
+    /// limits. Functions count only their own slot; their scope is charged
+    /// separately.
+    pub fn size(&self) -> usize {
+        let own = std::mem::size_of::<Object>();
+        match self {
+            Object::String(x) => own + x.len(),
+            Object::Error(x) | Object::ErrorValue(x) => own + x.message.len(),
+            Object::Return(x) => own + x.size(),
+            Object::Array(x) => own + x.iter().map(|e| e.size()).sum::<usize>(),
+            Object::Hash(x) => {
+                own + x
+                    .iter()
+                    .map(|(k, v)| k.to_object().size() + v.size())
+                    .sum::<usize>()
+            }
+            _ => own,
+        }
+    }
+
+    /// How many arrays and hashes deep this value goes.
+    pub fn nesting(&self) -> usize {
+        match self {
+            Object::Array(x) => 1 + x.iter().map(|e| e.nesting()).max().unwrap_or(0),
+            Object::Hash(x) => 1 + x.values().map(|v| v.nesting()).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(x) => Some(HashKey::Integer(*x)),
+            Object::Boolean(x) => Some(HashKey::Boolean(*x)),
+            Object::String(x) => Some(HashKey::String(x.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone)]
+pub struct TailCall {
+    pub function: Object,
+    pub args: Vec<Object>,
+    pub span: Span,
+}
+
+/// Broad category of a runtime error, shown as the error's name in
+/// tracebacks.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ErrorKind {
+    /// An identifier that is not bound in any enclosing scope.
+    Name,
+    /// An operator, call or loop applied to the wrong kind of value.
+    Type,
+    /// An index outside the bounds of an array.
+    Index,
+    /// A function called with the wrong number of arguments.
+    Argument,
+    ZeroDivision,
+    Overflow,
+    /// A construct used where it is not allowed, such as `break` outside a
+    /// loop, or a malformed tree.
+    Syntax,
+    /// Raised by a script with `throw`.
+    User,
+    /// A failed `assert`, `assert_eq` or `assert_error`.
+    Assertion,
+    /// The run evaluated more expressions than `Limits::max_steps`.
+    StepLimit,
+    /// Calls nested deeper than `Limits::max_depth`, or collections nested
+    /// deeper than `MAX_NESTING`.
+    DepthLimit,
+    /// The run took longer than `Limits::timeout`.
+    Timeout,
+    /// A value would take the memory held past `Limits::max_memory`.
+    Memory,
+}
+
+impl ErrorKind {
+    /// Whether the error comes from a limit on the run rather than from
+    /// the script's own logic.
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::StepLimit | ErrorKind::DepthLimit | ErrorKind::Timeout | ErrorKind::Memory
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Name => "NameError",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Index => "IndexError",
+            ErrorKind::Argument => "ArgumentError",
+            ErrorKind::ZeroDivision => "ZeroDivisionError",
+            ErrorKind::Overflow => "OverflowError",
+            ErrorKind::Syntax => "SyntaxError",
+            ErrorKind::User => "UserError",
+            ErrorKind::Assertion => "AssertionError",
+            ErrorKind::StepLimit => "StepLimitError",
+            ErrorKind::DepthLimit => "RecursionError",
+            ErrorKind::Timeout => "TimeoutError",
+            ErrorKind::Memory => "MemoryError",
+        }
+    }
+}
+
+/// One active function call at the time an error was raised: the function
+/// that was called and where it was called from.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub span: Span,
+}
+
+/// A runtime error. `span` is the innermost expression that failed, and
+/// `stack` lists the calls it propagated through, innermost first.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub stack: Vec<Frame>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: String) -> RuntimeError {
+        RuntimeError {
+            kind,
+            message,
+            span: Span::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Renders the error with its call stack, outermost call first. Runs
+    /// of identical frames, as in a deep recursion, are shown once with a
+    /// count.
+    pub fn traceback(&self) -> String {
+        let mut out = String::from("Traceback (most recent call last):\n");
+        let mut frames = self.stack.iter().rev().peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeats = 0;
+            while frames.peek() == Some(&frame) {
+                frames.next();
+                repeats += 1;
+            }
+            out.push_str(&format!("  at {}, in {}\n", frame.span, frame.function));
+            if repeats > 0 {
+                out.push_str(&format!(
+                    "  [previous frame repeated {} more times]\n",
+                    repeats
+                ));
+            }
+        }
+        if self.span.is_known() {
+            out.push_str(&format!("  at {}\n", self.span));
+        }
+        out.push_str(&self.to_string());
+        out
+    }
+
+    /// Looks up a field of a caught error, as in `e["message"]`.
+    pub fn field(&self, name: &str) -> Object {
+        match name {
+            "message" => Object::String(self.message.clone()),
+            "kind" => Object::String(self.kind.name().to_string()),
+            "line" => Object::Integer(self.span.line as i64),
+            "column" => Object::Integer(self.span.column as i64),
+            _ => Object::Null,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)
+    }
+}
+
+/// Keys of a hash object. Only integers, booleans and strings can be used as
+/// keys; the map keeps them ordered so iteration is deterministic.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl HashKey {
+    pub fn to_object(&self) -> Object {
+        match self {
+            HashKey::Integer(x) => Object::Integer(*x),
+            HashKey::Boolean(x) => Object::Boolean(*x),
+            HashKey::String(x) => Object::String(x.clone()),
         }
     }
 }
 
 #[derive(PartialEq, Clone)]
 pub struct Function {
-    pub parameters: Vec<Expression>,
-    pub body: BlockStatement,
+    pub name: Option<String>,
+    pub parameters: Rc<Vec<Expression>>,
+    /// Annotations checked when the function is called and when it
+    /// returns. Macros have none.
+    pub param_types: Rc<Vec<Option<TypeAnnotation>>>,
+    pub return_type: Option<Rc<TypeAnnotation>>,
+    pub body: Rc<BlockStatement>,
     pub env: Environment,
 }
 
-#[derive(PartialEq, Clone)]
+/// Call depth allowed by `Limits::default()`. Each call costs tens of
+/// kilobytes of native stack in a debug build, so this keeps a runaway
+/// recursion well inside an 8 MiB main-thread stack.
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+/// How deeply arrays and hashes may nest. Printing, comparing, copying and
+/// dropping a value recurse once per level, so building anything deeper
+/// raises an error instead of risking the native stack.
+pub const MAX_NESTING: usize = 1000;
+
+/// How much work a single run of `eval_prog` may do. `None` means
+/// unbounded. Exceeding a limit raises an error of its own kind; a script
+/// can catch it, but once the step or time budget is spent every further
+/// step fails again, so the error always reaches the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Approximate bytes that bindings and newly built strings and
+    /// collections may hold at once.
+    pub max_memory: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            timeout: None,
+            max_memory: None,
+        }
+    }
+}
+
+/// Work done so far against the limits, shared by every scope that
+/// descends from the same root environment. `memory` is the approximate
+/// number of bytes held by live scopes. The hook watching runs lives here
+/// too, so every scope reaches it.
+struct Budget {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    memory: usize,
+    started: Instant,
+    /// Set once the time limit has been exceeded, so the run fails at every
+    /// later step rather than only at the next clock reading.
+    timed_out: bool,
+    hook: Option<Rc<RefCell<dyn EvalHook>>>,
+}
+
+/// The clock is only read every this many steps.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// A handle to a scope. Cloning the handle shares the scope, so closures,
+/// loop bodies and the caller all see the same bindings and `assign` can
+/// update a name in the scope where it was defined.
+#[derive(Clone)]
 pub struct Environment {
+    scope: Handle,
+}
+
+/// A function bound in the very scope it closes over holds that scope
+/// weakly, or the scope would keep itself alive through its own bindings
+/// and never be freed. Such handles only live in the scope's store; `get`
+/// makes them strong again before a function leaves it.
+#[derive(Clone)]
+enum Handle {
+    Strong(Rc<RefCell<Scope>>),
+    Weak(Weak<RefCell<Scope>>),
+}
+
+struct Scope {
     store: HashMap<String, Object>,
-    outer: Option<Box<Environment>>,
+    outer: Option<Environment>,
+    budget: Rc<RefCell<Budget>>,
+    /// Bytes this scope's bindings are charged for in the budget.
+    held: usize,
+}
+
+impl Scope {
+    fn new(budget: Rc<RefCell<Budget>>, outer: Option<Environment>) -> Scope {
+        Scope {
+            store: HashMap::<String, Object>::new(),
+            outer,
+            budget,
+            held: 0,
+        }
+    }
+
+    /// Binds `name` in this scope and moves the difference in size into
+    /// the budget, failing if that takes it past the memory limit. The
+    /// replaced value is handed back so the caller drops it after releasing
+    /// its borrows, as it may own the last handle to another scope.
+    fn bind(&mut self, name: String, obj: Object) -> Result<Option<Object>, RuntimeError> {
+        let added = binding_size(&name, &obj);
+        let removed = match self.store.get(&name) {
+            Some(old) => binding_size(&name, old),
+            None => 0,
+        };
+        let mut budget = self.budget.borrow_mut();
+        let memory = budget.memory + added - removed;
+        if let Some(max) = budget.limits.max_memory {
+            if added > removed && memory > max {
+                return Err(RuntimeError::new(
+                    ErrorKind::Memory,
+                    format!("out of memory budget of {} bytes", max),
+                ));
+            }
+        }
+        budget.memory = memory;
+        drop(budget);
+        self.held = self.held + added - removed;
+        Ok(self.store.insert(name, obj))
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let mut budget = self.budget.borrow_mut();
+        budget.memory = budget.memory.saturating_sub(self.held);
+    }
+}
+
+fn binding_size(name: &str, obj: &Object) -> usize {
+    name.len() + obj.size()
+}
+
+impl Handle {
+    fn as_ptr(&self) -> *const RefCell<Scope> {
+        match self {
+            Handle::Strong(scope) => Rc::as_ptr(scope),
+            Handle::Weak(scope) => scope.as_ptr(),
+        }
+    }
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        std::ptr::eq(self.scope.as_ptr(), other.scope.as_ptr())
+    }
 }
 
 impl Environment {
+    /// An environment with the default limits. The REPL sets its own.
+    #[allow(dead_code)]
     pub fn new() -> Environment {
+        Environment::with_limits(Limits::default())
+    }
+
+    pub fn with_limits(limits: Limits) -> Environment {
+        let budget = Rc::new(RefCell::new(Budget {
+            limits,
+            steps: 0,
+            depth: 0,
+            memory: 0,
+            started: Instant::now(),
+            timed_out: false,
+            hook: None,
+        }));
         Environment {
-            store: HashMap::<String, Object>::new(),
-            outer: None,
+            scope: Handle::Strong(Rc::new(RefCell::new(Scope::new(budget, None)))),
         }
     }
 
     pub fn new_enclosed(&mut self, envir: Environment) -> Environment {
-        let mut env = Environment::new();
-        env.outer = Some(Box::new(envir));
-        env
-    }
-
-    pub fn get(&self, name: String) -> Option<Object> {
-        let p = self.store.get(&name);
-        match p {
-            Some(c) => return Some(c.clone()),
-            None => {
-                if self.outer != None {
-                    let p = self.outer.as_ref()?;
-
-                    let pp = p.get(name.clone());
-                    match pp {
-                        Some(c) => return Some(c),
-                        None => return None,
-                    }
-                } else {
-                    None
-                }
+        let budget = envir.budget();
+        Environment {
+            scope: Handle::Strong(Rc::new(RefCell::new(Scope::new(budget, Some(envir))))),
+        }
+    }
+
+    fn scope(&self) -> Rc<RefCell<Scope>> {
+        match &self.scope {
+            Handle::Strong(scope) => scope.clone(),
+            Handle::Weak(scope) => scope
+                .upgrade()
+                .expect("weak environment used outside its scope"),
+        }
+    }
+
+    /// A strong handle to the same scope.
+    fn strong(&self) -> Environment {
+        Environment {
+            scope: Handle::Strong(self.scope()),
+        }
+    }
+
+    /// `obj` as this scope stores it: a function closing over this scope
+    /// keeps only a weak handle to it.
+    fn held_here(&self, obj: Object) -> Object {
+        match obj {
+            Object::Function(mut f) if f.env == *self => {
+                f.env = Environment {
+                    scope: Handle::Weak(Rc::downgrade(&self.scope())),
+                };
+                Object::Function(f)
+            }
+            Object::Macro(mut f) if f.env == *self => {
+                f.env = Environment {
+                    scope: Handle::Weak(Rc::downgrade(&self.scope())),
+                };
+                Object::Macro(f)
             }
+            _ => obj,
         }
     }
 
-    pub fn set(&mut self, name: String, obj: Object) -> Object {
-        self.store.insert(name, obj.clone());
-        return obj;
+    fn budget(&self) -> Rc<RefCell<Budget>> {
+        self.scope().borrow().budget.clone()
+    }
+
+    /// Starts a fresh step count and clock for a new run. Memory held by
+    /// existing bindings carries over.
+    pub fn reset_budget(&self) {
+        let budget = self.budget();
+        let mut budget = budget.borrow_mut();
+        budget.steps = 0;
+        budget.depth = 0;
+        budget.started = Instant::now();
+        budget.timed_out = false;
+    }
+
+    /// Counts one evaluation step against the step and time limits.
+    pub fn step(&self) -> Result<(), RuntimeError> {
+        let budget = self.budget();
+        let mut budget = budget.borrow_mut();
+        budget.steps += 1;
+        if let Some(max) = budget.limits.max_steps {
+            if budget.steps > max {
+                return Err(RuntimeError::new(
+                    ErrorKind::StepLimit,
+                    format!("step limit of {} exceeded", max),
+                ));
+            }
+        }
+        if let Some(timeout) = budget.limits.timeout {
+            if budget.steps.is_multiple_of(CLOCK_INTERVAL) && budget.started.elapsed() > timeout {
+                budget.timed_out = true;
+            }
+            if budget.timed_out {
+                return Err(RuntimeError::new(
+                    ErrorKind::Timeout,
+                    format!("time limit of {:?} exceeded", timeout),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Enters a function call. Every successful call must be matched by
+    /// `exit_call`.
+    pub fn enter_call(&self) -> Result<(), RuntimeError> {
+        let budget = self.budget();
+        let mut budget = budget.borrow_mut();
+        if let Some(max) = budget.limits.max_depth {
+            if budget.depth >= max {
+                return Err(RuntimeError::new(
+                    ErrorKind::DepthLimit,
+                    format!("maximum call depth of {} exceeded", max),
+                ));
+            }
+        }
+        budget.depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&self) {
+        let budget = self.budget();
+        let mut budget = budget.borrow_mut();
+        budget.depth = budget.depth.saturating_sub(1);
+    }
+
+    /// Checks that a new value of `bytes` fits in the memory budget
+    /// alongside everything already bound.
+    pub fn reserve(&self, bytes: usize) -> Result<(), RuntimeError> {
+        let budget = self.budget();
+        let budget = budget.borrow();
+        match budget.limits.max_memory {
+            Some(max) if budget.memory.saturating_add(bytes) > max => Err(RuntimeError::new(
+                ErrorKind::Memory,
+                format!("out of memory budget of {} bytes", max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Approximate bytes held by bindings in live scopes. The REPL does not
+    /// report it; it is there for hosts embedding the interpreter.
+    #[allow(dead_code)]
+    pub fn memory_used(&self) -> usize {
+        self.budget().borrow().memory
+    }
+
+    /// Has `hook` watch every run in this environment and in the scopes
+    /// made from it.
+    pub fn set_hook(&self, hook: Rc<RefCell<dyn EvalHook>>) {
+        self.budget().borrow_mut().hook = Some(hook);
+    }
+
+    pub fn hook(&self) -> Option<Rc<RefCell<dyn EvalHook>>> {
+        self.budget().borrow().hook.clone()
+    }
+
+    /// The bindings of this scope and of each one enclosing it, innermost
+    /// first, each sorted by name.
+    pub fn scopes(&self) -> Vec<Vec<(String, Object)>> {
+        let scope = self.scope();
+        let scope = scope.borrow();
+        let mut bindings: Vec<(String, Object)> = scope
+            .store
+            .iter()
+            .map(|(k, v)| (k.clone(), released(v)))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut scopes = vec![bindings];
+        if let Some(outer) = &scope.outer {
+            scopes.extend(outer.scopes());
+        }
+        scopes
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        let scope = self.scope();
+        let scope = scope.borrow();
+        match scope.store.get(name) {
+            Some(c) => Some(released(c)),
+            None => match &scope.outer {
+                Some(outer) => outer.get(name),
+                None => None,
+            },
+        }
+    }
+
+    pub fn set(&mut self, name: String, obj: Object) -> Result<(), RuntimeError> {
+        let obj = self.held_here(obj);
+        let old = self.scope().borrow_mut().bind(name, obj)?;
+        drop(old);
+        Ok(())
+    }
+
+    /// Rebinds an existing name in the innermost scope that defines it.
+    /// Returns false if the name is not bound anywhere in the chain.
+    pub fn assign(&mut self, name: &str, obj: Object) -> Result<bool, RuntimeError> {
+        let scope = self.scope();
+        let mut scope = scope.borrow_mut();
+        if scope.store.contains_key(name) {
+            let old = scope.bind(name.to_string(), self.held_here(obj))?;
+            drop(scope);
+            drop(old);
+            return Ok(true);
+        }
+        match &mut scope.outer {
+            Some(outer) => outer.assign(name, obj),
+            None => Ok(false),
+        }
+    }
+}
+
+/// A stored value as it is handed out of its scope, with the handle of a
+/// function closing over that scope made strong again.
+fn released(obj: &Object) -> Object {
+    match obj {
+        Object::Function(f) => Object::Function(Function {
+            env: f.env.strong(),
+            ..f.clone()
+        }),
+        Object::Macro(f) => Object::Macro(Function {
+            env: f.env.strong(),
+            ..f.clone()
+        }),
+        _ => obj.clone(),
     }
 }
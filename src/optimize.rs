@@ -0,0 +1,209 @@
+use crate::ast::*;
+use crate::eval::{eval_infix_expr, eval_prefix, is_call_to};
+use crate::object::{Environment, Object};
+use crate::token::{Span, Token, TokenType};
+
+/// Evaluates operators whose operands are all literals ahead of time and
+/// drops the branch of an `if` whose condition is a literal. Operators are
+/// applied with the evaluator's own functions, and anything that would fail
+/// is left in place so it still fails at runtime.
+pub fn fold_constants(program: Program) -> Program {
+    let mut folder = ConstantFolder {
+        env: Environment::new(),
+    };
+    folder.fold_program(program)
+}
+
+struct ConstantFolder {
+    /// Only consulted for the size check on string concatenation.
+    env: Environment,
+}
+
+impl Folder for ConstantFolder {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        let expr = fold::walk_expression(self, expr);
+        let folded = match &expr {
+            Expression::PrefixExpression(x) => match x.right.as_deref().and_then(constant) {
+                Some(right) => eval_prefix(&x.operator, right),
+                None => return expr,
+            },
+            Expression::InfixExpression(x) if x.token.type_ != TokenType::OPERATOR => {
+                let left = x.left.as_deref().and_then(constant);
+                let right = x.right.as_deref().and_then(constant);
+                match (left, right) {
+                    (Some(l), Some(r)) => eval_infix_expr(&x.operator, l, r, &self.env),
+                    _ => return expr,
+                }
+            }
+            Expression::IfExpression(x) => match x.cond.as_deref().and_then(constant) {
+                Some(cond) => return prune_if(x.clone(), cond == Object::Boolean(true)),
+                None => return expr,
+            },
+            _ => return expr,
+        };
+        literal(folded, &expr.token().span).unwrap_or(expr)
+    }
+
+    /// Quoted code is a value, so it is left as written.
+    fn fold_call_expression(&mut self, expr: CallExpression) -> CallExpression {
+        match is_call_to(&expr, "quote") {
+            true => expr,
+            false => fold::walk_call_expression(self, expr),
+        }
+    }
+}
+
+/// The value of a literal the folder can compute with.
+fn constant(expr: &Expression) -> Option<Object> {
+    match expr {
+        Expression::IntegerLiteral(x) => Some(Object::Integer(x.value)),
+        Expression::Boolean(x) => Some(Object::Boolean(x.value)),
+        Expression::StringLiteral(x) => Some(Object::String(x.value.clone())),
+        _ => None,
+    }
+}
+
+/// The literal for a folded value. Errors and values without a literal
+/// form, such as ranges, give `None`.
+fn literal(obj: Object, span: &Span) -> Option<Expression> {
+    let token = |type_, literal: String| Token {
+        type_,
+        literal,
+        span: *span,
+    };
+    match obj {
+        Object::Integer(value) => Some(Expression::IntegerLiteral(IntegerLiteral {
+            token: token(TokenType::INT, value.to_string()),
+            value,
+        })),
+        Object::Boolean(value) => Some(Expression::Boolean(Boolean {
+            token: match value {
+                true => token(TokenType::TRUE, "true".to_string()),
+                false => token(TokenType::FALSE, "false".to_string()),
+            },
+            value,
+        })),
+        Object::String(value) => Some(Expression::StringLiteral(StringLiteral {
+            token: token(TokenType::STRING, value.clone()),
+            value,
+        })),
+        _ => None,
+    }
+}
+
+/// Keeps only the branch a constant condition selects. A branch holding a
+/// single expression replaces the `if` outright; otherwise the `if` stays
+/// with a `true` condition so the branch still runs as a block, or with an
+/// empty block when there is nothing left to run.
+fn prune_if(mut expr: IfExpression, taken: bool) -> Expression {
+    let span = expr.token.span;
+    let branch = match taken {
+        true => expr.consequence.take(),
+        false => expr.alternative.take(),
+    };
+    let block = match branch.map(|b| *b) {
+        Some(Statement::BlockStatement(block)) => block,
+        _ => BlockStatement {
+            token: Token {
+                type_: TokenType::LBRACE,
+                literal: "{".to_string(),
+                span,
+            },
+            statements: vec![],
+            end: Token {
+                type_: TokenType::RBRACE,
+                literal: "}".to_string(),
+                span,
+            },
+        },
+    };
+    if let [Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. })] =
+        block.statements.as_slice()
+    {
+        return e.as_ref().clone();
+    }
+    expr.cond = literal(Object::Boolean(!block.statements.is_empty()), &span).map(Box::new);
+    expr.consequence = Some(Box::new(Statement::BlockStatement(block)));
+    expr.alternative = None;
+    Expression::IfExpression(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::printer::Printer;
+    use crate::eval::eval_prog;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        program
+    }
+
+    fn source(program: &Program) -> String {
+        let mut printer = Printer::new("", &[]);
+        printer.program(program);
+        printer.finish()
+    }
+
+    #[test]
+    fn fold_constants_test() {
+        let tests = vec![
+            ("2 * 60 * 60", "7200;\n"),
+            ("let x = 1 + 2 * 3 - -4;", "let x = 11;\n"),
+            ("\"a\" + \"b\" == \"ab\"", "true;\n"),
+            ("!(1 < 2)", "false;\n"),
+            ("x + 2 * 3", "x + 6;\n"),
+            ("1 / 0", "1 / 0;\n"),
+            ("(1 / 0) + 1", "1 / 0 + 1;\n"),
+            ("9223372036854775807 + 1", "9223372036854775807 + 1;\n"),
+            ("true == true", "true == true;\n"),
+            ("\"a\" - \"b\"", "\"a\" - \"b\";\n"),
+            ("1..3", "1..3;\n"),
+            ("if (true) { a } else { b }", "a;\n"),
+            ("if (1 > 2) { a } else { b }", "b;\n"),
+            ("if (1) { a } else { b }", "b;\n"),
+            ("if (false) { a }", "if (false) {}\n"),
+            (
+                "if (true) { let y = 1; y } else { b }",
+                "if (true) {\n    let y = 1;\n    y\n}\n",
+            ),
+            ("if (x) { 1 + 1 } else { 2 }", "if (x) { 2 } else { 2 }\n"),
+            (
+                "let f = fn(a) { a * (60 * 60) };",
+                "let f = fn(a) { a * 3600 };\n",
+            ),
+            ("quote(1 + 2)", "quote(1 + 2);\n"),
+            (
+                "quote(if (true) { a } else { b })",
+                "quote(if (true) { a } else { b });\n",
+            ),
+            ("f(1 + 2)", "f(3);\n"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(source(&fold_constants(parse(input))), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn fold_constants_eval_test() {
+        let inputs = vec![
+            "let f = fn(n) { if (2 > 1) { n * (2 + 3) } else { 0 } }; f(4)",
+            "if (1 == 2) { 1 }",
+            "let g = fn() { if (true) { return 10 - 3; } 99 }; g()",
+            "\"x\" + \"y\"",
+            "let h = fn() { 10 / (5 - 5) }; h()",
+            "-(-9223372036854775807 - 1)",
+        ];
+        for input in inputs {
+            let expected = eval_prog(&parse(input), &mut Environment::new()).inspect();
+            let folded = fold_constants(parse(input));
+            let got = eval_prog(&folded, &mut Environment::new()).inspect();
+            assert_eq!(got, expected, "{}", input);
+        }
+    }
+}
@@ -2,34 +2,63 @@ use crate::ast::*;
 use crate::lexer::Lexer;
 use crate::token::*;
 use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Binding powers, from loosest to tightest. `infixl` and `infixr` accept
+/// levels `ASSIGN..=INDEX`, so a declared operator sits beside the built-in
+/// ones of the same level:
+///
+/// | level | built-in operators             |
+/// |-------|--------------------------------|
+/// | 1     | `=` `+=` `-=` `*=` `/=`        |
+/// | 2     | `==` `!=`                      |
+/// | 3     | `<` `>`                        |
+/// | 4     | `..`                           |
+/// | 5     | `+` `-`                        |
+/// | 6     | `*` `/`                        |
+/// | 7     | prefix `-` `!`                 |
+/// | 8     | call `f(x)`                    |
+/// | 9     | index `a[i]`                   |
+///
+/// `+` parses its right operand one level lower than its own, so it groups
+/// to the right: `a + b - c` is `a + (b - c)`, and an operator declared at
+/// level 5 after a `+` binds before it.
+pub const LOWEST: i32 = 0;
+pub const ASSIGN: i32 = 1;
+pub const EQUALS: i32 = 2;
+pub const LESSGREATER: i32 = 3;
+pub const RANGE: i32 = 4;
+pub const SUM: i32 = 5;
+pub const PRODUCT: i32 = 6;
+pub const PREFIX: i32 = 7;
+pub const CALL: i32 = 8;
+pub const INDEX: i32 = 9;
+
+/// Parses an expression that starts at the current token.
+pub type PrefixParseFn = fn(&mut Parser) -> Option<Expression>;
+
+/// Parses the rest of an expression whose operator is the current token,
+/// given the already parsed left operand.
+pub type InfixParseFn = fn(&mut Parser, Option<Box<Expression>>) -> Option<Expression>;
+
+#[derive(Clone, Copy)]
+pub struct InfixParselet {
+    precedence: i32,
+    parse: InfixParseFn,
+}
 
-const PARSE_IDENTIFIER: &str = "parse_identifier";
-const PARSE_INTEGER_LITERAL: &str = "parse_integer_literal";
-const PARSE_PREFIX_EXPR: &str = "parse_prefix_expr";
-const PARSE_INFIX_EXPR: &str = "parse_infix_expr";
-const PARSE_BOOLEAN: &str = "parse_boolean_expr";
-const PARSE_GROUP: &str = "parse_group_expr";
-const PARSE_IF: &str = "parse_if_expr";
-const PARSE_FUNCTION: &str = "parse_function_literal";
-const PARSE_CALL: &str = "parse_call";
-const PARSE_STRING: &str = "parse_string";
-
-const LOWEST: i32 = 0;
-const EQUALS: i32 = 1;
-const LESSGREATER: i32 = 2;
-const SUM: i32 = 3;
-const PRODUCT: i32 = 4;
-const PREFIX: i32 = 5;
-const CALL: i32 = 6;
+/// Parselets for operator symbols such as `<+>`, keyed by symbol.
+pub type OperatorTable = HashMap<String, InfixParselet>;
 
 pub struct Parser<'a> {
     lex: &'a mut Lexer<'a>,
     cur_token: Token,
     peek_token: Token,
-    prefix_fns: HashMap<TokenType, &'a str>,
-    infix_fns: HashMap<TokenType, &'a str>,
-    precedence: HashMap<TokenType, i32>,
-    errors: Vec<String>,
+    prefix_fns: HashMap<TokenType, PrefixParseFn>,
+    infix_fns: HashMap<TokenType, InfixParselet>,
+    operators: OperatorTable,
+    /// Each error with the span of the token it is about.
+    errors: Vec<(String, Span)>,
 }
 
 impl<'a> Parser<'a> {
@@ -39,63 +68,122 @@ impl<'a> Parser<'a> {
             cur_token: Token {
                 type_: TokenType::UNDEFINED,
                 literal: "".to_string(),
+                span: Span::default(),
             },
             peek_token: Token {
                 type_: TokenType::UNDEFINED,
                 literal: "".to_string(),
+                span: Span::default(),
             },
-            prefix_fns: HashMap::<TokenType, &str>::new(),
-            infix_fns: HashMap::<TokenType, &str>::new(),
-            precedence: HashMap::<TokenType, i32>::new(),
-            errors: Vec::<String>::new(),
+            prefix_fns: HashMap::<TokenType, PrefixParseFn>::new(),
+            infix_fns: HashMap::<TokenType, InfixParselet>::new(),
+            operators: OperatorTable::new(),
+            errors: vec![],
         };
         p.next_token();
         p.next_token();
 
-        p.precedence.insert(TokenType::EQ, EQUALS);
-        p.precedence.insert(TokenType::NEQ, EQUALS);
-        p.precedence.insert(TokenType::LT, LESSGREATER);
-        p.precedence.insert(TokenType::GT, LESSGREATER);
-        p.precedence.insert(TokenType::PLUS, SUM);
-        p.precedence.insert(TokenType::MINUS, SUM);
-        p.precedence.insert(TokenType::SLASH, PRODUCT);
-        p.precedence.insert(TokenType::ASTERICK, PRODUCT);
-        p.precedence.insert(TokenType::LPAREN, CALL);
-
-        p.register_prefix(TokenType::IDENT, PARSE_IDENTIFIER);
-        p.register_prefix(TokenType::INT, PARSE_INTEGER_LITERAL);
-        p.register_prefix(TokenType::BANG, PARSE_PREFIX_EXPR);
-        p.register_prefix(TokenType::MINUS, PARSE_PREFIX_EXPR);
-        p.register_prefix(TokenType::TRUE, PARSE_BOOLEAN);
-        p.register_prefix(TokenType::FALSE, PARSE_BOOLEAN);
-        p.register_prefix(TokenType::LPAREN, PARSE_GROUP);
-        p.register_prefix(TokenType::IF, PARSE_IF);
-        p.register_prefix(TokenType::FUNCTION, PARSE_FUNCTION);
-        p.register_prefix(TokenType::STRING, PARSE_STRING);
-
-        p.register_infix(TokenType::PLUS, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::MINUS, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::SLASH, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::ASTERICK, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::EQ, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::NEQ, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::LT, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::GT, PARSE_INFIX_EXPR);
-        p.register_infix(TokenType::LPAREN, PARSE_CALL);
+        p.register_prefix(TokenType::IDENT, |p| p.parse_identifier());
+        p.register_prefix(TokenType::INT, |p| p.parse_integer_literal());
+        p.register_prefix(TokenType::BANG, |p| p.parse_prefix_expression());
+        p.register_prefix(TokenType::MINUS, |p| p.parse_prefix_expression());
+        p.register_prefix(TokenType::TRUE, |p| p.parse_boolean());
+        p.register_prefix(TokenType::FALSE, |p| p.parse_boolean());
+        p.register_prefix(TokenType::LPAREN, |p| p.parse_group_expression());
+        p.register_prefix(TokenType::IF, |p| p.parse_if_expression());
+        p.register_prefix(TokenType::FUNCTION, |p| p.parse_function_expression());
+        p.register_prefix(TokenType::STRING, |p| p.parse_string());
+        p.register_prefix(TokenType::TEMPLATE, |p| p.parse_template());
+        p.register_prefix(TokenType::LBRACKET, |p| p.parse_array_literal());
+        p.register_prefix(TokenType::LBRACE, |p| p.parse_hash_literal());
+        p.register_prefix(TokenType::TRY, |p| p.parse_try_expression());
+        p.register_prefix(TokenType::MACRO, |p| p.parse_macro_literal());
+
+        let infix: InfixParseFn = |p, left| p.parse_infix_expression(left);
+        let assign: InfixParseFn = |p, target| p.parse_assign_expression(target);
+        p.register_infix(TokenType::ASSIGN, ASSIGN, assign);
+        p.register_infix(TokenType::PLUSASSIGN, ASSIGN, assign);
+        p.register_infix(TokenType::MINUSASSIGN, ASSIGN, assign);
+        p.register_infix(TokenType::ASTERICKASSIGN, ASSIGN, assign);
+        p.register_infix(TokenType::SLASHASSIGN, ASSIGN, assign);
+        p.register_infix(TokenType::EQ, EQUALS, infix);
+        p.register_infix(TokenType::NEQ, EQUALS, infix);
+        p.register_infix(TokenType::LT, LESSGREATER, infix);
+        p.register_infix(TokenType::GT, LESSGREATER, infix);
+        p.register_infix(TokenType::DOTDOT, RANGE, infix);
+        p.register_infix(TokenType::PLUS, SUM, infix);
+        p.register_infix(TokenType::MINUS, SUM, infix);
+        p.register_infix(TokenType::SLASH, PRODUCT, infix);
+        p.register_infix(TokenType::ASTERICK, PRODUCT, infix);
+        p.register_infix(TokenType::LPAREN, CALL, |p, l| p.parse_call_expression(l));
+        p.register_infix(TokenType::LBRACKET, INDEX, |p, l| {
+            p.parse_index_expression(l)
+        });
 
         p
     }
     pub fn next_token(&mut self) {
         self.cur_token = self.peek_token.clone();
         self.peek_token = self.lex.next_token();
+        for error in self.lex.take_errors() {
+            // A rewind lexes the same string again.
+            if !self.errors.contains(&error) {
+                self.errors.push(error);
+            }
+        }
     }
 
-    pub fn register_prefix(&mut self, tok_type: TokenType, fun: &'a str) {
+    pub fn register_prefix(&mut self, tok_type: TokenType, fun: PrefixParseFn) {
         self.prefix_fns.insert(tok_type, fun);
     }
 
-    pub fn register_infix(&mut self, tok_type: TokenType, fun: &'a str) {
-        self.infix_fns.insert(tok_type, fun);
+    pub fn register_infix(&mut self, tok_type: TokenType, precedence: i32, fun: InfixParseFn) {
+        self.infix_fns.insert(
+            tok_type,
+            InfixParselet {
+                precedence,
+                parse: fun,
+            },
+        );
+    }
+
+    /// Registers an infix parselet for a symbol the lexer does not know, such
+    /// as `<+>`. The symbol is lexed as an `OPERATOR` token from then on.
+    pub fn register_operator(&mut self, symbol: &str, precedence: i32, fun: InfixParseFn) {
+        let mut ops = OperatorTable::new();
+        ops.insert(
+            symbol.to_string(),
+            InfixParselet {
+                precedence,
+                parse: fun,
+            },
+        );
+        self.register_operators(&ops);
+    }
+
+    /// Registers every operator in `ops`, such as those declared while
+    /// parsing an earlier REPL line. The lookahead tokens were lexed before
+    /// the operators were known, so they are lexed again.
+    pub fn register_operators(&mut self, ops: &OperatorTable) {
+        for (symbol, parselet) in ops {
+            self.operators.insert(symbol.clone(), *parselet);
+            self.lex.add_operator(symbol);
+        }
+        self.lex.rewind(self.cur_token.span.start);
+        self.next_token();
+        self.next_token();
+    }
+
+    /// Operators registered so far, including those declared by the program.
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    fn infix_parselet(&self, tok: &Token) -> Option<InfixParselet> {
+        match tok.type_ {
+            TokenType::OPERATOR => self.operators.get(&tok.literal).copied(),
+            t => self.infix_fns.get(&t).copied(),
+        }
     }
 
     fn cur_token_is(&mut self, t: TokenType) -> bool {
@@ -106,7 +194,7 @@ impl<'a> Parser<'a> {
         self.peek_token.type_ == t
     }
 
-    fn expect_peek(&mut self, t: TokenType) -> bool {
+    pub fn expect_peek(&mut self, t: TokenType) -> bool {
         if self.peek_token_is(t) {
             self.next_token();
             return true;
@@ -116,8 +204,26 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// The comments the lexer has skipped so far.
+    pub fn comments(&self) -> &[Comment] {
+        self.lex.comments()
+    }
+
     pub fn errors(&mut self) -> Vec<String> {
-        self.errors.clone()
+        self.errors
+            .iter()
+            .map(|(message, _)| message.clone())
+            .collect()
+    }
+
+    /// The errors with the span of the token each is about.
+    pub fn located_errors(&self) -> &[(String, Span)] {
+        &self.errors
+    }
+
+    /// Records an error about the current token.
+    fn error(&mut self, message: String) {
+        self.errors.push((message, self.cur_token.span));
     }
 
     fn peek_error(&mut self, tok: TokenType) {
@@ -125,22 +231,20 @@ impl<'a> Parser<'a> {
             "expected next token to be {tok}, got {}",
             self.peek_token.type_
         );
-        self.errors.push(message);
+        self.errors.push((message, self.peek_token.span));
     }
 
     fn peek_precedence(&mut self) -> i32 {
-        let prec = self.precedence.get(&self.peek_token.type_);
-        match prec {
-            Some(prec) => return prec.clone(),
-            None => return LOWEST,
+        match self.infix_parselet(&self.peek_token) {
+            Some(infix) => infix.precedence,
+            None => LOWEST,
         }
     }
 
     fn cur_precedence(&mut self) -> i32 {
-        let prec = self.precedence.get(&self.cur_token.type_);
-        match prec {
-            Some(prec) => return prec.clone(),
-            None => return LOWEST,
+        match self.infix_parselet(&self.cur_token) {
+            Some(infix) => infix.precedence,
+            None => LOWEST,
         }
     }
 
@@ -175,6 +279,28 @@ impl<'a> Parser<'a> {
                     None => return None,
                 }
             }
+            TokenType::WHILE => self.parse_while_statement(),
+            TokenType::FOR => self.parse_for_statement(),
+            TokenType::THROW => self.parse_throw_statement(),
+            TokenType::INFIXL | TokenType::INFIXR => self.parse_infix_statement(),
+            TokenType::BREAK => {
+                let stmt = Statement::BreakStatement(BreakStatement {
+                    token: self.cur_token.clone(),
+                });
+                if self.peek_token_is(TokenType::SEMICOLON) {
+                    self.next_token();
+                }
+                Some(stmt)
+            }
+            TokenType::CONTINUE => {
+                let stmt = Statement::ContinueStatement(ContinueStatement {
+                    token: self.cur_token.clone(),
+                });
+                if self.peek_token_is(TokenType::SEMICOLON) {
+                    self.next_token();
+                }
+                Some(stmt)
+            }
             _default => {
                 let stmt = self.parse_expression_statement();
                 match stmt {
@@ -190,6 +316,7 @@ impl<'a> Parser<'a> {
         let mut stmt = Statement::LetStatement(LetStatement {
             token: self.cur_token.clone(),
             name: None,
+            annotation: None,
             value: None,
         });
 
@@ -205,6 +332,14 @@ impl<'a> Parser<'a> {
             }
             _ => (),
         }
+        if self.peek_token_is(TokenType::COLON) {
+            self.next_token();
+            self.next_token();
+            let annotation = self.parse_type()?;
+            if let Statement::LetStatement(ref mut x) = stmt {
+                x.annotation = Some(annotation);
+            }
+        }
 
         if !self.expect_peek(TokenType::ASSIGN) {
             return None;
@@ -250,20 +385,166 @@ impl<'a> Parser<'a> {
         Some(stmt)
     }
 
+    /// Parses `infixl 6 <+> = fn(a, b) { ... }`. The operator is registered
+    /// before its value is parsed, so the function can use it recursively.
+    fn parse_infix_statement(&mut self) -> Option<Statement> {
+        let token = self.cur_token.clone();
+        if !self.expect_peek(TokenType::INT) {
+            return None;
+        }
+        let precedence = match self.cur_token.literal.parse::<i32>() {
+            Ok(p) if (ASSIGN..=INDEX).contains(&p) => p,
+            _ => {
+                self.error(format!(
+                    "operator precedence must be between {} and {}, got {}",
+                    ASSIGN, INDEX, self.cur_token.literal
+                ));
+                return None;
+            }
+        };
+        self.next_token();
+        let operator = self.parse_operator_symbol()?;
+        let fun: InfixParseFn = match token.type_ {
+            TokenType::INFIXR => |p, left| p.parse_right_infix_expression(left),
+            _ => |p, left| p.parse_infix_expression(left),
+        };
+        self.register_operator(&operator, precedence, fun);
+        if !self.expect_peek(TokenType::ASSIGN) {
+            return None;
+        }
+        self.next_token();
+        let value = self.parse_expression(LOWEST);
+        if value.is_none() {
+            self.error(format!("could not parse value of operator {}", operator));
+            return None;
+        }
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Some(Statement::InfixStatement(InfixStatement {
+            token,
+            precedence,
+            operator,
+            value: value.map(Box::new),
+        }))
+    }
+
+    /// Reads an operator symbol starting at the current token. The lexer
+    /// splits an undeclared symbol like `<+>` into several tokens, so
+    /// adjacent symbol tokens are joined.
+    fn parse_operator_symbol(&mut self) -> Option<String> {
+        if !is_symbol(&self.cur_token) {
+            self.error(format!(
+                "expected operator symbol, got {}",
+                self.cur_token.literal
+            ));
+            return None;
+        }
+        let first = self.cur_token.type_;
+        let mut symbol = self.cur_token.literal.clone();
+        let mut parts = 1;
+        while is_symbol(&self.peek_token) && self.peek_token.span.start == self.cur_token.span.end {
+            self.next_token();
+            symbol.push_str(&self.cur_token.literal);
+            parts += 1;
+        }
+        if parts == 1 && first != TokenType::ILLEGAL && first != TokenType::OPERATOR {
+            self.error(format!("cannot redeclare built-in operator {}", symbol));
+            return None;
+        }
+        Some(symbol)
+    }
+
+    fn parse_throw_statement(&mut self) -> Option<Statement> {
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(LOWEST);
+        if value.is_none() {
+            self.error("could not parse value of throw statement".to_string());
+            return None;
+        }
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Some(Statement::ThrowStatement(ThrowStatement {
+            token,
+            value: value.map(Box::new),
+        }))
+    }
+
+    fn parse_while_statement(&mut self) -> Option<Statement> {
+        let token = self.cur_token.clone();
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+        self.next_token();
+        let cond = self.parse_expression(LOWEST);
+        if cond.is_none() {
+            self.error("could not parse while loop condition".to_string());
+            return None;
+        }
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_statement();
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Some(Statement::WhileStatement(WhileStatement {
+            token,
+            cond: cond.map(Box::new),
+            body: body.map(Box::new),
+        }))
+    }
+
+    fn parse_for_statement(&mut self) -> Option<Statement> {
+        let token = self.cur_token.clone();
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::IDENT) {
+            return None;
+        }
+        let ident = Identifier {
+            token: self.cur_token.clone(),
+            value: self.cur_token.literal.clone(),
+        };
+        if !self.expect_peek(TokenType::IN) {
+            return None;
+        }
+        self.next_token();
+        let iterable = self.parse_expression(LOWEST);
+        if iterable.is_none() {
+            self.error("could not parse for loop collection".to_string());
+            return None;
+        }
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_statement();
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Some(Statement::ForStatement(ForStatement {
+            token,
+            ident: Some(ident),
+            iterable: iterable.map(Box::new),
+            body: body.map(Box::new),
+        }))
+    }
+
     fn parse_expression_statement(&mut self) -> Option<Statement> {
         let mut stmt: Statement = Statement::ExpressionStatement(ExpressionStatement {
             token: self.cur_token.clone(),
             expr: None,
         });
-        let prec = self.precedence.get(&self.cur_token.type_).clone();
-        let precedence: i32;
-        match prec {
-            Some(p) => {
-                precedence = *p;
-            }
-            _ => precedence = LOWEST,
-        }
-        let st = self.parse_expression(precedence);
+        let st = self.parse_expression(LOWEST);
         match st {
             Some(s) => {
                 match stmt {
@@ -273,122 +554,28 @@ impl<'a> Parser<'a> {
             }
             None => (),
         }
-        //stmt.expr = self.parse_expression( LOWEST);
         if self.peek_token_is(TokenType::SEMICOLON) {
             self.next_token();
         }
         return Some(stmt);
     }
 
-    fn parse_expression(&mut self, prec: i32) -> Option<Expression> {
-        let prefix = self.prefix_fns.get(&self.cur_token.type_);
-        let mut left_expr: Option<Box<Expression>> = None;
-        match prefix {
-            Some(fun) => {
-                let x = &*fun.clone();
-                match x {
-                    PARSE_IDENTIFIER => {
-                        let s = self.parse_identifier();
-                        match s {
-                            Some(stmt) => {
-                                match stmt {
-                                    Expression::Identifier(_) => left_expr = Some(Box::new(stmt)),
-                                    _ => left_expr = None, // Maybe add error handling here
-                                }
-                            }
-                            _ => left_expr = None,
-                        }
-                    }
-                    PARSE_INTEGER_LITERAL => {
-                        let s = self.parse_integer_literal();
-                        match s {
-                            Some(stmt) => {
-                                match stmt {
-                                    Expression::IntegerLiteral(_) => {
-                                        left_expr = Some(Box::new(stmt))
-                                    }
-                                    _ => left_expr = None, //NOTE: Error handling here
-                                }
-                            }
-                            _ => left_expr = None,
-                        }
-                    }
-
-                    PARSE_PREFIX_EXPR => {
-                        let s = self.parse_prefix_expression();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-
-                    PARSE_BOOLEAN => {
-                        let s = self.parse_boolean();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-                    PARSE_GROUP => {
-                        let s = self.parse_group_expression();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-                    PARSE_IF => {
-                        let s = self.parse_if_expression();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-                    PARSE_FUNCTION => {
-                        let s = self.parse_function_expression();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-                    PARSE_STRING => {
-                        let s = self.parse_string();
-                        match s {
-                            Some(stmt) => left_expr = Some(Box::new(stmt)),
-                            _ => left_expr = None,
-                        }
-                    }
-                    _ => return None,
-                }
-            }
+    pub fn parse_expression(&mut self, prec: i32) -> Option<Expression> {
+        let prefix = match self.prefix_fns.get(&self.cur_token.type_) {
+            Some(fun) => *fun,
             None => return None,
-        }
+        };
+        let mut left_expr = prefix(self).map(Box::new);
 
         while !self.peek_token_is(TokenType::SEMICOLON) && prec < self.peek_precedence() {
-            let infix = self.infix_fns.get(&self.peek_token.type_);
-            match infix {
-                Some(inx) => {
-                    let x = &*inx.clone();
-                    match x {
-                        PARSE_INFIX_EXPR => {
-                            self.next_token();
-                            let ex = self.parse_infix_expression(left_expr);
-                            match ex {
-                                Some(s) => left_expr = Some(Box::new(s)),
-                                _ => return None,
-                            }
-                        }
-                        PARSE_CALL => {
-                            self.next_token();
-                            let ex = self.parse_call_expression(left_expr);
-                            match ex {
-                                Some(s) => left_expr = Some(Box::new(s)),
-                                _ => return None,
-                            }
-                        }
-                        _ => return None,
-                    }
-                }
-                None => (), //return left_expr,
+            let infix = match self.infix_parselet(&self.peek_token) {
+                Some(infix) => infix,
+                None => break,
+            };
+            self.next_token();
+            match (infix.parse)(self, left_expr) {
+                Some(s) => left_expr = Some(Box::new(s)),
+                _ => return None,
             }
         }
         match left_expr {
@@ -406,10 +593,7 @@ impl<'a> Parser<'a> {
 
     fn parse_integer_literal(&mut self) -> Option<Expression> {
         let mut lit: Expression = Expression::IntegerLiteral(IntegerLiteral {
-            token: Token {
-                type_: self.cur_token.type_,
-                literal: self.cur_token.literal.clone(),
-            },
+            token: self.cur_token.clone(),
             value: 0,
         });
         let val = self.cur_token.literal.parse::<i64>();
@@ -426,13 +610,13 @@ impl<'a> Parser<'a> {
                     "could not parse {} as integer. {}",
                     self.cur_token.literal, err
                 );
-                self.errors.push(msg);
+                self.error(msg);
                 return None;
             }
         }
     }
 
-    fn parse_prefix_expression(&mut self) -> Option<Expression> {
+    pub fn parse_prefix_expression(&mut self) -> Option<Expression> {
         let mut expr: Expression = Expression::PrefixExpression(PrefixExpression {
             token: self.cur_token.clone(),
             operator: self.cur_token.literal.clone(),
@@ -457,6 +641,111 @@ impl<'a> Parser<'a> {
         }))
     }
 
+    /// Splits a template token into literal text and `${...}` parts. Each
+    /// embedded expression is parsed with its own lexer and parser, and any
+    /// errors it reports are added to this parser's errors.
+    fn parse_template(&mut self) -> Option<Expression> {
+        let literal = self.cur_token.literal.clone();
+        let bytes = literal.as_bytes();
+        let mut parts = Vec::<InterpolationPart>::new();
+        // Start of the literal text not yet added to `parts`. Only ASCII
+        // bytes are matched, so every index stops on a char boundary.
+        let mut text = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] != b'$' || i + 1 >= bytes.len() || bytes[i + 1] != b'{' {
+                i += 1;
+                continue;
+            }
+            let start = i + 2;
+            let mut end = start;
+            let mut depth = 1;
+            while end < bytes.len() {
+                match bytes[end] {
+                    b'{' => depth += 1,
+                    b'}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    b'"' => {
+                        end += 1;
+                        while end < bytes.len() && bytes[end] != b'"' {
+                            end += 1;
+                        }
+                    }
+                    _ => (),
+                }
+                end += 1;
+            }
+            if depth != 0 {
+                self.error(format!("unterminated interpolation in {:?}", literal));
+                return None;
+            }
+            if text < i {
+                parts.push(InterpolationPart::Literal(literal[text..i].to_string()));
+            }
+            let origin = self.interpolation_origin(&literal, start);
+            let expr = self.parse_interpolation(&literal[start..end], origin)?;
+            parts.push(InterpolationPart::Expression(expr));
+            i = end + 1;
+            text = i;
+        }
+        if text < literal.len() {
+            parts.push(InterpolationPart::Literal(literal[text..].to_string()));
+        }
+        Some(Expression::InterpolatedString(InterpolatedString {
+            token: self.cur_token.clone(),
+            parts,
+        }))
+    }
+
+    /// Location of byte `offset` of a template literal in the enclosing source.
+    fn interpolation_origin(&self, literal: &str, offset: usize) -> Span {
+        let tok = self.cur_token.span;
+        let before = &literal[..offset];
+        let (line, column) = match before.rfind('\n') {
+            Some(nl) => (tok.line + before.matches('\n').count(), offset - nl),
+            None => (tok.line, tok.column + 1 + offset),
+        };
+        Span {
+            start: tok.start + 1 + offset,
+            end: tok.start + 1 + offset,
+            line,
+            column,
+        }
+    }
+
+    fn parse_interpolation(&mut self, source: &str, origin: Span) -> Option<Expression> {
+        if source.trim().is_empty() {
+            self.error("empty expression in string interpolation".to_string());
+            return None;
+        }
+        let mut lex = Lexer::init_lexer(source);
+        lex.set_origin(origin);
+        let mut parser = Parser::new(&mut lex);
+        parser.prefix_fns = self.prefix_fns.clone();
+        parser.infix_fns = self.infix_fns.clone();
+        parser.register_operators(&self.operators);
+        let expr = parser.parse_expression(LOWEST);
+        if !parser.peek_token_is(TokenType::EOF) {
+            let message = format!(
+                "unexpected {} in string interpolation",
+                parser.peek_token.literal
+            );
+            parser.errors.push((message, parser.peek_token.span));
+        }
+        self.errors.append(&mut parser.errors);
+        if expr.is_none() {
+            self.error(format!(
+                "could not parse interpolated expression {:?}",
+                source
+            ));
+        }
+        expr
+    }
+
     fn parse_boolean(&mut self) -> Option<Expression> {
         Some(Expression::Boolean(Boolean {
             token: self.cur_token.clone(),
@@ -476,10 +765,7 @@ impl<'a> Parser<'a> {
 
     fn parse_if_expression(&mut self) -> Option<Expression> {
         let mut exp: Expression = Expression::IfExpression(IfExpression {
-            token: Token {
-                type_: TokenType::IF,
-                literal: "if".to_string(),
-            },
+            token: self.cur_token.clone(),
             cond: None,
             consequence: None,
             alternative: None,
@@ -494,7 +780,10 @@ impl<'a> Parser<'a> {
                 Expression::IfExpression(ref mut x) => x.cond = Some(Box::new(e)),
                 _ => println!("Should have been an if expression (err in cond section)"),
             },
-            None => todo!(),
+            None => {
+                self.error("could not parse if condition".to_string());
+                return None;
+            }
         }
 
         if !self.expect_peek(TokenType::RPAREN) {
@@ -530,13 +819,45 @@ impl<'a> Parser<'a> {
         return Some(exp);
     }
 
+    fn parse_try_expression(&mut self) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_statement();
+        if !self.expect_peek(TokenType::CATCH) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::IDENT) {
+            return None;
+        }
+        let ident = Identifier {
+            token: self.cur_token.clone(),
+            value: self.cur_token.literal.clone(),
+        };
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let handler = self.parse_block_statement();
+        Some(Expression::TryExpression(TryExpression {
+            token,
+            body: body.map(Box::new),
+            ident: Some(ident),
+            handler: handler.map(Box::new),
+        }))
+    }
+
     fn parse_block_statement(&mut self) -> Option<Statement> {
         let mut block = Statement::BlockStatement(BlockStatement {
-            token: Token {
-                type_: self.cur_token.type_,
-                literal: self.cur_token.literal.clone(),
-            },
+            token: self.cur_token.clone(),
             statements: Vec::<Statement>::new(),
+            end: self.cur_token.clone(),
         });
         self.next_token();
         while !self.cur_token_is(TokenType::RBRACE) && !self.cur_token_is(TokenType::EOF) {
@@ -550,25 +871,37 @@ impl<'a> Parser<'a> {
             }
             self.next_token();
         }
+        if let Statement::BlockStatement(ref mut x) = block {
+            x.end = self.cur_token.clone();
+        }
         return Some(block);
     }
 
-    fn parse_infix_expression(&mut self, left: Option<Box<Expression>>) -> Option<Expression> {
+    pub fn parse_infix_expression(&mut self, left: Option<Box<Expression>>) -> Option<Expression> {
+        let mut prc = self.cur_precedence();
+        if self.cur_token.literal == "+" {
+            prc = prc - 1;
+        }
+        self.parse_infix_right(left, prc)
+    }
+
+    /// Like `parse_infix_expression`, but groups to the right, so
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`.
+    pub fn parse_right_infix_expression(
+        &mut self,
+        left: Option<Box<Expression>>,
+    ) -> Option<Expression> {
+        let prc = self.cur_precedence() - 1;
+        self.parse_infix_right(left, prc)
+    }
+
+    fn parse_infix_right(&mut self, left: Option<Box<Expression>>, prc: i32) -> Option<Expression> {
         let mut expr: Expression = Expression::InfixExpression(InfixExpression {
             token: self.cur_token.clone(),
             operator: self.cur_token.literal.clone(),
             left,
             right: None,
         });
-        let mut prc = self.cur_precedence();
-        match expr {
-            Expression::InfixExpression(ref mut x) => {
-                if x.operator == "+".to_string() {
-                    prc = prc - 1;
-                }
-            }
-            _ => (),
-        }
         //println!("{}", expr.print());
         self.next_token();
         let stmt = self.parse_expression(prc);
@@ -601,6 +934,8 @@ impl<'a> Parser<'a> {
         let mut lit: Expression = Expression::FunctionLiteral(FunctionLiteral {
             token: self.cur_token.clone(),
             parameters: None,
+            param_types: Rc::default(),
+            return_type: None,
             body: None,
         });
         if !self.expect_peek(TokenType::LPAREN) {
@@ -608,13 +943,23 @@ impl<'a> Parser<'a> {
         }
         let par = self.parse_function_parameters();
         match par {
-            Some(v) => match lit {
-                Expression::FunctionLiteral(ref mut x) => x.parameters = Some(v),
-                _ => (),
-            }, //lit.parameters = Some(v),
+            Some((v, types)) => {
+                if let Expression::FunctionLiteral(ref mut x) = lit {
+                    x.parameters = Some(Rc::new(v));
+                    x.param_types = Rc::new(types);
+                }
+            }
             None => (), //TODO: add error handling here when parsing func parameters goes wrong
                         //I dont think its necessary a function can have no parameters;
         }
+        if self.peek_token_is(TokenType::ARROW) {
+            self.next_token();
+            self.next_token();
+            let ret = self.parse_type()?;
+            if let Expression::FunctionLiteral(ref mut x) = lit {
+                x.return_type = Some(Rc::new(ret));
+            }
+        }
 
         if !self.expect_peek(TokenType::LBRACE) {
             return None;
@@ -622,21 +967,37 @@ impl<'a> Parser<'a> {
 
         let bod = self.parse_block_statement();
         match bod {
-            Some(b) => match lit {
-                Expression::FunctionLiteral(ref mut x) => x.body = Some(Box::new(b)),
+            Some(Statement::BlockStatement(b)) => match lit {
+                Expression::FunctionLiteral(ref mut x) => x.body = Some(Rc::new(b)),
                 _ => (),
             }, //lit.body = Some(b),
-            None => return Some(lit), //TODO: add error handling here as well
+            _ => return Some(lit), //TODO: add error handling here as well
         }
 
         return Some(lit);
     }
 
-    fn parse_function_parameters(&mut self) -> Option<Vec<Expression>> {
+    /// A macro literal has the same shape as a function literal.
+    fn parse_macro_literal(&mut self) -> Option<Expression> {
+        match self.parse_function_expression()? {
+            Expression::FunctionLiteral(f) => Some(Expression::MacroLiteral(MacroLiteral {
+                token: f.token,
+                parameters: f.parameters.map(Rc::unwrap_or_clone),
+                body: f.body,
+            })),
+            other => Some(other),
+        }
+    }
+
+    /// The parameters and the annotation of each.
+    fn parse_function_parameters(
+        &mut self,
+    ) -> Option<(Vec<Expression>, Vec<Option<TypeAnnotation>>)> {
         let mut ident = Vec::<Expression>::new();
+        let mut types = Vec::new();
         if self.peek_token_is(TokenType::RPAREN) {
             self.next_token();
-            return Some(ident);
+            return Some((ident, types));
         }
         self.next_token();
         let identi = Expression::Identifier(Identifier {
@@ -644,6 +1005,7 @@ impl<'a> Parser<'a> {
             value: self.cur_token.literal.clone(),
         });
         ident.push(identi);
+        types.push(self.parse_parameter_type()?);
         while self.peek_token_is(TokenType::COMMA) {
             self.next_token();
             self.next_token();
@@ -651,11 +1013,88 @@ impl<'a> Parser<'a> {
                 token: self.cur_token.clone(),
                 value: self.cur_token.literal.clone(),
             }));
+            types.push(self.parse_parameter_type()?);
         }
         if !self.expect_peek(TokenType::RPAREN) {
             return None;
         }
-        return Some(ident);
+        return Some((ident, types));
+    }
+
+    /// The `: type` after a parameter name, if there is one.
+    fn parse_parameter_type(&mut self) -> Option<Option<TypeAnnotation>> {
+        if !self.peek_token_is(TokenType::COLON) {
+            return Some(None);
+        }
+        self.next_token();
+        self.next_token();
+        self.parse_type().map(Some)
+    }
+
+    /// Parses the type annotation starting at the current token.
+    fn parse_type(&mut self) -> Option<TypeAnnotation> {
+        let token = self.cur_token.clone();
+        match token.type_ {
+            TokenType::IDENT if TypeAnnotation::NAMES.contains(&token.literal.as_str()) => {
+                Some(TypeAnnotation::Named(token))
+            }
+            TokenType::IDENT => {
+                self.error(format!("unknown type {}", token.literal));
+                None
+            }
+            TokenType::LBRACKET => {
+                self.next_token();
+                let item = self.parse_type()?;
+                if !self.expect_peek(TokenType::RBRACKET) {
+                    return None;
+                }
+                Some(TypeAnnotation::Array(token, Box::new(item)))
+            }
+            TokenType::LBRACE => {
+                self.next_token();
+                let key = self.parse_type()?;
+                if !self.expect_peek(TokenType::COLON) {
+                    return None;
+                }
+                self.next_token();
+                let value = self.parse_type()?;
+                if !self.expect_peek(TokenType::RBRACE) {
+                    return None;
+                }
+                Some(TypeAnnotation::Hash(token, Box::new(key), Box::new(value)))
+            }
+            TokenType::FUNCTION => {
+                if !self.expect_peek(TokenType::LPAREN) {
+                    return None;
+                }
+                let mut params = vec![];
+                if self.peek_token_is(TokenType::RPAREN) {
+                    self.next_token();
+                } else {
+                    loop {
+                        self.next_token();
+                        params.push(self.parse_type()?);
+                        if !self.peek_token_is(TokenType::COMMA) {
+                            break;
+                        }
+                        self.next_token();
+                    }
+                    if !self.expect_peek(TokenType::RPAREN) {
+                        return None;
+                    }
+                }
+                if !self.expect_peek(TokenType::ARROW) {
+                    return None;
+                }
+                self.next_token();
+                let ret = self.parse_type()?;
+                Some(TypeAnnotation::Function(token, params, Box::new(ret)))
+            }
+            other => {
+                self.error(format!("expected a type, got {}", other));
+                None
+            }
+        }
     }
 
     fn parse_call_expression(&mut self, func: Option<Box<Expression>>) -> Option<Expression> {
@@ -678,8 +1117,12 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_call_arguments(&mut self) -> Option<Vec<Expression>> {
+        self.parse_expression_list(TokenType::RPAREN)
+    }
+
+    fn parse_expression_list(&mut self, end: TokenType) -> Option<Vec<Expression>> {
         let mut arg = Vec::<Expression>::new();
-        if self.peek_token_is(TokenType::RPAREN) {
+        if self.peek_token_is(end) {
             self.next_token();
             return Some(arg);
         }
@@ -704,16 +1147,106 @@ impl<'a> Parser<'a> {
                 None => return None,
             }
         }
-        if !self.expect_peek(TokenType::RPAREN) {
+        if !self.expect_peek(end) {
             return None; //TODO: Error handling
         }
 
         return Some(arg);
     }
 
+    fn parse_array_literal(&mut self) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let elements = self.parse_expression_list(TokenType::RBRACKET)?;
+        Some(Expression::ArrayLiteral(ArrayLiteral { token, elements }))
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let mut pairs = Vec::<(Expression, Expression)>::new();
+        while !self.peek_token_is(TokenType::RBRACE) {
+            self.next_token();
+            let key = self.parse_expression(LOWEST)?;
+            if !self.expect_peek(TokenType::COLON) {
+                return None;
+            }
+            self.next_token();
+            let value = self.parse_expression(LOWEST)?;
+            pairs.push((key, value));
+            if !self.peek_token_is(TokenType::RBRACE) && !self.expect_peek(TokenType::COMMA) {
+                return None;
+            }
+        }
+        if !self.expect_peek(TokenType::RBRACE) {
+            return None;
+        }
+        Some(Expression::HashLiteral(HashLiteral { token, pairs }))
+    }
+
+    fn parse_index_expression(&mut self, left: Option<Box<Expression>>) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        self.next_token();
+        let index = self.parse_expression(LOWEST)?;
+        if !self.expect_peek(TokenType::RBRACKET) {
+            return None;
+        }
+        Some(Expression::IndexExpression(IndexExpression {
+            token,
+            left,
+            index: Some(Box::new(index)),
+        }))
+    }
+
+    /// Assignment is right associative, so `a = b = 1` assigns 1 to both.
+    fn parse_assign_expression(&mut self, target: Option<Box<Expression>>) -> Option<Expression> {
+        match target.as_deref() {
+            Some(Expression::Identifier(_)) | Some(Expression::IndexExpression(_)) => (),
+            Some(t) => {
+                self.error(format!(
+                    "invalid assignment target: {}",
+                    printer::print_expression(t)
+                ));
+                return None;
+            }
+            None => return None,
+        }
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(ASSIGN - 1)?;
+        Some(Expression::AssignExpression(AssignExpression {
+            operator: token.literal.clone(),
+            token,
+            target,
+            value: Some(Box::new(value)),
+        }))
+    }
+
     fn no_prefix_parse_fn_error(&mut self, token: TokenType) {
         let msg = format!("no prefix parse function for {} found", token);
-        self.errors.push(msg);
+        self.error(msg);
+    }
+}
+
+/// Whether a token can be part of an operator symbol.
+fn is_symbol(tok: &Token) -> bool {
+    match tok.type_ {
+        TokenType::PLUS
+        | TokenType::MINUS
+        | TokenType::BANG
+        | TokenType::ASTERICK
+        | TokenType::SLASH
+        | TokenType::EQ
+        | TokenType::NEQ
+        | TokenType::LT
+        | TokenType::GT
+        | TokenType::ASSIGN
+        | TokenType::DOTDOT
+        | TokenType::PLUSASSIGN
+        | TokenType::MINUSASSIGN
+        | TokenType::ASTERICKASSIGN
+        | TokenType::SLASHASSIGN
+        | TokenType::OPERATOR => true,
+        TokenType::ILLEGAL => tok.literal.chars().all(|c| c.is_ascii_punctuation()),
+        _ => false,
     }
 }
 
@@ -840,4 +1373,340 @@ mod tests {
         }
         println!("{:?}", p.errors);
     }
+
+    #[test]
+    fn parse_template() {
+        let input = "\"hello ${name}, you have ${count + 1} items\";";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors().len(), 0);
+        let parts = match &prog[0] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => {
+                match e.as_ref() {
+                    Expression::InterpolatedString(x) => x.parts.clone(),
+                    _ => panic!("expected interpolated string, got {}", e.print()),
+                }
+            }
+            _ => panic!("expected expression statement"),
+        };
+        assert_eq!(parts.len(), 5);
+        assert!(parts[0] == InterpolationPart::Literal("hello ".to_string()));
+        assert!(matches!(
+            &parts[1],
+            InterpolationPart::Expression(Expression::Identifier(_))
+        ));
+        assert!(matches!(
+            &parts[3],
+            InterpolationPart::Expression(Expression::InfixExpression(_))
+        ));
+        assert!(parts[4] == InterpolationPart::Literal(" items".to_string()));
+
+        let mut lex: Lexer = Lexer::init_lexer("\"é ${\"ü\"} ñ\";");
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors().len(), 0);
+        match &prog[0] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => {
+                match e.as_ref() {
+                    Expression::InterpolatedString(x) => {
+                        assert!(x.parts[0] == InterpolationPart::Literal("é ".to_string()));
+                        assert!(x.parts[2] == InterpolationPart::Literal(" ñ".to_string()));
+                    }
+                    _ => panic!("expected interpolated string, got {}", e.print()),
+                }
+            }
+            _ => panic!("expected expression statement"),
+        }
+    }
+
+    #[test]
+    fn parse_template_errors() {
+        let input = "\"empty ${}\";";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        p.parse_program();
+        assert_ne!(p.errors().len(), 0);
+    }
+
+    #[test]
+    fn parse_while() {
+        let input = "while (x < 10) { if (x == 5) { break; } continue; };";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors().len(), 0);
+        assert_eq!(prog.len(), 1);
+        match &prog[0] {
+            Statement::WhileStatement(x) => {
+                assert!(x.cond.is_some());
+                match x.body.as_deref() {
+                    Some(Statement::BlockStatement(b)) => {
+                        assert_eq!(b.statements.len(), 2);
+                        assert!(matches!(b.statements[1], Statement::ContinueStatement(_)));
+                    }
+                    _ => panic!("expected block body"),
+                }
+            }
+            _ => panic!("expected while statement, got {}", prog[0].print()),
+        }
+    }
+
+    #[test]
+    fn parse_try() {
+        let input = "let r = try { risky(); } catch (err) { err[\"message\"] }; throw r;";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors().len(), 0);
+        assert_eq!(prog.len(), 2);
+        match &prog[0] {
+            Statement::LetStatement(LetStatement { value: Some(v), .. }) => match v.as_ref() {
+                Expression::TryExpression(x) => {
+                    assert!(x.body.is_some());
+                    assert!(x.handler.is_some());
+                    assert_eq!(x.ident.as_ref().map(|i| i.value.as_str()), Some("err"));
+                }
+                _ => panic!("expected try expression, got {}", v.print()),
+            },
+            _ => panic!("expected let statement, got {}", prog[0].print()),
+        }
+        assert!(matches!(prog[1], Statement::ThrowStatement(_)));
+
+        let mut lex: Lexer = Lexer::init_lexer("try { 1 } (e) { 2 }");
+        let mut p: Parser = Parser::new(&mut lex);
+        p.parse_program();
+        assert!(!p.errors().is_empty());
+    }
+
+    #[test]
+    fn parse_operator_declarations() {
+        let input = "infixl 5 <+> = fn(a, b) { a }; infixr 6 ^ = fn(a, b) { a <+> b };
+            a <+> b <+> c; a ^ b ^ c;";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors(), Vec::<String>::new());
+        assert_eq!(prog.len(), 4);
+        match &prog[0] {
+            Statement::InfixStatement(x) => {
+                assert_eq!(x.token.type_, TokenType::INFIXL);
+                assert_eq!(x.precedence, 5);
+                assert_eq!(x.operator, "<+>");
+                assert!(matches!(
+                    x.value.as_deref(),
+                    Some(Expression::FunctionLiteral(_))
+                ));
+            }
+            _ => panic!("expected operator declaration, got {}", prog[0].print()),
+        }
+        let grouping: Vec<(bool, bool)> = prog[2..]
+            .iter()
+            .map(|s| match s {
+                Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => {
+                    match e.as_ref() {
+                        Expression::InfixExpression(x) => (
+                            matches!(x.left.as_deref(), Some(Expression::InfixExpression(_))),
+                            matches!(x.right.as_deref(), Some(Expression::InfixExpression(_))),
+                        ),
+                        _ => panic!("expected infix expression, got {}", e.print()),
+                    }
+                }
+                _ => panic!("expected expression statement, got {}", s.print()),
+            })
+            .collect();
+        assert_eq!(grouping, vec![(true, false), (false, true)]);
+
+        let errors = [
+            (
+                "infixl 5 + = fn(a, b) { a };",
+                "cannot redeclare built-in operator +",
+            ),
+            (
+                "infixl 0 <+> = fn(a, b) { a };",
+                "operator precedence must be between 1 and 9, got 0",
+            ),
+            (
+                "infixl 5 plus = fn(a, b) { a };",
+                "expected operator symbol, got plus",
+            ),
+        ];
+        for (input, expected) in errors {
+            let mut lex: Lexer = Lexer::init_lexer(input);
+            let mut p: Parser = Parser::new(&mut lex);
+            p.parse_program();
+            assert_eq!(p.errors().first().map(|e| e.as_str()), Some(expected));
+        }
+    }
+
+    #[test]
+    fn custom_parselets() {
+        fn infix(e: &Expression) -> (&str, &Expression, &Expression) {
+            match e {
+                Expression::InfixExpression(InfixExpression {
+                    operator,
+                    left: Some(l),
+                    right: Some(r),
+                    ..
+                }) => (operator.as_str(), l, r),
+                _ => panic!("expected infix expression, got {}", e.print()),
+            }
+        }
+        let input = "a <+> b * 2; 1 + +x;";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        p.register_operator("<+>", PRODUCT, |p, l| p.parse_infix_expression(l));
+        p.register_prefix(TokenType::PLUS, |p| p.parse_prefix_expression());
+        let prog = p.parse_program();
+        assert_eq!(p.errors(), Vec::<String>::new());
+        assert_eq!(prog.len(), 2);
+        let exprs: Vec<&Expression> = prog
+            .iter()
+            .map(|s| match s {
+                Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => {
+                    e.as_ref()
+                }
+                _ => panic!("expected expression statement, got {}", s.print()),
+            })
+            .collect();
+
+        let (op, left, _) = infix(exprs[0]);
+        assert_eq!(op, "*");
+        let (op, left, right) = infix(left);
+        assert_eq!(op, "<+>");
+        assert_eq!(left.token().literal, "a");
+        assert_eq!(right.token().literal, "b");
+
+        let (op, _, right) = infix(exprs[1]);
+        assert_eq!(op, "+");
+        assert!(matches!(
+            right,
+            Expression::PrefixExpression(PrefixExpression { operator, .. }) if operator == "+"
+        ));
+    }
+
+    #[test]
+    fn parse_collections() {
+        let input = "[1, 2 * 2][0]; {\"one\": 1, true: 0..n + 1}; for (x in xs) { x };";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors().len(), 0);
+        assert_eq!(prog.len(), 3);
+        let first = match &prog[0] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => panic!("expected expression statement"),
+        };
+        match first.as_ref() {
+            Expression::IndexExpression(x) => assert!(matches!(
+                x.left.as_deref(),
+                Some(Expression::ArrayLiteral(ArrayLiteral { elements, .. })) if elements.len() == 2
+            )),
+            _ => panic!("expected index expression, got {}", first.print()),
+        }
+        let second = match &prog[1] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => panic!("expected expression statement"),
+        };
+        match second.as_ref() {
+            Expression::HashLiteral(x) => {
+                assert_eq!(x.pairs.len(), 2);
+                match &x.pairs[1].1 {
+                    Expression::InfixExpression(r) => assert_eq!(r.operator, ".."),
+                    _ => panic!("expected range"),
+                }
+            }
+            _ => panic!("expected hash literal, got {}", second.print()),
+        }
+        assert!(matches!(prog[2], Statement::ForStatement(_)));
+    }
+
+    #[test]
+    fn parse_assign() {
+        let input = "a = b = 1 + 2; xs[0] += 3; 5 = x;";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors(), vec!["invalid assignment target: 5".to_string()]);
+        let first = match &prog[0] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => panic!("expected expression statement"),
+        };
+        match first.as_ref() {
+            Expression::AssignExpression(x) => {
+                assert_eq!(x.operator, "=");
+                assert!(matches!(
+                    x.value.as_deref(),
+                    Some(Expression::AssignExpression(_))
+                ));
+            }
+            _ => panic!("expected assignment, got {}", first.print()),
+        }
+        let second = match &prog[1] {
+            Statement::ExpressionStatement(ExpressionStatement { expr: Some(e), .. }) => e,
+            _ => panic!("expected expression statement"),
+        };
+        match second.as_ref() {
+            Expression::AssignExpression(x) => {
+                assert_eq!(x.operator, "+=");
+                assert!(matches!(
+                    x.target.as_deref(),
+                    Some(Expression::IndexExpression(_))
+                ));
+            }
+            _ => panic!("expected assignment, got {}", second.print()),
+        }
+    }
+
+    #[test]
+    fn parse_type_annotations() {
+        let input = "let n: int = 1; let f = fn(a: string, b) -> {string: [int]} { {} };";
+        let mut lex: Lexer = Lexer::init_lexer(input);
+        let mut p: Parser = Parser::new(&mut lex);
+        let prog = p.parse_program();
+        assert_eq!(p.errors(), Vec::<String>::new());
+        match &prog[0] {
+            Statement::LetStatement(x) => {
+                assert_eq!(x.annotation.as_ref().unwrap().to_string(), "int")
+            }
+            _ => panic!("expected let statement"),
+        }
+        let lit = match &prog[1] {
+            Statement::LetStatement(LetStatement { value: Some(v), .. }) => match v.as_ref() {
+                Expression::FunctionLiteral(f) => f,
+                _ => panic!("expected function literal"),
+            },
+            _ => panic!("expected let statement"),
+        };
+        let types: Vec<Option<String>> = lit
+            .param_types
+            .iter()
+            .map(|t| t.as_ref().map(|t| t.to_string()))
+            .collect();
+        assert_eq!(types, vec![Some("string".to_string()), None]);
+        assert_eq!(
+            lit.return_type.as_ref().unwrap().to_string(),
+            "{string: [int]}"
+        );
+
+        let tests = vec![
+            ("let x: integer = 1;", "unknown type integer"),
+            ("let x: = 1;", "expected a type, got TokenType: ASSIGN"),
+            (
+                "fn(f: fn(int)) { f }",
+                "expected next token to be TokenType: ARROW, got TokenType: RPAREN",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut lex: Lexer = Lexer::init_lexer(input);
+            let mut p: Parser = Parser::new(&mut lex);
+            p.parse_program();
+            assert_eq!(
+                p.errors().first().map(String::as_str),
+                Some(expected),
+                "{}",
+                input
+            );
+        }
+    }
 }
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::ast::printer::{print_expression, print_statement};
+use crate::ast::{Expression, Statement};
+use crate::eval::EvalHook;
+use crate::object::{Environment, Function};
+use crate::token::Span;
+
+/// Nodes listed in the report, most evaluated first.
+const REPORT_NODES: usize = 20;
+
+/// Counts calls and time per function literal, and evaluations per AST
+/// node, while a program runs. Time spent in the profiler itself is
+/// included, so the numbers are best compared with each other.
+pub struct Profiler {
+    started: Instant,
+    /// How long the program ran, once it has finished.
+    total: Option<Duration>,
+    /// Keyed by the position of the literal's body.
+    functions: HashMap<usize, FunctionStats>,
+    /// Keyed by the position of the node's token and whether it is a
+    /// statement, since a statement shares its first token with its
+    /// expression.
+    nodes: HashMap<(usize, bool), NodeStats>,
+    /// Calls being run, innermost last. The first is the top level.
+    stack: Vec<Call>,
+    /// Time spent in each call stack outside the calls it made, by stack
+    /// as written in a folded-stack file.
+    folded: HashMap<String, Duration>,
+}
+
+struct FunctionStats {
+    name: String,
+    line: usize,
+    calls: u64,
+    /// Time from the first call in a recursion to its return, so recursive
+    /// calls are not counted twice.
+    total: Duration,
+    own: Duration,
+    /// Calls of this function being run.
+    active: usize,
+}
+
+struct NodeStats {
+    label: String,
+    span: Span,
+    count: u64,
+}
+
+struct Call {
+    function: Option<usize>,
+    started: Instant,
+    /// Time spent in the calls this call made.
+    children: Duration,
+    path: String,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            started: Instant::now(),
+            total: None,
+            functions: HashMap::new(),
+            nodes: HashMap::new(),
+            stack: vec![Call {
+                function: None,
+                started: Instant::now(),
+                children: Duration::ZERO,
+                path: "main".to_string(),
+            }],
+            folded: HashMap::new(),
+        }
+    }
+
+    fn count(&mut self, span: Span, statement: bool, label: impl FnOnce() -> String) {
+        if !span.is_known() {
+            return;
+        }
+        self.nodes
+            .entry((span.start, statement))
+            .or_insert_with(|| NodeStats {
+                label: label(),
+                span,
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    /// Ends the top level once the program has run.
+    pub fn finish(&mut self) {
+        if let Some(top) = self.stack.pop() {
+            let own = top.started.elapsed().saturating_sub(top.children);
+            *self.folded.entry(top.path).or_default() += own;
+        }
+        self.total = Some(self.started.elapsed());
+    }
+
+    /// The report: calls and time per function, with the top level last,
+    /// then the most evaluated nodes.
+    pub fn report(&self) -> String {
+        let total = self.total.unwrap_or_else(|| self.started.elapsed());
+        let mut functions: Vec<&FunctionStats> = self.functions.values().collect();
+        functions.sort_by(|a, b| b.own.cmp(&a.own).then(a.line.cmp(&b.line)));
+        let mut out = format!(
+            "{:<30} {:>10} {:>12} {:>12}\n",
+            "function", "calls", "total ms", "self ms"
+        );
+        let called: Duration = functions.iter().map(|f| f.own).sum();
+        let rows = functions
+            .iter()
+            .map(|f| {
+                (
+                    format!("{} (line {})", f.name, f.line),
+                    f.calls,
+                    f.total,
+                    f.own,
+                )
+            })
+            .chain([(
+                "<top level>".to_string(),
+                1,
+                total,
+                total.saturating_sub(called),
+            )]);
+        for (name, calls, total, own) in rows {
+            let _ = writeln!(
+                out,
+                "{:<30} {:>10} {:>12.3} {:>12.3}",
+                name,
+                calls,
+                total.as_secs_f64() * 1000.0,
+                own.as_secs_f64() * 1000.0
+            );
+        }
+        let mut nodes: Vec<&NodeStats> = self.nodes.values().collect();
+        nodes.sort_by(|a, b| b.count.cmp(&a.count).then(a.span.start.cmp(&b.span.start)));
+        let _ = write!(out, "\n{:<43} {:>12}\n", "node", "evaluations");
+        for node in nodes.iter().take(REPORT_NODES) {
+            let position = format!("{}:{}", node.span.line, node.span.column);
+            let _ = writeln!(out, "{:<8} {:<34} {:>12}", position, node.label, node.count);
+        }
+        out
+    }
+
+    /// The time spent in each call stack as folded stacks, one
+    /// `main;f:1;g:4 microseconds` line per stack, for flame graph tools.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<(&String, &Duration)> = self.folded.iter().collect();
+        lines.sort();
+        lines
+            .into_iter()
+            .map(|(path, time)| format!("{} {}\n", path, time.as_micros()))
+            .collect()
+    }
+
+    /// Ends the innermost call, leaving the top level in place.
+    fn exit_call(&mut self) {
+        let call = match self.stack.pop() {
+            Some(call) if call.function.is_some() => call,
+            Some(top) => return self.stack.push(top),
+            None => return,
+        };
+        let elapsed = call.started.elapsed();
+        let own = elapsed.saturating_sub(call.children);
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+        *self.folded.entry(call.path).or_default() += own;
+        if let Some(stats) = call.function.and_then(|f| self.functions.get_mut(&f)) {
+            stats.own += own;
+            stats.active -= 1;
+            if stats.active == 0 {
+                stats.total += elapsed;
+            }
+        }
+    }
+}
+
+impl EvalHook for Profiler {
+    /// Expression statements are left to `before_expression`, which sees
+    /// the same node.
+    fn before_statement(&mut self, stmt: &Statement, _env: &Environment) {
+        if let Statement::BlockStatement(_) | Statement::ExpressionStatement(_) = stmt {
+            return;
+        }
+        self.count(stmt.token().span, true, || snippet(&print_statement(stmt)));
+    }
+
+    fn before_expression(&mut self, expr: &Expression, _env: &Environment) {
+        self.count(expr.token().span, false, || {
+            snippet(&print_expression(expr))
+        });
+    }
+
+    fn enter_function(&mut self, function: &Function) {
+        let span = function.body.token.span;
+        let name = function.name.as_deref().unwrap_or("<anonymous>");
+        let stats = self
+            .functions
+            .entry(span.start)
+            .or_insert_with(|| FunctionStats {
+                name: name.to_string(),
+                line: span.line,
+                calls: 0,
+                total: Duration::ZERO,
+                own: Duration::ZERO,
+                active: 0,
+            });
+        stats.calls += 1;
+        stats.active += 1;
+        let parent = self.stack.last().map_or("main", |c| c.path.as_str());
+        let path = format!("{};{}:{}", parent, stats.name, stats.line);
+        self.stack.push(Call {
+            function: Some(span.start),
+            started: Instant::now(),
+            children: Duration::ZERO,
+            path,
+        });
+    }
+
+    fn exit_function(&mut self, _function: &Function) {
+        self.exit_call();
+    }
+}
+
+/// The first line of a node's source, shortened to fit the report.
+fn snippet(source: &str) -> String {
+    let line = source.lines().next().unwrap_or("");
+    match line.char_indices().nth(30) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_prog;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn profile(input: &str) -> Rc<RefCell<Profiler>> {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new());
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut env = Environment::new();
+        env.set_hook(profiler.clone());
+        eval_prog(&program, &mut env);
+        profiler.borrow_mut().finish();
+        profiler
+    }
+
+    #[test]
+    fn profile_test() {
+        let profiler = profile(
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+let twice = fn(f, x) { f(f(x)) };
+fib(5);
+twice(fn(x) { x * 2 }, 3);",
+        );
+        let profiler = profiler.borrow();
+        let mut calls: Vec<(String, u64)> = profiler
+            .functions
+            .values()
+            .map(|f| (format!("{}:{}", f.name, f.line), f.calls))
+            .collect();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec![
+                ("<anonymous>:4".to_string(), 2),
+                ("fib:1".to_string(), 15),
+                ("twice:2".to_string(), 1),
+            ]
+        );
+        assert!(profiler.functions.values().all(|f| f.active == 0));
+
+        let comparison = profiler
+            .nodes
+            .values()
+            .find(|n| n.label == "n < 2")
+            .unwrap_or_else(|| panic!("{}", profiler.report()));
+        assert_eq!(
+            (
+                comparison.span.line,
+                comparison.span.column,
+                comparison.count
+            ),
+            (1, 25, 15)
+        );
+
+        let branch = profiler
+            .nodes
+            .values()
+            .find(|n| n.label.starts_with("if (n < 2)"));
+        assert_eq!(branch.map(|n| n.count), Some(15));
+
+        let report = profiler.report();
+        assert!(report.starts_with("function"), "{}", report);
+        assert!(report.contains("fib (line 1)"), "{}", report);
+        assert!(report.contains("<top level>"), "{}", report);
+
+        let folded = profiler.folded();
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert!(stacks.contains(&"main"));
+        assert!(stacks.contains(&"main;fib:1;fib:1;fib:1;fib:1;fib:1"));
+        assert!(stacks.contains(&"main;twice:2;<anonymous>:4"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::ast::{Program, Statement};
+use crate::eval::{call_function, eval_prog};
+use crate::object::{Environment, Limits, Object};
+
+/// The outcome of one test function. `failure` holds the traceback of the
+/// error it raised.
+pub struct TestResult {
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+/// Calls each function bound at the top level to a name starting with
+/// `test_`, in source order. Every test gets a fresh environment in which
+/// the whole program has run first, so tests cannot see each other's
+/// changes. Each environment has `limits` of its own, so a test that
+/// exceeds them fails alone. An error from the program itself is returned
+/// instead.
+pub fn run_tests(program: &Program, limits: Limits) -> Result<Vec<TestResult>, String> {
+    let tests = program.iter().filter_map(|stmt| match stmt {
+        Statement::LetStatement(x) => x.name.as_ref().filter(|n| n.value.starts_with("test_")),
+        _ => None,
+    });
+    let mut results = vec![];
+    for test in tests {
+        let mut env = Environment::with_limits(limits);
+        if let Object::Error(e) = eval_prog(program, &mut env) {
+            return Err(e.traceback());
+        }
+        let function = env.get(&test.value).unwrap_or(Object::Null);
+        let failure = match call_function(function, vec![], test.token.span, &mut env) {
+            Object::Error(e) => Some(e.traceback()),
+            _ => None,
+        };
+        results.push(TestResult {
+            name: test.value.clone(),
+            failure,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Result<Vec<(String, Option<String>)>, String> {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new());
+        let limits = Limits {
+            max_steps: Some(10_000),
+            ..Limits::default()
+        };
+        let results = run_tests(&program, limits)?;
+        Ok(results.into_iter().map(|r| (r.name, r.failure)).collect())
+    }
+
+    #[test]
+    fn run_tests_test() {
+        let results = run("let add = fn(a, b) { a + b };
+let counter = 0;
+let test_add = fn() { assert_eq(add(1, 2), 3) };
+let test_fresh = fn() { counter = counter + 1; assert_eq(counter, 1) };
+let test_again = fn() { counter = counter + 1; assert_eq(counter, 1) };
+let test_sub = fn() {
+  assert_eq(add(1, 2), 4)
+};
+let helper = fn() { assert(false) };");
+        assert_eq!(
+            results,
+            Ok(vec![
+                ("test_add".to_string(), None),
+                ("test_fresh".to_string(), None),
+                ("test_again".to_string(), None),
+                (
+                    "test_sub".to_string(),
+                    Some(
+                        "Traceback (most recent call last):
+  at line 6, column 5, in test_sub
+  at line 7, column 12
+AssertionError: assert_eq failed
+  left:  3
+  right: 4
+         ^"
+                        .to_string()
+                    )
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn run_tests_limit_test() {
+        let results = run("let test_spin = fn() { while (true) { } };
+let test_after = fn() { assert(true) };")
+        .unwrap();
+        assert_eq!(results[0].0, "test_spin");
+        let failure = results[0].1.as_deref().unwrap_or_default();
+        assert!(
+            failure.ends_with("StepLimitError: step limit of 10000 exceeded"),
+            "{}",
+            failure
+        );
+        assert_eq!(results[1], ("test_after".to_string(), None));
+    }
+
+    #[test]
+    fn run_tests_setup_error_test() {
+        let results = run("let test_a = fn() { 1 };\n1 / 0;");
+        assert_eq!(
+            results,
+            Err("Traceback (most recent call last):\n  at line 2, column 3\nZeroDivisionError: division by zero".to_string())
+        );
+    }
+}
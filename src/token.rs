@@ -1,9 +1,49 @@
 use std::fmt;
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, Debug)]
 pub struct Token {
     pub type_: TokenType,
     pub literal: String,
+    pub span: Span,
+}
+
+/// Tokens compare by type and literal only, so parsed trees can be compared
+/// against hand-built ones regardless of where they appeared in the source.
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        self.type_ == other.type_ && self.literal == other.literal
+    }
+}
+
+/// Location of a token in the source. `start` and `end` are byte offsets,
+/// `line` and `column` are 1-based. A default span (line 0) means unknown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A `//` comment the lexer skipped. `own_line` is set when only whitespace
+/// precedes it on its line; otherwise it trails code.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+    pub own_line: bool,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
@@ -37,6 +77,92 @@ pub enum TokenType {
     LET,
     UNDEFINED,
     STRING,
+    TEMPLATE,
+    WHILE,
+    BREAK,
+    CONTINUE,
+    FOR,
+    IN,
+    DOTDOT,
+    LBRACKET,
+    RBRACKET,
+    COLON,
+    PLUSASSIGN,
+    MINUSASSIGN,
+    ASTERICKASSIGN,
+    SLASHASSIGN,
+    THROW,
+    TRY,
+    CATCH,
+    OPERATOR,
+    INFIXL,
+    INFIXR,
+    MACRO,
+    ARROW,
+}
+
+impl TokenType {
+    /// Every token type, in declaration order.
+    pub const ALL: [TokenType; 51] = [
+        TokenType::ILLEGAL,
+        TokenType::EOF,
+        TokenType::IDENT,
+        TokenType::INT,
+        TokenType::ASSIGN,
+        TokenType::PLUS,
+        TokenType::MINUS,
+        TokenType::BANG,
+        TokenType::ASTERICK,
+        TokenType::SLASH,
+        TokenType::EQ,
+        TokenType::NEQ,
+        TokenType::LT,
+        TokenType::GT,
+        TokenType::TRUE,
+        TokenType::FALSE,
+        TokenType::IF,
+        TokenType::ELSE,
+        TokenType::RETURN,
+        TokenType::COMMA,
+        TokenType::SEMICOLON,
+        TokenType::LPAREN,
+        TokenType::RPAREN,
+        TokenType::LBRACE,
+        TokenType::RBRACE,
+        TokenType::FUNCTION,
+        TokenType::LET,
+        TokenType::UNDEFINED,
+        TokenType::STRING,
+        TokenType::TEMPLATE,
+        TokenType::WHILE,
+        TokenType::BREAK,
+        TokenType::CONTINUE,
+        TokenType::FOR,
+        TokenType::IN,
+        TokenType::DOTDOT,
+        TokenType::LBRACKET,
+        TokenType::RBRACKET,
+        TokenType::COLON,
+        TokenType::PLUSASSIGN,
+        TokenType::MINUSASSIGN,
+        TokenType::ASTERICKASSIGN,
+        TokenType::SLASHASSIGN,
+        TokenType::THROW,
+        TokenType::TRY,
+        TokenType::CATCH,
+        TokenType::OPERATOR,
+        TokenType::INFIXL,
+        TokenType::INFIXR,
+        TokenType::MACRO,
+        TokenType::ARROW,
+    ];
+
+    /// Looks a token type up by its variant name, as `{:?}` prints it.
+    pub fn from_name(name: &str) -> Option<TokenType> {
+        TokenType::ALL
+            .into_iter()
+            .find(|t| format!("{:?}", t) == name)
+    }
 }
 
 impl fmt::Display for TokenType {
@@ -71,6 +197,28 @@ impl fmt::Display for TokenType {
             TokenType::LET => write!(f, "TokenType: LET"),
             TokenType::UNDEFINED => write!(f, "TokenType: UNDEFINED"),
             TokenType::STRING => write!(f, "TokenType: STRING"),
+            TokenType::TEMPLATE => write!(f, "TokenType: TEMPLATE"),
+            TokenType::WHILE => write!(f, "TokenType: WHILE"),
+            TokenType::BREAK => write!(f, "TokenType: BREAK"),
+            TokenType::CONTINUE => write!(f, "TokenType: CONTINUE"),
+            TokenType::FOR => write!(f, "TokenType: FOR"),
+            TokenType::IN => write!(f, "TokenType: IN"),
+            TokenType::DOTDOT => write!(f, "TokenType: DOTDOT"),
+            TokenType::LBRACKET => write!(f, "TokenType: LBRACKET"),
+            TokenType::RBRACKET => write!(f, "TokenType: RBRACKET"),
+            TokenType::COLON => write!(f, "TokenType: COLON"),
+            TokenType::PLUSASSIGN => write!(f, "TokenType: PLUSASSIGN"),
+            TokenType::MINUSASSIGN => write!(f, "TokenType: MINUSASSIGN"),
+            TokenType::ASTERICKASSIGN => write!(f, "TokenType: ASTERISKASSIGN"),
+            TokenType::SLASHASSIGN => write!(f, "TokenType: SLASHASSIGN"),
+            TokenType::THROW => write!(f, "TokenType: THROW"),
+            TokenType::TRY => write!(f, "TokenType: TRY"),
+            TokenType::CATCH => write!(f, "TokenType: CATCH"),
+            TokenType::OPERATOR => write!(f, "TokenType: OPERATOR"),
+            TokenType::INFIXL => write!(f, "TokenType: INFIXL"),
+            TokenType::INFIXR => write!(f, "TokenType: INFIXR"),
+            TokenType::MACRO => write!(f, "TokenType: MACRO"),
+            TokenType::ARROW => write!(f, "TokenType: ARROW"),
         }
     }
 }
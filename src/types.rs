@@ -0,0 +1,1121 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::visit::{self, Visitor};
+use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::eval::is_call_to;
+use crate::token::{Span, TokenType};
+
+/// A static type. `Var` is an unknown the checker solves for.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Type {
+    Int,
+    Bool,
+    Str,
+    Null,
+    Range,
+    /// The value a `catch` binds.
+    Error,
+    Quote,
+    Array(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    Var(usize),
+}
+
+/// A type with the variables it is polymorphic over.
+#[derive(Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme { vars: vec![], ty }
+    }
+}
+
+/// A type error at `span`. `other` points at the expression whose type the
+/// one at `span` conflicts with, when there is one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+    pub other: Option<Span>,
+}
+
+impl TypeError {
+    /// The message, with where the conflicting expression is.
+    pub fn detail(&self) -> String {
+        match self.other {
+            Some(other) => format!(
+                "{} (conflicts with the expression at {})",
+                self.message, other
+            ),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line,
+            self.span.column,
+            self.detail()
+        )
+    }
+}
+
+/// The inferred type of a `let` or operator declaration.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TypedBinding {
+    pub name: String,
+    pub span: Span,
+    pub ty: String,
+}
+
+pub struct Typecheck {
+    pub bindings: Vec<TypedBinding>,
+    /// The types inferred for function parameters.
+    pub parameters: Vec<TypedBinding>,
+    pub errors: Vec<TypeError>,
+}
+
+/// Infers types for `program` with Hindley-Milner inference. Functions bound
+/// by `let` are polymorphic; other bindings can be reassigned and so keep
+/// one type. `+`, `==` and `!=` work on integers or strings, so a type
+/// variable used with them is limited to those two. Macros must already be
+/// expanded.
+pub fn typecheck(program: &Program) -> Typecheck {
+    let mut assigned = Assigned(HashSet::new());
+    assigned.visit_program(program);
+    let mut infer = Infer {
+        subst: vec![],
+        addable: vec![],
+        scopes: vec![HashMap::new()],
+        returns: vec![],
+        bindings: vec![],
+        parameters: vec![],
+        errors: vec![],
+        assigned: assigned.0,
+    };
+    for builtin in Builtin::ALL {
+        let scheme = infer.builtin(builtin);
+        infer.bind(builtin.name(), scheme);
+    }
+    infer.statements(program);
+    let bindings = std::mem::take(&mut infer.bindings);
+    let parameters = std::mem::take(&mut infer.parameters);
+    Typecheck {
+        bindings: infer.typed(bindings),
+        parameters: infer.typed(parameters),
+        errors: infer.errors,
+    }
+}
+
+struct Infer {
+    /// What each type variable has been solved to, if anything.
+    subst: Vec<Option<Type>>,
+    /// Whether each type variable may only be `int` or `string`.
+    addable: Vec<bool>,
+    scopes: Vec<HashMap<String, Scheme>>,
+    /// The return type of each function being checked, innermost last.
+    returns: Vec<Type>,
+    bindings: Vec<(String, Span, Scheme)>,
+    parameters: Vec<(String, Span, Scheme)>,
+    errors: Vec<TypeError>,
+    /// Names the program assigns to anywhere. A `let` of one of these is
+    /// not generalized, as a later value may not be as polymorphic.
+    assigned: HashSet<String>,
+}
+
+impl Infer {
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        self.addable.push(false);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    fn fresh_addable(&mut self) -> Type {
+        let var = self.fresh();
+        if let Type::Var(v) = var {
+            self.addable[v] = true;
+        }
+        var
+    }
+
+    /// Follows solved variables until reaching a type that is not one.
+    fn resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(v) = ty {
+            match &self.subst[v] {
+                Some(t) => ty = t.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Replaces every solved variable in `ty`, all the way down.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Array(t) => Type::Array(Box::new(self.zonk(&t))),
+            Type::Hash(k, v) => Type::Hash(Box::new(self.zonk(&k)), Box::new(self.zonk(&v))),
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|p| self.zonk(p)).collect(),
+                Box::new(self.zonk(&ret)),
+            ),
+            other => other,
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        match (&a, &b) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(x), _) => self.bind_var(*x, &b),
+            (_, Type::Var(y)) => self.bind_var(*y, &a),
+            (Type::Array(x), Type::Array(y)) => self.unify(x, y),
+            (Type::Hash(k1, v1), Type::Hash(k2, v2)) => {
+                self.unify(k1, k2)?;
+                self.unify(v1, v2)
+            }
+            (Type::Fn(p1, r1), Type::Fn(p2, r2)) if p1.len() == p2.len() => {
+                for (x, y) in p1.iter().zip(p2) {
+                    self.unify(x, y)?;
+                }
+                self.unify(r1, r2)
+            }
+            (Type::Array(_), _) | (Type::Hash(..), _) | (Type::Fn(..), _) => Err(()),
+            _ if a == b => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    fn bind_var(&mut self, var: usize, ty: &Type) -> Result<(), ()> {
+        if self.occurs(var, ty) {
+            return Err(());
+        }
+        if self.addable[var] {
+            match ty {
+                Type::Var(other) => self.addable[*other] = true,
+                Type::Int | Type::Str => (),
+                _ => return Err(()),
+            }
+        }
+        self.subst[var] = Some(ty.clone());
+        Ok(())
+    }
+
+    fn occurs(&self, var: usize, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(v) => v == var,
+            Type::Array(t) => self.occurs(var, &t),
+            Type::Hash(k, v) => self.occurs(var, &k) || self.occurs(var, &v),
+            Type::Fn(params, ret) => {
+                params.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &ret)
+            }
+            _ => false,
+        }
+    }
+
+    /// Unifies the type `found` of the expression at `span` with the type
+    /// it is `expected` to have, reporting a mismatch as an error there.
+    /// Returns whether they matched.
+    fn expect(&mut self, expected: &Type, found: &Type, span: Span, other: Option<Span>) -> bool {
+        if self.unify(expected, found).is_ok() {
+            return true;
+        }
+        // Both types share variable names, so the same variable reads the
+        // same on either side.
+        let mut names = Names::default();
+        let expected = match self.resolve(expected) {
+            Type::Var(v) if self.addable[v] => "int or string".to_string(),
+            t => self.name_type(&self.zonk(&t), &mut names),
+        };
+        let found = self.name_type(&self.zonk(found), &mut names);
+        let message = format!("expected {}, found {}", expected, found);
+        self.error(span, message, other);
+        false
+    }
+
+    fn error(&mut self, span: Span, message: String, other: Option<Span>) {
+        self.errors.push(TypeError {
+            span,
+            message,
+            other,
+        });
+    }
+
+    fn free_vars(&self, ty: &Type, out: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Type::Var(v) if !out.contains(&v) => out.push(v),
+            Type::Array(t) => self.free_vars(&t, out),
+            Type::Hash(k, v) => {
+                self.free_vars(&k, out);
+                self.free_vars(&v, out);
+            }
+            Type::Fn(params, ret) => {
+                for p in &params {
+                    self.free_vars(p, out);
+                }
+                self.free_vars(&ret, out);
+            }
+            _ => (),
+        }
+    }
+
+    /// Quantifies over the variables of `ty` that nothing in scope uses.
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let mut in_scope = vec![];
+        for scheme in self.scopes.iter().flat_map(|s| s.values()) {
+            let mut vars = vec![];
+            self.free_vars(&scheme.ty, &mut vars);
+            in_scope.extend(vars.into_iter().filter(|v| !scheme.vars.contains(v)));
+        }
+        for ret in &self.returns {
+            self.free_vars(ret, &mut in_scope);
+        }
+        let mut vars = vec![];
+        self.free_vars(ty, &mut vars);
+        vars.retain(|v| !in_scope.contains(v));
+        Scheme {
+            vars,
+            ty: self.zonk(ty),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let mut fresh = HashMap::new();
+        for &v in &scheme.vars {
+            let var = match self.addable[v] {
+                true => self.fresh_addable(),
+                false => self.fresh(),
+            };
+            fresh.insert(v, var);
+        }
+        self.substitute(&scheme.ty, &fresh)
+    }
+
+    fn substitute(&self, ty: &Type, vars: &HashMap<usize, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(v) => vars.get(&v).cloned().unwrap_or(Type::Var(v)),
+            Type::Array(t) => Type::Array(Box::new(self.substitute(&t, vars))),
+            Type::Hash(k, v) => Type::Hash(
+                Box::new(self.substitute(&k, vars)),
+                Box::new(self.substitute(&v, vars)),
+            ),
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|p| self.substitute(p, vars)).collect(),
+                Box::new(self.substitute(&ret, vars)),
+            ),
+            other => other,
+        }
+    }
+
+    fn builtin(&mut self, builtin: Builtin) -> Scheme {
+        let a = self.fresh();
+        let null = Box::new(Type::Null);
+        let ty = match builtin {
+            Builtin::Assert => Type::Fn(vec![Type::Bool], null),
+            Builtin::AssertEq => Type::Fn(vec![a.clone(), a], null),
+            Builtin::AssertError => Type::Fn(vec![Type::Fn(vec![], Box::new(a))], null),
+        };
+        let mut vars = vec![];
+        self.free_vars(&ty, &mut vars);
+        Scheme { vars, ty }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Scheme> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).cloned()
+    }
+
+    fn bind(&mut self, name: &str, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), scheme);
+    }
+
+    fn scoped<T>(&mut self, f: impl FnOnce(&mut Infer) -> T) -> T {
+        self.scopes.push(HashMap::new());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    /// The type of the last statement. Functions bound by `let` in the list
+    /// are in scope from its start, so they can call each other.
+    fn statements(&mut self, statements: &[Statement]) -> Type {
+        let mut pending = HashMap::new();
+        for stmt in statements {
+            if let Statement::LetStatement(LetStatement {
+                name: Some(name),
+                value: Some(value),
+                ..
+            }) = stmt
+            {
+                let scope = self.scopes.last().unwrap();
+                if matches!(**value, Expression::FunctionLiteral(_))
+                    && !scope.contains_key(&name.value)
+                    && !pending.contains_key(&name.value)
+                {
+                    let var = self.fresh();
+                    self.bind(&name.value, Scheme::mono(var.clone()));
+                    pending.insert(name.value.clone(), var);
+                }
+            }
+        }
+        let mut ty = Type::Null;
+        for stmt in statements {
+            ty = self.statement(stmt, &mut pending);
+        }
+        ty
+    }
+
+    fn statement(&mut self, stmt: &Statement, pending: &mut HashMap<String, Type>) -> Type {
+        match stmt {
+            Statement::LetStatement(x) => {
+                let (name, value) = match (&x.name, &x.value) {
+                    (Some(n), Some(v)) => (n, v),
+                    _ => return Type::Null,
+                };
+                let ty = self.expr(value);
+                if let Some(annotation) = &x.annotation {
+                    let declared = self.annotation(annotation);
+                    let span = annotation.token().span;
+                    self.expect(&declared, &ty, span_of(value), Some(span));
+                }
+                if let Some(var) = pending.remove(&name.value) {
+                    self.expect(&var, &ty, span_of(value), None);
+                    // The placeholder that let the function call itself
+                    // must not keep it from being generalized.
+                    self.scopes.last_mut().unwrap().remove(&name.value);
+                }
+                // Only functions that are never reassigned are generalized:
+                // anything else must keep one type for every value it holds.
+                let scheme = match **value {
+                    Expression::FunctionLiteral(_) if !self.assigned.contains(&name.value) => {
+                        self.generalize(&ty)
+                    }
+                    _ => Scheme::mono(ty),
+                };
+                self.bindings
+                    .push((name.value.clone(), name.token.span, scheme.clone()));
+                self.bind(&name.value, scheme);
+                Type::Null
+            }
+            Statement::ReturnStatement(x) => {
+                let ty = match &x.value {
+                    Some(v) => self.expr(v),
+                    None => Type::Null,
+                };
+                if let Some(ret) = self.returns.last().cloned() {
+                    let span = x.value.as_deref().map_or(x.token.span, span_of);
+                    self.expect(&ret, &ty, span, None);
+                }
+                self.fresh()
+            }
+            Statement::ExpressionStatement(x) => match &x.expr {
+                Some(e) => self.expr(e),
+                None => Type::Null,
+            },
+            Statement::BlockStatement(x) => self.statements(&x.statements),
+            Statement::WhileStatement(x) => {
+                if let Some(cond) = &x.cond {
+                    let ty = self.expr(cond);
+                    self.expect(&Type::Bool, &ty, span_of(cond), None);
+                }
+                if let Some(body) = &x.body {
+                    self.statement(body, pending);
+                }
+                Type::Null
+            }
+            Statement::ForStatement(x) => {
+                let item = match &x.iterable {
+                    Some(iterable) => self.iterate(iterable),
+                    None => self.fresh(),
+                };
+                self.scoped(|infer| {
+                    if let Some(ident) = &x.ident {
+                        infer.bind(&ident.value, Scheme::mono(item));
+                    }
+                    if let Some(body) = &x.body {
+                        infer.statement(body, &mut HashMap::new());
+                    }
+                });
+                Type::Null
+            }
+            Statement::BreakStatement(_) | Statement::ContinueStatement(_) => self.fresh(),
+            Statement::ThrowStatement(x) => {
+                if let Some(v) = &x.value {
+                    self.expr(v);
+                }
+                self.fresh()
+            }
+            Statement::InfixStatement(x) => {
+                let value = match &x.value {
+                    Some(v) => v,
+                    None => return Type::Null,
+                };
+                let ty = self.expr(value);
+                let (a, b, r) = (self.fresh(), self.fresh(), self.fresh());
+                let binary = Type::Fn(vec![a, b], Box::new(r));
+                self.expect(&binary, &ty, span_of(value), None);
+                let scheme = match **value {
+                    Expression::FunctionLiteral(_) => self.generalize(&ty),
+                    _ => Scheme::mono(ty),
+                };
+                self.bindings
+                    .push((x.operator.clone(), x.token.span, scheme.clone()));
+                self.bind(&x.operator, scheme);
+                Type::Null
+            }
+        }
+    }
+
+    /// The type of the items a `for` loop takes from `iterable`.
+    fn iterate(&mut self, iterable: &Expression) -> Type {
+        let ty = self.expr(iterable);
+        match self.resolve(&ty) {
+            Type::Array(t) => *t,
+            Type::Hash(k, _) => *k,
+            Type::Str => Type::Str,
+            Type::Range => Type::Int,
+            Type::Var(_) => {
+                let item = self.fresh();
+                self.expect(
+                    &Type::Array(Box::new(item.clone())),
+                    &ty,
+                    span_of(iterable),
+                    None,
+                );
+                item
+            }
+            other => {
+                let message = format!("cannot iterate over {}", self.show(&other));
+                self.error(span_of(iterable), message, None);
+                self.fresh()
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Option<Box<Statement>>) -> Type {
+        match block.as_deref() {
+            Some(Statement::BlockStatement(b)) => self.statements(&b.statements),
+            _ => Type::Null,
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) -> Type {
+        match expr {
+            Expression::Identifier(x) => match self.lookup(&x.value) {
+                Some(scheme) => self.instantiate(&scheme),
+                None => {
+                    self.error(x.token.span, format!("unknown name {}", x.value), None);
+                    self.fresh()
+                }
+            },
+            Expression::IntegerLiteral(_) => Type::Int,
+            Expression::Boolean(_) => Type::Bool,
+            Expression::StringLiteral(_) => Type::Str,
+            Expression::InterpolatedString(x) => {
+                for part in &x.parts {
+                    if let InterpolationPart::Expression(e) = part {
+                        self.expr(e);
+                    }
+                }
+                Type::Str
+            }
+            Expression::PrefixExpression(x) => {
+                let right = match &x.right {
+                    Some(r) => r,
+                    None => return self.fresh(),
+                };
+                let ty = self.expr(right);
+                match x.operator.as_str() {
+                    "-" => {
+                        self.expect(&Type::Int, &ty, span_of(right), None);
+                        Type::Int
+                    }
+                    _ => Type::Bool,
+                }
+            }
+            Expression::InfixExpression(x) => self.infix(x),
+            Expression::IfExpression(x) => {
+                if let Some(cond) = &x.cond {
+                    let ty = self.expr(cond);
+                    self.expect(&Type::Bool, &ty, span_of(cond), None);
+                }
+                let cons = self.block(&x.consequence);
+                match &x.alternative {
+                    Some(alt) => {
+                        let alt_ty = self.block(&x.alternative);
+                        let cons_span = x.consequence.as_ref().map(|c| c.token().span);
+                        self.expect(&cons, &alt_ty, alt.token().span, cons_span);
+                        cons
+                    }
+                    // Without an `else` the value may be null, so it is not
+                    // meant to be used.
+                    None => Type::Null,
+                }
+            }
+            Expression::FunctionLiteral(x) => {
+                let params: Vec<Type> = (0..x.parameters.as_deref().map_or(0, Vec::len))
+                    .map(|i| match x.param_types.get(i) {
+                        Some(Some(ty)) => self.annotation(ty),
+                        _ => self.fresh(),
+                    })
+                    .collect();
+                let ret = match &x.return_type {
+                    Some(ty) => self.annotation(ty),
+                    None => self.fresh(),
+                };
+                self.returns.push(ret.clone());
+                let body = self.scoped(|infer| {
+                    for (param, ty) in x.parameters.as_deref().into_iter().flatten().zip(&params) {
+                        if let Expression::Identifier(p) = param {
+                            let scheme = Scheme::mono(ty.clone());
+                            infer
+                                .parameters
+                                .push((p.value.clone(), p.token.span, scheme.clone()));
+                            infer.bind(&p.value, scheme);
+                        }
+                    }
+                    match &x.body {
+                        Some(b) => infer.statements(&b.statements),
+                        None => Type::Null,
+                    }
+                });
+                self.returns.pop();
+                let span = match x.body.as_ref().and_then(|b| b.statements.last()) {
+                    Some(last) => last.token().span,
+                    None => x.token.span,
+                };
+                self.expect(&ret, &body, span, None);
+                Type::Fn(params, Box::new(ret))
+            }
+            Expression::MacroLiteral(_) => self.fresh(),
+            Expression::CallExpression(x) => self.call(x),
+            Expression::ArrayLiteral(x) => {
+                let item = self.fresh();
+                let first = x.elements.first().map(span_of);
+                for e in &x.elements {
+                    let ty = self.expr(e);
+                    self.expect(&item, &ty, span_of(e), first);
+                }
+                Type::Array(Box::new(item))
+            }
+            Expression::HashLiteral(x) => {
+                let (key, value) = (self.fresh(), self.fresh());
+                let first = x.pairs.first().map(|(k, v)| (span_of(k), span_of(v)));
+                for (k, v) in &x.pairs {
+                    let ty = self.expr(k);
+                    self.expect(&key, &ty, span_of(k), first.map(|f| f.0));
+                    let ty = self.expr(v);
+                    self.expect(&value, &ty, span_of(v), first.map(|f| f.1));
+                }
+                Type::Hash(Box::new(key), Box::new(value))
+            }
+            Expression::IndexExpression(x) => match (&x.left, &x.index) {
+                (Some(left), Some(index)) => self.index(left, index),
+                _ => self.fresh(),
+            },
+            Expression::AssignExpression(x) => {
+                let (target, value) = match (&x.target, &x.value) {
+                    (Some(t), Some(v)) => (t, v),
+                    _ => return self.fresh(),
+                };
+                let target_ty = self.expr(target);
+                let ty = self.expr(value);
+                let operand = match x.operator.as_str() {
+                    "=" => None,
+                    "+=" => Some(self.fresh_addable()),
+                    _ => Some(Type::Int),
+                };
+                if let Some(operand) = operand {
+                    self.expect(&operand, &target_ty, span_of(target), None);
+                }
+                self.expect(&target_ty, &ty, span_of(value), Some(span_of(target)));
+                target_ty
+            }
+            Expression::TryExpression(x) => {
+                let body = self.block(&x.body);
+                let handler = self.scoped(|infer| {
+                    if let Some(ident) = &x.ident {
+                        infer.bind(&ident.value, Scheme::mono(Type::Error));
+                    }
+                    infer.block(&x.handler)
+                });
+                if let Some(h) = &x.handler {
+                    let body_span = x.body.as_ref().map(|b| b.token().span);
+                    self.expect(&body, &handler, h.token().span, body_span);
+                }
+                body
+            }
+        }
+    }
+
+    fn infix(&mut self, x: &InfixExpression) -> Type {
+        let (left, right) = match (&x.left, &x.right) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return self.fresh(),
+        };
+        let (lt, rt) = (self.expr(left), self.expr(right));
+        let (ls, rs) = (span_of(left), span_of(right));
+        if x.token.type_ == TokenType::OPERATOR {
+            return match self.lookup(&x.operator) {
+                Some(scheme) => {
+                    let op = self.instantiate(&scheme);
+                    self.apply(op, x.token.span, &[(lt, ls), (rt, rs)])
+                }
+                None => {
+                    let message = format!("unknown operator {}", x.operator);
+                    self.error(x.token.span, message, None);
+                    self.fresh()
+                }
+            };
+        }
+        match x.operator.as_str() {
+            "+" | "==" | "!=" => {
+                let operand = self.fresh_addable();
+                if self.expect(&operand, &lt, ls, None) {
+                    self.expect(&operand, &rt, rs, Some(ls));
+                }
+                match x.operator.as_str() {
+                    "+" => operand,
+                    _ => Type::Bool,
+                }
+            }
+            op => {
+                self.expect(&Type::Int, &lt, ls, None);
+                self.expect(&Type::Int, &rt, rs, None);
+                match op {
+                    "<" | ">" => Type::Bool,
+                    ".." => Type::Range,
+                    _ => Type::Int,
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, x: &CallExpression) -> Type {
+        if is_call_to(x, "quote") {
+            return Type::Quote;
+        }
+        let func = match &x.func {
+            Some(f) => f,
+            None => return self.fresh(),
+        };
+        let ft = self.expr(func);
+        let args: Vec<(Type, Span)> = x
+            .args
+            .iter()
+            .flatten()
+            .map(|a| (self.expr(a), span_of(a)))
+            .collect();
+        match self.resolve(&ft) {
+            Type::Fn(..) | Type::Var(_) => self.apply(ft, x.token.span, &args),
+            other => {
+                let message = format!("cannot call {}", self.show(&other));
+                self.error(span_of(func), message, None);
+                self.fresh()
+            }
+        }
+    }
+
+    /// The result of calling a function of type `func` at `span`, checking
+    /// each argument against its parameter.
+    fn apply(&mut self, func: Type, span: Span, args: &[(Type, Span)]) -> Type {
+        match self.resolve(&func) {
+            Type::Fn(params, ret) => {
+                if params.len() != args.len() {
+                    let message = format!(
+                        "function takes {} arguments, got {}",
+                        params.len(),
+                        args.len()
+                    );
+                    self.error(span, message, None);
+                    return *ret;
+                }
+                for (param, (ty, arg_span)) in params.iter().zip(args) {
+                    self.expect(param, ty, *arg_span, None);
+                }
+                *ret
+            }
+            _ => {
+                let ret = self.fresh();
+                let types = args.iter().map(|(ty, _)| ty.clone()).collect();
+                let call = Type::Fn(types, Box::new(ret.clone()));
+                self.expect(&func, &call, span, None);
+                ret
+            }
+        }
+    }
+
+    fn index(&mut self, left: &Expression, index: &Expression) -> Type {
+        let lt = self.expr(left);
+        let it = self.expr(index);
+        let is = span_of(index);
+        match self.resolve(&lt) {
+            Type::Array(item) => {
+                self.expect(&Type::Int, &it, is, None);
+                *item
+            }
+            Type::Str => {
+                self.expect(&Type::Int, &it, is, None);
+                Type::Str
+            }
+            Type::Hash(key, value) => {
+                self.expect(&key, &it, is, None);
+                *value
+            }
+            Type::Error => match index {
+                Expression::StringLiteral(s) => match s.value.as_str() {
+                    "message" | "kind" => Type::Str,
+                    "line" | "column" => Type::Int,
+                    _ => Type::Null,
+                },
+                _ => {
+                    self.expect(&Type::Str, &it, is, None);
+                    self.fresh()
+                }
+            },
+            // Which collection it is cannot be told from the expression, so
+            // the index decides: a string key means a hash.
+            Type::Var(_) => {
+                let item = self.fresh();
+                let collection = match self.resolve(&it) {
+                    Type::Str => Type::Hash(Box::new(Type::Str), Box::new(item.clone())),
+                    _ => {
+                        self.expect(&Type::Int, &it, is, None);
+                        Type::Array(Box::new(item.clone()))
+                    }
+                };
+                self.expect(&collection, &lt, span_of(left), None);
+                item
+            }
+            other => {
+                let message = format!("cannot index {}", self.show(&other));
+                self.error(span_of(left), message, None);
+                self.fresh()
+            }
+        }
+    }
+
+    /// The type an annotation stands for. `any` is left for inference to
+    /// fill in.
+    fn annotation(&mut self, annotation: &TypeAnnotation) -> Type {
+        match annotation {
+            TypeAnnotation::Named(t) => match t.literal.as_str() {
+                "int" => Type::Int,
+                "string" => Type::Str,
+                "bool" => Type::Bool,
+                "null" => Type::Null,
+                "range" => Type::Range,
+                "error" => Type::Error,
+                _ => self.fresh(),
+            },
+            TypeAnnotation::Array(_, item) => Type::Array(Box::new(self.annotation(item))),
+            TypeAnnotation::Hash(_, key, value) => Type::Hash(
+                Box::new(self.annotation(key)),
+                Box::new(self.annotation(value)),
+            ),
+            TypeAnnotation::Function(_, params, ret) => Type::Fn(
+                params.iter().map(|p| self.annotation(p)).collect(),
+                Box::new(self.annotation(ret)),
+            ),
+        }
+    }
+
+    /// Renders recorded bindings in source order.
+    fn typed(&self, mut bindings: Vec<(String, Span, Scheme)>) -> Vec<TypedBinding> {
+        bindings.sort_by_key(|(_, span, _)| (span.line, span.column));
+        bindings
+            .into_iter()
+            .map(|(name, span, scheme)| TypedBinding {
+                name,
+                span,
+                ty: self.render(&scheme),
+            })
+            .collect()
+    }
+
+    fn show(&self, ty: &Type) -> String {
+        let mut names = Names::default();
+        self.name_type(&self.zonk(ty), &mut names)
+    }
+
+    fn render(&self, scheme: &Scheme) -> String {
+        let mut names = Names::default();
+        let ty = self.name_type(&self.zonk(&scheme.ty), &mut names);
+        let addable: Vec<&String> = names
+            .vars
+            .iter()
+            .filter(|(v, _)| self.addable[*v])
+            .map(|(_, name)| name)
+            .collect();
+        match addable.as_slice() {
+            [] => ty,
+            vars => {
+                let bounds: Vec<String> = vars
+                    .iter()
+                    .map(|v| format!("{}: int | string", v))
+                    .collect();
+                format!("{} where {}", ty, bounds.join(", "))
+            }
+        }
+    }
+
+    fn name_type(&self, ty: &Type, names: &mut Names) -> String {
+        match ty {
+            Type::Int => "int".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Str => "string".to_string(),
+            Type::Null => "null".to_string(),
+            Type::Range => "range".to_string(),
+            Type::Error => "error".to_string(),
+            Type::Quote => "quote".to_string(),
+            Type::Array(t) => format!("[{}]", self.name_type(t, names)),
+            Type::Hash(k, v) => format!(
+                "{{{}: {}}}",
+                self.name_type(k, names),
+                self.name_type(v, names)
+            ),
+            Type::Fn(params, ret) => {
+                let params: Vec<String> = params.iter().map(|p| self.name_type(p, names)).collect();
+                format!(
+                    "fn({}) -> {}",
+                    params.join(", "),
+                    self.name_type(ret, names)
+                )
+            }
+            Type::Var(v) => names.name(*v),
+        }
+    }
+}
+
+/// Names type variables `'a`, `'b`, ... in the order they are printed.
+#[derive(Default)]
+struct Names {
+    vars: Vec<(usize, String)>,
+}
+
+impl Names {
+    fn name(&mut self, var: usize) -> String {
+        if let Some((_, name)) = self.vars.iter().find(|(v, _)| *v == var) {
+            return name.clone();
+        }
+        let n = self.vars.len();
+        let letter = (b'a' + (n % 26) as u8) as char;
+        let name = match n / 26 {
+            0 => format!("'{}", letter),
+            k => format!("'{}{}", letter, k),
+        };
+        self.vars.push((var, name.clone()));
+        name
+    }
+}
+
+/// Where an expression starts in the source.
+fn span_of(expr: &Expression) -> Span {
+    let left = match expr {
+        Expression::InfixExpression(x) => &x.left,
+        Expression::CallExpression(x) => &x.func,
+        Expression::IndexExpression(x) => &x.left,
+        Expression::AssignExpression(x) => &x.target,
+        _ => &None,
+    };
+    match left {
+        Some(e) => span_of(e),
+        None => expr.token().span,
+    }
+}
+
+/// Collects the names assigned to with `=` or a compound operator.
+struct Assigned(HashSet<String>);
+
+impl Visitor for Assigned {
+    fn visit_assign_expression(&mut self, expr: &AssignExpression) {
+        if let Some(Expression::Identifier(x)) = expr.target.as_deref() {
+            self.0.insert(x.value.clone());
+        }
+        visit::walk_assign_expression(self, expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Typecheck {
+        let mut lex = Lexer::init_lexer(input);
+        let mut par = Parser::new(&mut lex);
+        let program = par.parse_program();
+        assert_eq!(par.errors(), Vec::<String>::new(), "{}", input);
+        typecheck(&program)
+    }
+
+    #[test]
+    fn infer_test() {
+        let tests = vec![
+            ("let x = 5;", "int"),
+            ("let s = \"a\" + \"b\";", "string"),
+            ("let id = fn(x) { x };", "fn('a) -> 'a"),
+            (
+                "let add = fn(a, b) { a + b };",
+                "fn('a, 'a) -> 'a where 'a: int | string",
+            ),
+            ("let inc = fn(a) { a + 1 };", "fn(int) -> int"),
+            (
+                "let eq = fn(a, b) { a == b };",
+                "fn('a, 'a) -> bool where 'a: int | string",
+            ),
+            ("let k = fn(a, b) { a };", "fn('a, 'b) -> 'a"),
+            ("let first = fn(xs) { xs[0] };", "fn(['a]) -> 'a"),
+            ("let get = fn(h) { h[\"k\"] };", "fn({string: 'a}) -> 'a"),
+            ("let ap = fn(f, x) { f(x) };", "fn(fn('a) -> 'b, 'a) -> 'b"),
+            (
+                "let fact = fn(n) { if (n < 2) { return 1; } n * fact(n - 1) };",
+                "fn(int) -> int",
+            ),
+            ("let h = {\"a\": [1], \"b\": []};", "{string: [int]}"),
+            ("let r = 0..3;", "range"),
+            ("let t = try { 1 } catch (e) { e[\"line\"] };", "int"),
+            ("let m = fn(e) { \"${e}!\" };", "fn('a) -> string"),
+            (
+                "let t = fn() { assert_eq([1], [2]); assert_error(fn() { 1 / 0 }) };",
+                "fn() -> null",
+            ),
+        ];
+        for (input, expected) in tests {
+            let result = run(input);
+            let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors, Vec::<String>::new(), "{}", input);
+            assert_eq!(result.bindings.last().unwrap().ty, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn let_polymorphism_test() {
+        let result = run("let id = fn(x) { x }; let a = id(1); let b = id(\"s\"); let c = id(id);");
+        let types: Vec<(&str, &str)> = result
+            .bindings
+            .iter()
+            .map(|b| (b.name.as_str(), b.ty.as_str()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("id", "fn('a) -> 'a"),
+                ("a", "int"),
+                ("b", "string"),
+                ("c", "fn('a) -> 'a"),
+            ]
+        );
+        assert!(result.errors.is_empty());
+        let result = run("let f = fn(g) { [g(1), g(\"s\")] };");
+        assert_eq!(
+            result.errors[0].to_string(),
+            "1:26: expected int, found string"
+        );
+    }
+
+    #[test]
+    fn mutual_recursion_test() {
+        let result = run(
+            "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+             let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };",
+        );
+        assert!(result.errors.is_empty());
+        assert_eq!(result.bindings[0].ty, "fn(int) -> bool");
+        assert_eq!(result.bindings[1].ty, "fn(int) -> bool");
+    }
+
+    #[test]
+    fn type_error_test() {
+        let tests = vec![
+            (
+                "1 + \"a\"",
+                "1:5: expected int, found string (conflicts with the expression at line 1, column 1)",
+            ),
+            ("[1] + [2]", "1:1: expected int or string, found [int]"),
+            ("true == false", "1:1: expected int or string, found bool"),
+            ("-\"a\"", "1:2: expected int, found string"),
+            ("if (1) { 2 }", "1:5: expected bool, found int"),
+            (
+                "if (true) { 1 } else { \"a\" }",
+                "1:22: expected int, found string (conflicts with the expression at line 1, column 11)",
+            ),
+            ("let f = fn(x) { x }; f(1, 2)", "1:23: function takes 1 arguments, got 2"),
+            ("5(1)", "1:1: cannot call int"),
+            ("[1, \"a\"]", "1:5: expected int, found string (conflicts with the expression at line 1, column 2)"),
+            ("let x = 1; x = \"s\";", "1:16: expected int, found string (conflicts with the expression at line 1, column 12)"),
+            ("let f = fn(x) { x(x) };", "1:18: expected 'a, found fn('a) -> 'b"),
+            (
+                "let f = fn(n) { if (n) { return 1; } \"s\" };",
+                "1:38: expected int, found string",
+            ),
+            ("for (x in 5) { x }", "1:11: cannot iterate over int"),
+            ("y", "1:1: unknown name y"),
+            ("assert(1)", "1:8: expected bool, found int"),
+            ("assert_eq(1, \"a\")", "1:14: expected int, found string"),
+            ("infixl 5 <> = fn(a) { a };", "1:15: expected fn('a, 'b) -> 'c, found fn('d) -> 'd"),
+            (
+                "let f = fn(x) { x }; f = fn(x) { x + 1 }; let s = f(\"a\");",
+                "1:53: expected int, found string",
+            ),
+        ];
+        for (input, expected) in tests {
+            let result = run(input);
+            let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors, vec![expected.to_string()], "{}", input);
+        }
+    }
+
+    #[test]
+    fn annotation_test() {
+        let result = run("let f = fn(a: string, b: any) -> [string] { [a] };");
+        assert_eq!(result.bindings[0].ty, "fn(string, 'a) -> [string]");
+        let result = run("let id = fn(x: any) -> any { x }; let n: int = id(1);");
+        assert!(result.errors.is_empty());
+        assert_eq!(result.bindings[0].ty, "fn('a) -> 'a");
+        assert_eq!(result.parameters[0].name, "x");
+        assert_eq!(result.parameters[0].ty, "'a");
+        let tests = vec![
+            (
+                "let x: int = \"s\";",
+                "1:14: expected int, found string (conflicts with the expression at line 1, column 8)",
+            ),
+            ("let f = fn(a: int) { a }; f(\"s\")", "1:29: expected int, found string"),
+            ("let f = fn() -> bool { 1 };", "1:24: expected bool, found int"),
+            (
+                "let h: {string: int} = {1: 2};",
+                "1:24: expected {string: int}, found {int: int} (conflicts with the expression at line 1, column 8)",
+            ),
+        ];
+        for (input, expected) in tests {
+            let result = run(input);
+            let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors, vec![expected.to_string()], "{}", input);
+        }
+    }
+
+    #[test]
+    fn user_operator_test() {
+        let result = run("infixl 5 <> = fn(a, b) { [a, b] }; let x = 1 <> 2; let y = \"a\" <> 2;");
+        assert_eq!(result.bindings[0].ty, "fn('a, 'a) -> ['a]");
+        assert_eq!(result.bindings[1].ty, "[int]");
+        assert_eq!(
+            result.errors[0].to_string(),
+            "1:67: expected string, found int"
+        );
+    }
+}